@@ -0,0 +1,90 @@
+use clap::{AppSettings, Clap};
+use mwwolf::infrastructure::datastore;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+
+/// 名前空間のエンティティを改行区切りのJSONに書き出したり、書き戻したりする
+#[derive(Clap)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(short, long)]
+    project: String,
+    /// 省略した場合はデフォルトの名前空間を使う
+    #[clap(short, long, default_value = "")]
+    namespace: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// エンティティを書き出す
+    Export(Export),
+    /// 書き出したエンティティを書き戻す。同じキーのエンティティは上書きする
+    Import(Import),
+}
+
+#[derive(Clap)]
+struct Export {
+    /// 書き出す種類。省略した場合は全ての種類を書き出す
+    #[clap(short, long)]
+    kind: Vec<String>,
+    /// 省略した場合は標準出力に書き出す
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clap)]
+struct Import {
+    /// 省略した場合は標準入力から読み込む
+    #[clap(short, long)]
+    input: Option<PathBuf>,
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    let opts: Opts = Opts::parse();
+    match opts.command {
+        Command::Export(export) => {
+            let exported = match export.output {
+                Some(path) => {
+                    let writer = BufWriter::new(File::create(path)?);
+                    datastore::export_namespace(
+                        &opts.project,
+                        &opts.namespace,
+                        &export.kind,
+                        writer,
+                    )
+                    .await?
+                }
+                None => {
+                    let writer = BufWriter::new(io::stdout());
+                    datastore::export_namespace(
+                        &opts.project,
+                        &opts.namespace,
+                        &export.kind,
+                        writer,
+                    )
+                    .await?
+                }
+            };
+            eprintln!("{}: {} entities exported", opts.namespace, exported);
+        }
+        Command::Import(import) => {
+            let imported = match import.input {
+                Some(path) => {
+                    let reader = BufReader::new(File::open(path)?);
+                    datastore::import_namespace(&opts.project, &opts.namespace, reader).await?
+                }
+                None => {
+                    let stdin = io::stdin();
+                    let reader = stdin.lock();
+                    datastore::import_namespace(&opts.project, &opts.namespace, reader).await?
+                }
+            };
+            eprintln!("{}: {} entities imported", opts.namespace, imported);
+        }
+    }
+    Ok(())
+}
@@ -1,13 +1,41 @@
 use actix_web::middleware::Logger;
-use actix_web::{guard, web, App, HttpResponse, HttpServer, Result};
+use actix_web::{error, guard, web, App, HttpRequest, HttpResponse, HttpServer, Result};
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
 
 use async_graphql::EmptySubscription;
 use async_graphql_actix_web::{Request, Response};
 use graphql::KzSchema;
+use mwwolf::domain::RepositoryErrorKind;
+use mwwolf::infrastructure::metrics::{self, PrometheusMetrics};
+use mwwolf::infrastructure::tenant::{TenantSelectionError, TenantSelector};
+use std::sync::Arc;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-async fn index(schema: web::Data<KzSchema>, req: Request) -> Response {
-    schema.execute(req.into_inner()).await.into()
+// NOTE: テナントはTenantSelectorで決める。ヘッダーで選べるのは信頼する設定にしたときだけ
+async fn index(
+    schema: web::Data<KzSchema>,
+    registry: web::Data<di::TenantRegistry>,
+    selector: web::Data<TenantSelector>,
+    http_request: HttpRequest,
+    req: Request,
+) -> Result<Response> {
+    let header = http_request
+        .headers()
+        .get(TenantSelector::HEADER)
+        .and_then(|value| value.to_str().ok());
+    let tenant_id = selector.select(header).map_err(|e| match e {
+        TenantSelectionError::Forbidden(_) => error::ErrorForbidden(e),
+        _ => error::ErrorBadRequest(e),
+    })?;
+    let cf = registry
+        .resolve(&tenant_id)
+        .await
+        .map_err(|e| match e.kind() {
+            RepositoryErrorKind::NotFound => error::ErrorNotFound(e),
+            _ => error::ErrorInternalServerError(e),
+        })?;
+    Ok(schema.execute(req.into_inner().data(cf)).await.into())
 }
 
 async fn index_playground() -> Result<HttpResponse> {
@@ -18,11 +46,31 @@ async fn index_playground() -> Result<HttpResponse> {
         )))
 }
 
+async fn index_metrics(metrics: web::Data<PrometheusMetrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     println!("Playground: http://localhost:8000");
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
+    println!("Metrics: http://localhost:8000/metrics");
+    // NOTE: actix_webのログもtracingに流し、Datastoreのスパンは閉じたときに記録した値と一緒に出力する
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("actix_web=info,mwwolf=info")),
+        )
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+    let prometheus = Arc::new(PrometheusMetrics::default());
+    metrics::set_metrics(prometheus.clone());
+    let selector = web::Data::new(
+        TenantSelector::from_env()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+    );
+    let registry = web::Data::from(di::create_tenant_registry());
     HttpServer::new(move || {
         let schema = di::create_schema(
             graphql::Query::default(),
@@ -34,8 +82,16 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .wrap(Logger::default())
             .app_data(actix_web::web::Data::new(schema))
+            .app_data(web::Data::from(prometheus.clone()))
+            .app_data(registry.clone())
+            .app_data(selector.clone())
             .service(web::resource("/").guard(guard::Post()).to(index))
             .service(web::resource("/").guard(guard::Get()).to(index_playground))
+            .service(
+                web::resource("/metrics")
+                    .guard(guard::Get())
+                    .to(index_metrics),
+            )
     })
     .bind("127.0.0.1:8000")?
     .run()
@@ -0,0 +1,84 @@
+use clap::{AppSettings, Clap};
+use mwwolf::infrastructure::datastore::{self, Migrator};
+
+/// Datastoreのエンティティの移行を、名前空間ごとに適用する
+#[derive(Clap)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(short, long)]
+    project: String,
+    /// 省略した場合はデフォルトの名前空間を使う
+    #[clap(short, long, default_value = "")]
+    namespace: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// 移行ごとに適用状況を表示する
+    Status,
+    /// 未適用の移行を順に適用する
+    Run(Run),
+}
+
+#[derive(Clap)]
+struct Run {
+    /// 変更をコミットせずに、移行される件数だけを表示する
+    #[clap(long)]
+    dry_run: bool,
+    /// 1トランザクションで移行するエンティティの数
+    #[clap(long, default_value = "100")]
+    batch_size: usize,
+    /// 指定したバッチ数で止める。次に実行したときは続きから再開する
+    #[clap(long)]
+    max_batches: Option<usize>,
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    let opts: Opts = Opts::parse();
+    let migrator = Migrator::new(datastore::migrations());
+    match opts.command {
+        Command::Status => {
+            for status in
+                datastore::migration_status(&opts.project, &opts.namespace, &migrator).await?
+            {
+                let state = match status.record {
+                    Some(record) if record.completed => {
+                        format!("applied at {}", record.updated_at)
+                    }
+                    Some(record) => format!("in progress ({} migrated)", record.migrated),
+                    None => String::from("pending"),
+                };
+                println!("{} {}: {}", status.version, status.name, state);
+            }
+        }
+        Command::Run(run) => {
+            let migrator = migrator
+                .batch_size(run.batch_size)
+                .dry_run(run.dry_run)
+                .max_batches(run.max_batches);
+            let reports =
+                datastore::migrate_namespace(&opts.project, &opts.namespace, &migrator).await?;
+            if reports.is_empty() {
+                println!("no pending migrations");
+            }
+            for report in reports {
+                println!(
+                    "{} {}: {} entities {}{}",
+                    report.version,
+                    report.name,
+                    report.migrated,
+                    if run.dry_run {
+                        "to migrate"
+                    } else {
+                        "migrated"
+                    },
+                    if report.completed { "" } else { " (paused)" }
+                );
+            }
+        }
+    }
+    Ok(())
+}
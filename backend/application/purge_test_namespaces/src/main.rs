@@ -0,0 +1,48 @@
+use clap::{AppSettings, Clap};
+use mwwolf::infrastructure::datastore;
+
+/// テストが作った名前空間(ハイフン無しのUUID)の中身をまとめて削除する
+#[derive(Clap)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(short, long)]
+    project: String,
+    /// 指定した場合は、テスト用かどうかに関わらずこの名前空間だけを削除する
+    #[clap(short, long)]
+    namespace: Vec<String>,
+    /// 削除せずに対象の名前空間を表示するだけにする
+    #[clap(long)]
+    dry_run: bool,
+}
+
+// NOTE: testmww::integration_test::init_test_databaseが作る名前空間と同じ形式
+fn is_test_namespace(namespace: &str) -> bool {
+    namespace.len() == 32
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    let opts: Opts = Opts::parse();
+    let namespaces = if opts.namespace.is_empty() {
+        datastore::list_namespaces(&opts.project)
+            .await?
+            .into_iter()
+            .filter(|namespace| is_test_namespace(namespace))
+            .collect()
+    } else {
+        opts.namespace
+    };
+
+    for namespace in namespaces {
+        if opts.dry_run {
+            println!("{}", namespace);
+            continue;
+        }
+        let deleted = datastore::purge_namespace(&opts.project, &namespace).await?;
+        println!("{}: {} entities deleted", namespace, deleted);
+    }
+    Ok(())
+}
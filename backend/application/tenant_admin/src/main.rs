@@ -0,0 +1,99 @@
+use clap::{AppSettings, Clap};
+use mwwolf::infrastructure::datastore::{self, Migrator};
+
+/// テナント(コミュニティ)の登録と、全てのテナントにまたがる管理操作を行う
+#[derive(Clap)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    #[clap(short, long)]
+    project: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// 登録されているテナントを表示する
+    List,
+    /// テナントを登録し、名前空間を割り当てる
+    Register(Register),
+    /// テナントのデータを全て削除し、登録を外す
+    Remove(Remove),
+    /// テナントごとに、種類ごとのエンティティ数を表示する
+    Stats,
+    /// 全てのテナントに未適用の移行を適用する
+    Migrate(Migrate),
+}
+
+#[derive(Clap)]
+struct Register {
+    /// リクエストのX-Tenant-IDヘッダーで指定する識別子
+    id: String,
+    name: String,
+}
+
+#[derive(Clap)]
+struct Remove {
+    id: String,
+    /// 確認のために指定する
+    #[clap(long)]
+    yes: bool,
+}
+
+#[derive(Clap)]
+struct Migrate {
+    /// 変更をコミットせずに、移行される件数だけを表示する
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    let opts: Opts = Opts::parse();
+    let registry = datastore::tenant_registry(&opts.project);
+    match opts.command {
+        Command::List => {
+            for tenant in registry.tenants().await? {
+                println!("{}\t{}\t{}", tenant.id(), tenant.name(), tenant.namespace());
+            }
+        }
+        Command::Register(register) => {
+            let tenant = registry.register(&register.id, &register.name).await?;
+            println!("{}: registered as {}", tenant.id(), tenant.namespace());
+        }
+        Command::Remove(remove) => {
+            if !remove.yes {
+                anyhow::bail!("pass --yes to delete all data of {}", remove.id);
+            }
+            let purged = registry.remove(&remove.id).await?;
+            println!("{}: {} entities deleted", remove.id, purged);
+        }
+        Command::Stats => {
+            for tenant in registry.tenants().await? {
+                for (kind, count) in registry.stats(&tenant).await? {
+                    println!("{}\t{}\t{}", tenant.id(), kind, count);
+                }
+            }
+        }
+        Command::Migrate(migrate) => {
+            let migrator = Migrator::new(datastore::migrations()).dry_run(migrate.dry_run);
+            for (tenant, reports) in registry.migrate(&migrator).await? {
+                for report in reports {
+                    println!(
+                        "{}: {} {}: {} entities {}",
+                        tenant.id(),
+                        report.version,
+                        report.name,
+                        report.migrated,
+                        if migrate.dry_run {
+                            "to migrate"
+                        } else {
+                            "migrated"
+                        }
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,420 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta, Path, Type};
+
+enum FieldKind {
+    Key,
+    Version,
+    Required,
+    Optional,
+    Repeated,
+}
+
+struct EntityField {
+    ident: Ident,
+    ty: Type,
+    property: String,
+    kind: FieldKind,
+    exclude_from_indexes: bool,
+}
+
+struct EntityStruct {
+    api: Path,
+    remote: Option<Path>,
+    constructor: Option<Ident>,
+    fields: Vec<EntityField>,
+}
+
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+    let entity = parse_struct(&input)?;
+    let api = &entity.api;
+    let mirror = &input.ident;
+    let vis = &input.vis;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    // NOTE: remoteを指定した場合は、この構造体を保存する形の定義として使い、変換は指定した型に実装する
+    let type_name = match &entity.remote {
+        Some(remote) => quote!(#remote),
+        None => quote!(#mirror #ty_generics),
+    };
+    if let (Some(remote), Some(_)) = (&entity.remote, input.generics.params.first()) {
+        return Err(syn::Error::new_spanned(
+            remote,
+            "#[entity(remote = \"...\")] does not support generics",
+        ));
+    }
+
+    let key = entity
+        .fields
+        .iter()
+        .filter(|f| matches!(f.kind, FieldKind::Key))
+        .collect::<Vec<_>>();
+    if key.len() > 1 {
+        return Err(syn::Error::new_spanned(
+            &key[1].ident,
+            "#[entity(key)] can be specified only once",
+        ));
+    }
+    let key = key.first();
+    let version = entity
+        .fields
+        .iter()
+        .filter(|f| matches!(f.kind, FieldKind::Version))
+        .collect::<Vec<_>>();
+    if version.len() > 1 {
+        return Err(syn::Error::new_spanned(
+            &version[1].ident,
+            "#[entity(version)] can be specified only once",
+        ));
+    }
+    let version = version.first();
+    if let (None, Some(version)) = (key, version) {
+        return Err(syn::Error::new_spanned(
+            &version.ident,
+            "#[entity(version)] requires #[entity(key)] field",
+        ));
+    }
+    if key.is_none() {
+        if let Some(f) = entity.fields.iter().find(|f| f.exclude_from_indexes) {
+            return Err(syn::Error::new_spanned(
+                &f.ident,
+                "exclude_from_indexes requires #[entity(key)] field",
+            ));
+        }
+    }
+
+    let fields_name = match &entity.remote {
+        Some(remote) => format_ident!("{}Fields", remote.segments.last().unwrap().ident),
+        None => format_ident!("{}Fields", mirror),
+    };
+    let consts = entity
+        .fields
+        .iter()
+        .filter(|f| !matches!(f.kind, FieldKind::Key | FieldKind::Version))
+        .map(|f| {
+            let name = format_ident!(
+                "{}",
+                f.ident.to_string().trim_start_matches("r#").to_uppercase()
+            );
+            let property = &f.property;
+            quote!(#vis const #name: &'static str = #property;)
+        });
+
+    let inserts = entity
+        .fields
+        .iter()
+        .filter(|f| !matches!(f.kind, FieldKind::Key | FieldKind::Version))
+        .map(|f| {
+            let ident = &f.ident;
+            let property = &f.property;
+            match f.kind {
+                FieldKind::Optional => quote! {
+                    if let ::std::option::Option::Some(property) = value.#ident {
+                        properties.insert(
+                            ::std::string::String::from(#property),
+                            #api::IntoValue::into_value(property),
+                        );
+                    }
+                },
+                _ => quote! {
+                    properties.insert(
+                        ::std::string::String::from(#property),
+                        #api::IntoValue::into_value(value.#ident),
+                    );
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let removes = entity.fields.iter().map(|f| {
+        let ident = &f.ident;
+        let ty = &f.ty;
+        let property = &f.property;
+        match f.kind {
+            FieldKind::Key | FieldKind::Version => quote!(),
+            FieldKind::Required => quote! {
+                let #ident: #ty = #api::FromValue::from_value(
+                    properties.remove(#property).ok_or_else(|| {
+                        #api::ConvertError::MissingProperty(::std::string::String::from(#property))
+                    })?,
+                )?;
+            },
+            // NOTE: NULLが保存されていれば、プロパティが無いのと同じく扱う
+            FieldKind::Optional => quote! {
+                let #ident: #ty = properties
+                    .remove(#property)
+                    .filter(|value| !::std::matches!(value, #api::Value::Null))
+                    .map(#api::FromValue::from_value)
+                    .transpose()?;
+            },
+            FieldKind::Repeated => quote! {
+                let #ident: #ty = properties
+                    .remove(#property)
+                    .filter(|value| !::std::matches!(value, #api::Value::Null))
+                    .map(#api::FromValue::from_value)
+                    .transpose()?
+                    .unwrap_or_default();
+            },
+        }
+    })
+    .collect::<Vec<_>>();
+
+    let idents = entity.fields.iter().map(|f| &f.ident).collect::<Vec<_>>();
+    let construct = match (&entity.remote, &entity.constructor) {
+        // NOTE: バージョンはドメインの値ではないので、コンストラクタには渡さずに後から設定する
+        (None, Some(constructor)) => {
+            let args = entity
+                .fields
+                .iter()
+                .filter(|f| !matches!(f.kind, FieldKind::Version))
+                .map(|f| &f.ident);
+            let set_version = version.map(|EntityField { ident, .. }| {
+                quote!(.map(|mut value| {
+                    value.#ident = #ident;
+                    value
+                }))
+            });
+            quote! {
+                Self::#constructor(#(#args),*)
+                    .map_err(#api::ConvertError::from)
+                    #set_version
+            }
+        }
+        (None, None) => quote! {
+            ::std::result::Result::Ok(Self { #(#idents),* })
+        },
+        // NOTE: 指定した型のフィールドには触れないので、コンストラクタで生成してから`with_<field>`でバージョンを設定する
+        (Some(_), constructor) => {
+            let args = entity
+                .fields
+                .iter()
+                .filter(|f| !matches!(f.kind, FieldKind::Version))
+                .map(|f| &f.ident);
+            let take_version =
+                version.map(|EntityField { ident, .. }| quote!(let #ident = value.#ident;));
+            let set_version = version.map(|EntityField { ident, .. }| {
+                let with = format_ident!("with_{}", ident);
+                quote!(.map(|entity| entity.#with(#ident)))
+            });
+            let construct = match constructor {
+                Some(constructor) => quote! {
+                    #type_name::#constructor(#(value.#args),*).map_err(#api::ConvertError::from)
+                },
+                None => quote! {
+                    ::std::result::Result::Ok(#type_name::new(#(value.#args),*))
+                },
+            };
+            quote! {
+                let value = #mirror { #(#idents),* };
+                #take_version
+                #construct
+                    #set_version
+            }
+        }
+    };
+    // NOTE: remoteの場合はゲッターで値を写してから、この構造体のフィールドとして読む
+    let into_mirror = match &entity.remote {
+        Some(_) => quote! {
+            let value = #mirror {
+                #(#idents: ::std::clone::Clone::clone(self.#idents())),*
+            };
+        },
+        None => quote!(let value = self;),
+    };
+
+    let read_version = version.map(|EntityField { ident, ty, .. }| {
+        quote! {
+            let #ident: #ty = ::std::convert::From::from(entity.version());
+        }
+    });
+    let from = match key {
+        Some(EntityField { ident, ty, .. }) => quote! {
+            impl #impl_generics #api::FromEntity for #type_name #where_clause {
+                fn from_entity(
+                    entity: #api::Entity,
+                ) -> ::std::result::Result<Self, #api::ConvertError> {
+                    let #ident: #ty = ::std::convert::TryFrom::try_from(entity.key().clone())?;
+                    #read_version
+                    let mut properties = <::std::collections::HashMap<
+                        ::std::string::String,
+                        #api::Value,
+                    > as #api::FromValue>::from_value(entity.into_properties())?;
+                    #(#removes)*
+                    #construct
+                }
+            }
+        },
+        None => quote! {
+            impl #impl_generics #api::FromValue for #type_name #where_clause {
+                fn from_value(
+                    value: #api::Value,
+                ) -> ::std::result::Result<Self, #api::ConvertError> {
+                    let mut properties = <::std::collections::HashMap<
+                        ::std::string::String,
+                        #api::Value,
+                    > as #api::FromValue>::from_value(value)?;
+                    #(#removes)*
+                    #construct
+                }
+            }
+        },
+    };
+
+    let into_entity = key.map(|key| {
+        let key_ident = &key.ident;
+        let excluded = entity
+            .fields
+            .iter()
+            .filter(|f| f.exclude_from_indexes)
+            .map(|f| &f.property);
+        let take_version = version.map(|EntityField { ident, .. }| {
+            quote! {
+                let version: ::std::option::Option<i64> = ::std::convert::From::from(value.#ident);
+            }
+        });
+        let with_version = version.map(|_| quote!(.map(|entity| entity.with_version(version))));
+        quote! {
+            impl #impl_generics #api::IntoEntity for #type_name #where_clause {
+                fn into_entity(self) -> ::std::result::Result<#api::Entity, #api::ConvertError> {
+                    #into_mirror
+                    let key: #api::Key = ::std::convert::From::from(value.#key_ident);
+                    #take_version
+                    let mut properties = ::std::collections::HashMap::new();
+                    #(#inserts)*
+                    #api::Entity::new(key, #api::Value::Entity(properties))
+                        .map(|entity| entity.exclude_from_indexes(&[#(#excluded),*]))
+                        #with_version
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        #vis struct #fields_name;
+
+        #[allow(dead_code)]
+        impl #fields_name {
+            #(#consts)*
+        }
+
+        impl #impl_generics #api::IntoValue for #type_name #where_clause {
+            fn into_value(self) -> #api::Value {
+                #into_mirror
+                let mut properties = ::std::collections::HashMap::new();
+                #(#inserts)*
+                #api::Value::Entity(properties)
+            }
+        }
+
+        #into_entity
+
+        #from
+    })
+}
+
+fn parse_struct(input: &DeriveInput) -> syn::Result<EntityStruct> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "#[derive(Entity)] supports only structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "#[derive(Entity)] supports only structs",
+            ))
+        }
+    };
+
+    let mut api = syn::parse_quote!(proto_api);
+    let mut remote = None;
+    let mut constructor = None;
+    for meta in entity_metas(&input.attrs)? {
+        match meta {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("api") => {
+                api = lit_str(&nv.lit)?.parse()?;
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("remote") => {
+                remote = Some(lit_str(&nv.lit)?.parse()?);
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("constructor") => {
+                constructor = Some(lit_str(&nv.lit)?.parse()?);
+            }
+            meta => return Err(syn::Error::new_spanned(meta, "unknown entity attribute")),
+        }
+    }
+
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let ident = field.ident.clone().unwrap();
+            let mut entity_field = EntityField {
+                property: ident.to_string().trim_start_matches("r#").into(),
+                kind: field_kind(&field.ty),
+                ident,
+                ty: field.ty.clone(),
+                exclude_from_indexes: false,
+            };
+            for meta in entity_metas(&field.attrs)? {
+                match meta {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("key") => {
+                        entity_field.kind = FieldKind::Key;
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("version") => {
+                        entity_field.kind = FieldKind::Version;
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("exclude_from_indexes") => {
+                        entity_field.exclude_from_indexes = true;
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                        entity_field.property = lit_str(&nv.lit)?.value();
+                    }
+                    meta => return Err(syn::Error::new_spanned(meta, "unknown entity attribute")),
+                }
+            }
+            Ok(entity_field)
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+    Ok(EntityStruct {
+        api,
+        remote,
+        constructor,
+        fields,
+    })
+}
+
+fn entity_metas(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut metas = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("entity")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => metas.extend(list.nested),
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[entity(...)]")),
+        }
+    }
+    Ok(metas)
+}
+
+fn lit_str(lit: &Lit) -> syn::Result<&syn::LitStr> {
+    match lit {
+        Lit::Str(s) => Ok(s),
+        lit => Err(syn::Error::new_spanned(lit, "expected string literal")),
+    }
+}
+
+// NOTE: Optionは値が無ければプロパティを書かず、Vecは空配列が保存されないことがあるので、
+// 読み込み時にプロパティが無ければそれぞれNone・空として扱う
+fn field_kind(ty: &Type) -> FieldKind {
+    let last = match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    };
+    match last.as_deref() {
+        Some("Option") => FieldKind::Optional,
+        Some("Vec") => FieldKind::Repeated,
+        _ => FieldKind::Required,
+    }
+}
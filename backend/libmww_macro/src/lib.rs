@@ -1,3 +1,6 @@
+mod entity;
+mod validated_newtype;
+
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::parse_macro_input;
@@ -16,3 +19,36 @@ pub fn named_tuple_from(input: TokenStream) -> TokenStream {
     };
     expanded.into()
 }
+
+/// Datastoreのエンティティとの変換を実装する
+///
+/// - `#[entity(key)]`: キーにするフィールド。無い場合はネストした値として`FromValue`を実装する
+/// - `#[entity(rename = "...")]`: プロパティ名を変える
+/// - `#[entity(exclude_from_indexes)]`: インデックスを作らない
+/// - `#[entity(version)]`: エンティティのバージョンを持つフィールド。`Option<i64>`と相互に変換でき、書き込むときの競合の検出に使う
+/// - `#[entity(constructor = "try_new")]`: バージョン以外の全フィールドを定義順に渡して生成する。エラーは`ConvertError`に変換する
+/// - `#[entity(api = "...")]`: `Entity`・`Value`・`IntoValue`などを持つモジュールのパス。省略すると`proto_api`
+/// - `#[entity(remote = "...")]`: 変換を指定した型に実装する。この構造体はフィールドの並びだけを表し、
+///   値はゲッターで読み、`constructor`で生成する。省略すると失敗しない`new`を使う。バージョンは`with_<field>`で設定する
+#[proc_macro_derive(Entity, attributes(entity))]
+pub fn entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    entity::derive(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// `struct Foo(String)`に検証付きの`try_new`・`raw`・`Display`を実装する
+///
+/// `#[validate(name = "...", trim, non_empty, non_blank, min_length = 1, max_length = 10, regex = "...")]`
+/// で検証を指定する。`name`はエラーメッセージに使う。`non_empty`は空文字列を、`non_blank`は空白だけの文字列も拒否する。
+/// 正規表現はマクロの展開時に検証する。
+/// `datastore = "..."`に`Value`・`IntoValue`・`FromValue`・`ConvertError`を持つモジュールのパスを指定すると、
+/// Datastoreの値との変換も実装する
+#[proc_macro_derive(ValidatedNewtype, attributes(validate))]
+pub fn validated_newtype(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    validated_newtype::derive(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
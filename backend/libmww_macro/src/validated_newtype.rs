@@ -0,0 +1,220 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Lit, LitStr, Meta, NestedMeta};
+
+struct Validation {
+    name: String,
+    trim: bool,
+    non_empty: bool,
+    non_blank: bool,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    regex: Option<LitStr>,
+    datastore: Option<syn::Path>,
+}
+
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+    check_newtype(&input)?;
+    let validation = parse_validation(&input)?;
+    let domain = quote!(crate::domain);
+    let type_name = &input.ident;
+    let name = &validation.name;
+
+    let trim = if validation.trim {
+        quote!(let raw = ::std::string::String::from(raw.trim());)
+    } else {
+        quote!()
+    };
+    let mut checks = vec![];
+    if validation.non_empty {
+        checks.push((
+            quote!(raw.is_empty()),
+            format!("{} should not be blank", name),
+        ));
+    }
+    if validation.non_blank {
+        checks.push((
+            quote!(raw.trim().is_empty()),
+            format!("{} should not be blank", name),
+        ));
+    }
+    if let Some(min_length) = validation.min_length {
+        checks.push((
+            quote!(raw.chars().count() < #min_length),
+            format!("{} should be at least {} characters", name, min_length),
+        ));
+    }
+    if let Some(max_length) = validation.max_length {
+        checks.push((
+            quote!(raw.chars().count() > #max_length),
+            format!("{} should be at most {} characters", name, max_length),
+        ));
+    }
+    if let Some(regex) = &validation.regex {
+        // NOTE: 正規表現は展開時に検証済みなので、実行時のコンパイルは失敗しない
+        checks.push((
+            quote! {{
+                static PATTERN: ::once_cell::sync::Lazy<::regex::Regex> =
+                    ::once_cell::sync::Lazy::new(|| {
+                        ::regex::Regex::new(#regex).expect("validated when the macro expanded")
+                    });
+                !PATTERN.is_match(&raw)
+            }},
+            format!("{} should match {}", name, regex.value()),
+        ));
+    }
+    let checks = checks.iter().map(|(condition, message)| {
+        quote! {
+            if #condition {
+                return ::std::result::Result::Err(#domain::DomainError::new(
+                    #domain::DomainErrorKind::InvalidInput,
+                    #message,
+                ));
+            }
+        }
+    });
+
+    // NOTE: Datastoreには文字列として保存し、読み込むときにもう一度検証する
+    let datastore = validation.datastore.as_ref().map(|api| {
+        quote! {
+            impl #api::IntoValue for #type_name {
+                fn into_value(self) -> #api::Value {
+                    #api::IntoValue::into_value(::std::string::String::from(self))
+                }
+            }
+
+            impl #api::FromValue for #type_name {
+                fn from_value(
+                    value: #api::Value,
+                ) -> ::std::result::Result<Self, #api::ConvertError> {
+                    let raw: ::std::string::String = #api::FromValue::from_value(value)?;
+                    ::std::result::Result::Ok(Self::try_new(raw)?)
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #type_name {
+            pub fn try_new(
+                raw: impl ::std::convert::Into<::std::string::String>,
+            ) -> #domain::DomainResult<Self> {
+                let raw = raw.into();
+                #trim
+                #(#checks)*
+                ::std::result::Result::Ok(Self(raw))
+            }
+
+            pub fn raw(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl ::std::fmt::Display for #type_name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl ::std::convert::From<#type_name> for ::std::string::String {
+            fn from(v: #type_name) -> ::std::string::String {
+                v.0
+            }
+        }
+
+        #datastore
+    })
+}
+
+fn check_newtype(input: &DeriveInput) -> syn::Result<()> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Ok(()),
+            _ => Err(syn::Error::new_spanned(
+                input,
+                "#[derive(ValidatedNewtype)] supports only newtypes like `struct Foo(String)`",
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            input,
+            "#[derive(ValidatedNewtype)] supports only structs",
+        )),
+    }
+}
+
+fn parse_validation(input: &DeriveInput) -> syn::Result<Validation> {
+    let mut validation = Validation {
+        name: input.ident.to_string(),
+        trim: false,
+        non_empty: false,
+        non_blank: false,
+        min_length: None,
+        max_length: None,
+        regex: None,
+        datastore: None,
+    };
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("validate"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[validate(...)]")),
+        };
+        for meta in list.nested {
+            match meta {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("trim") => {
+                    validation.trim = true;
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("non_empty") => {
+                    validation.non_empty = true;
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("non_blank") => {
+                    validation.non_blank = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                    validation.name = lit_str(&nv.lit)?;
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("min_length") => {
+                    validation.min_length = Some(lit_usize(&nv.lit)?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("max_length") => {
+                    validation.max_length = Some(lit_usize(&nv.lit)?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("regex") => {
+                    let regex = match &nv.lit {
+                        Lit::Str(s) => s.clone(),
+                        lit => return Err(syn::Error::new_spanned(lit, "expected string literal")),
+                    };
+                    if let Err(e) = regex::Regex::new(&regex.value()) {
+                        return Err(syn::Error::new_spanned(
+                            &regex,
+                            format!("invalid regex: {}", e),
+                        ));
+                    }
+                    validation.regex = Some(regex);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("datastore") => {
+                    validation.datastore = Some(syn::parse_str(&lit_str(&nv.lit)?)?);
+                }
+                meta => return Err(syn::Error::new_spanned(meta, "unknown validate attribute")),
+            }
+        }
+    }
+    Ok(validation)
+}
+
+fn lit_str(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        lit => Err(syn::Error::new_spanned(lit, "expected string literal")),
+    }
+}
+
+fn lit_usize(lit: &Lit) -> syn::Result<usize> {
+    match lit {
+        Lit::Int(i) => i.base10_parse(),
+        lit => Err(syn::Error::new_spanned(lit, "expected integer literal")),
+    }
+}
@@ -0,0 +1,426 @@
+#[macro_use]
+extern crate libmww_macro;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// NOTE: 生成されたコードが使うAPIだけを持つ、Datastoreの変換の代わり
+mod api {
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Null,
+        Integer(i64),
+        Strings(String),
+        Array(Vec<Value>),
+        Entity(HashMap<String, Value>),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum ConvertError {
+        MissingProperty(String),
+        UnexpectedPropertyType,
+        InvalidValue(String),
+    }
+
+    impl From<String> for ConvertError {
+        fn from(message: String) -> Self {
+            ConvertError::InvalidValue(message)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Key(pub String);
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Entity {
+        key: Key,
+        properties: Value,
+        pub excluded: Vec<String>,
+        version: Option<i64>,
+    }
+
+    impl Entity {
+        pub fn new(key: Key, properties: Value) -> Result<Entity, ConvertError> {
+            Ok(Entity {
+                key,
+                properties,
+                excluded: vec![],
+                version: None,
+            })
+        }
+
+        pub fn exclude_from_indexes(mut self, names: &[&str]) -> Entity {
+            self.excluded
+                .extend(names.iter().map(|name| name.to_string()));
+            self
+        }
+
+        pub fn with_version(mut self, version: Option<i64>) -> Entity {
+            self.version = version;
+            self
+        }
+
+        pub fn version(&self) -> Option<i64> {
+            self.version
+        }
+
+        pub fn key(&self) -> &Key {
+            &self.key
+        }
+
+        pub fn properties(&self) -> &Value {
+            &self.properties
+        }
+
+        pub fn into_properties(self) -> Value {
+            self.properties
+        }
+    }
+
+    pub trait IntoValue {
+        fn into_value(self) -> Value;
+    }
+
+    pub trait FromValue: Sized {
+        fn from_value(value: Value) -> Result<Self, ConvertError>;
+    }
+
+    pub trait IntoEntity {
+        fn into_entity(self) -> Result<Entity, ConvertError>;
+    }
+
+    pub trait FromEntity: Sized {
+        fn from_entity(entity: Entity) -> Result<Self, ConvertError>;
+    }
+
+    impl IntoValue for i64 {
+        fn into_value(self) -> Value {
+            Value::Integer(self)
+        }
+    }
+
+    impl FromValue for i64 {
+        fn from_value(value: Value) -> Result<Self, ConvertError> {
+            match value {
+                Value::Integer(value) => Ok(value),
+                _ => Err(ConvertError::UnexpectedPropertyType),
+            }
+        }
+    }
+
+    impl IntoValue for String {
+        fn into_value(self) -> Value {
+            Value::Strings(self)
+        }
+    }
+
+    impl FromValue for String {
+        fn from_value(value: Value) -> Result<Self, ConvertError> {
+            match value {
+                Value::Strings(value) => Ok(value),
+                _ => Err(ConvertError::UnexpectedPropertyType),
+            }
+        }
+    }
+
+    impl<T: IntoValue> IntoValue for Vec<T> {
+        fn into_value(self) -> Value {
+            Value::Array(self.into_iter().map(IntoValue::into_value).collect())
+        }
+    }
+
+    impl<T: FromValue> FromValue for Vec<T> {
+        fn from_value(value: Value) -> Result<Self, ConvertError> {
+            match value {
+                Value::Array(values) => values.into_iter().map(T::from_value).collect(),
+                _ => Err(ConvertError::UnexpectedPropertyType),
+            }
+        }
+    }
+
+    impl FromValue for HashMap<String, Value> {
+        fn from_value(value: Value) -> Result<Self, ConvertError> {
+            match value {
+                Value::Entity(properties) => Ok(properties),
+                _ => Err(ConvertError::UnexpectedPropertyType),
+            }
+        }
+    }
+}
+
+use api::{ConvertError, FromEntity, FromValue, IntoEntity, IntoValue, Key, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Id(String);
+
+impl TryFrom<Key> for Id {
+    type Error = ConvertError;
+
+    fn try_from(key: Key) -> Result<Self, ConvertError> {
+        Ok(Id(key.0))
+    }
+}
+
+impl From<Id> for Key {
+    fn from(id: Id) -> Key {
+        Key(id.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Entity)]
+#[entity(api = "crate::api")]
+struct Member {
+    name: String,
+    score: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Entity)]
+#[entity(api = "crate::api", constructor = "try_new")]
+struct Team {
+    #[entity(key)]
+    id: Id,
+    #[entity(rename = "teamName")]
+    name: String,
+    #[entity(exclude_from_indexes)]
+    note: Option<String>,
+    members: Vec<Member>,
+    #[entity(version)]
+    version: Option<i64>,
+}
+
+impl Team {
+    fn try_new(
+        id: Id,
+        name: String,
+        note: Option<String>,
+        members: Vec<Member>,
+    ) -> Result<Team, String> {
+        if name.is_empty() {
+            return Err(String::from("name should not be empty"));
+        }
+        Ok(Team {
+            id,
+            name,
+            note,
+            members,
+            version: None,
+        })
+    }
+}
+
+fn team() -> Team {
+    Team {
+        id: Id(String::from("team1")),
+        name: String::from("wolves"),
+        note: Some(String::from("note")),
+        members: vec![Member {
+            name: String::from("alice"),
+            score: 3,
+        }],
+        version: Some(7),
+    }
+}
+
+fn properties(entity: &api::Entity) -> HashMap<String, Value> {
+    HashMap::from_value(entity.properties().clone()).unwrap()
+}
+
+#[test]
+fn entity_round_trip_works() {
+    let entity = team().into_entity().unwrap();
+    assert_eq!(entity.key(), &Key(String::from("team1")));
+    assert_eq!(entity.version(), Some(7));
+    assert_eq!(entity.excluded, vec![String::from("note")]);
+    assert_eq!(Team::from_entity(entity), Ok(team()));
+}
+
+#[test]
+fn entity_uses_renamed_property_names() {
+    let entity = team().into_entity().unwrap();
+    let properties = properties(&entity);
+    assert_eq!(TeamFields::NAME, "teamName");
+    assert_eq!(
+        properties[TeamFields::NAME],
+        Value::Strings(String::from("wolves"))
+    );
+    assert!(!properties.contains_key("id"));
+    assert!(!properties.contains_key("version"));
+}
+
+#[test]
+fn entity_nests_values_without_key() {
+    let member = Member {
+        name: String::from("bob"),
+        score: 1,
+    };
+    let value = member.clone().into_value();
+    let mut expected = HashMap::new();
+    expected.insert(String::from("name"), Value::Strings(String::from("bob")));
+    expected.insert(String::from("score"), Value::Integer(1));
+    assert_eq!(value, Value::Entity(expected));
+    assert_eq!(Member::from_value(value), Ok(member));
+}
+
+#[test]
+fn entity_skips_none_and_reads_missing_option_and_vec_as_empty() {
+    let team = Team {
+        note: None,
+        members: vec![],
+        version: None,
+        ..team()
+    };
+    let entity = team.clone().into_entity().unwrap();
+    assert!(!properties(&entity).contains_key(TeamFields::NOTE));
+
+    let mut properties = HashMap::new();
+    properties.insert(
+        String::from(TeamFields::NAME),
+        Value::Strings(String::from("wolves")),
+    );
+    let entity = api::Entity::new(Key(String::from("team1")), Value::Entity(properties)).unwrap();
+    assert_eq!(Team::from_entity(entity), Ok(team));
+}
+
+#[test]
+fn entity_reads_null_option_and_vec_as_empty() {
+    let team = Team {
+        note: None,
+        members: vec![],
+        version: None,
+        ..team()
+    };
+    let mut properties = HashMap::new();
+    properties.insert(
+        String::from(TeamFields::NAME),
+        Value::Strings(String::from("wolves")),
+    );
+    properties.insert(String::from(TeamFields::NOTE), Value::Null);
+    properties.insert(String::from(TeamFields::MEMBERS), Value::Null);
+    let entity = api::Entity::new(Key(String::from("team1")), Value::Entity(properties)).unwrap();
+    assert_eq!(Team::from_entity(entity), Ok(team));
+}
+
+#[test]
+fn entity_reports_missing_required_property() {
+    let entity =
+        api::Entity::new(Key(String::from("team1")), Value::Entity(HashMap::new())).unwrap();
+    assert_eq!(
+        Team::from_entity(entity),
+        Err(ConvertError::MissingProperty(String::from("teamName")))
+    );
+}
+
+#[test]
+fn entity_converts_constructor_error_instead_of_panicking() {
+    let entity = Team {
+        name: String::new(),
+        ..team()
+    }
+    .into_entity()
+    .unwrap();
+    assert_eq!(
+        Team::from_entity(entity),
+        Err(ConvertError::InvalidValue(String::from(
+            "name should not be empty"
+        )))
+    );
+}
+
+// NOTE: フィールドが非公開の型を、別のモジュールの構造体から変換する
+mod model {
+    use super::Id;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Player {
+        id: Id,
+        name: String,
+        version: Option<i64>,
+    }
+
+    impl Player {
+        pub fn try_new(id: Id, name: String) -> Result<Player, String> {
+            Ok(Player {
+                id,
+                name,
+                version: None,
+            })
+        }
+
+        pub fn with_version(mut self, version: Option<i64>) -> Player {
+            self.version = version;
+            self
+        }
+
+        pub fn id(&self) -> &Id {
+            &self.id
+        }
+
+        pub fn name(&self) -> &String {
+            &self.name
+        }
+
+        pub fn version(&self) -> &Option<i64> {
+            &self.version
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Score {
+        value: i64,
+    }
+
+    impl Score {
+        pub fn new(value: i64) -> Score {
+            Score { value }
+        }
+
+        pub fn value(&self) -> &i64 {
+            &self.value
+        }
+    }
+}
+
+mod record {
+    use super::model;
+    use super::Id;
+    // NOTE: apiを省略すると、呼び出し側で見える`proto_api`を使う
+    use crate::api as proto_api;
+
+    #[derive(Entity)]
+    #[entity(remote = "model::Player", constructor = "try_new")]
+    struct PlayerRecord {
+        #[entity(key)]
+        id: Id,
+        name: String,
+        #[entity(version)]
+        version: Option<i64>,
+    }
+
+    #[derive(Entity)]
+    #[entity(remote = "model::Score")]
+    struct ScoreRecord {
+        value: i64,
+    }
+}
+
+#[test]
+fn entity_remote_round_trip_works() {
+    let player = model::Player::try_new(Id(String::from("player1")), String::from("alice"))
+        .unwrap()
+        .with_version(Some(2));
+    let entity = player.clone().into_entity().unwrap();
+    assert_eq!(entity.version(), Some(2));
+    assert_eq!(
+        properties(&entity)["name"],
+        Value::Strings(String::from("alice"))
+    );
+    assert_eq!(model::Player::from_entity(entity), Ok(player));
+
+    let score = model::Score::new(5);
+    assert_eq!(
+        model::Score::from_value(score.clone().into_value()),
+        Ok(score)
+    );
+}
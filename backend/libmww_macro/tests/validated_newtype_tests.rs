@@ -0,0 +1,163 @@
+#[macro_use]
+extern crate libmww_macro;
+
+// NOTE: 生成されたコードが使う`crate::domain`のエラーの代わり
+mod domain {
+    #[derive(Debug, PartialEq)]
+    pub enum DomainErrorKind {
+        InvalidInput,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct DomainError {
+        kind: DomainErrorKind,
+        message: String,
+    }
+
+    impl DomainError {
+        pub fn new(kind: DomainErrorKind, message: impl Into<String>) -> Self {
+            DomainError {
+                kind,
+                message: message.into(),
+            }
+        }
+    }
+
+    pub type DomainResult<T> = Result<T, DomainError>;
+}
+
+// NOTE: `datastore`で指定するDatastoreの値の代わり
+mod api {
+    #[derive(Debug, PartialEq)]
+    pub enum Value {
+        Integer(i64),
+        Strings(String),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum ConvertError {
+        UnexpectedPropertyType,
+        InvalidValue(String),
+    }
+
+    impl From<super::domain::DomainError> for ConvertError {
+        fn from(err: super::domain::DomainError) -> Self {
+            ConvertError::InvalidValue(format!("{:?}", err))
+        }
+    }
+
+    pub trait IntoValue {
+        fn into_value(self) -> Value;
+    }
+
+    pub trait FromValue: Sized {
+        fn from_value(value: Value) -> Result<Self, ConvertError>;
+    }
+
+    impl IntoValue for String {
+        fn into_value(self) -> Value {
+            Value::Strings(self)
+        }
+    }
+
+    impl FromValue for String {
+        fn from_value(value: Value) -> Result<Self, ConvertError> {
+            match value {
+                Value::Strings(s) => Ok(s),
+                _ => Err(ConvertError::UnexpectedPropertyType),
+            }
+        }
+    }
+}
+
+use api::{ConvertError, FromValue, IntoValue, Value};
+use domain::{DomainError, DomainErrorKind, DomainResult};
+
+#[derive(Debug, PartialEq, ValidatedNewtype)]
+#[validate(
+    name = "code",
+    trim,
+    min_length = 2,
+    max_length = 4,
+    regex = "^[a-z]+$"
+)]
+struct Code(String);
+
+#[derive(Debug, PartialEq, ValidatedNewtype)]
+#[validate(name = "name", non_empty)]
+struct Name(String);
+
+#[derive(Debug, PartialEq, ValidatedNewtype)]
+#[validate(name = "title", non_blank)]
+struct Title(String);
+
+#[derive(Debug, PartialEq, ValidatedNewtype)]
+#[validate(name = "label", non_empty, datastore = "api")]
+struct Label(String);
+
+fn invalid(message: &str) -> DomainError {
+    DomainError::new(DomainErrorKind::InvalidInput, message)
+}
+
+#[test]
+fn validated_newtype_try_new_works() {
+    assert_eq!(Code::try_new(" ab "), Ok(Code("ab".into())));
+    assert_eq!(Code::try_new("abcd"), Ok(Code("abcd".into())));
+    assert_eq!(
+        Code::try_new("a"),
+        Err(invalid("code should be at least 2 characters"))
+    );
+    assert_eq!(
+        Code::try_new("abcde"),
+        Err(invalid("code should be at most 4 characters"))
+    );
+    assert_eq!(
+        Code::try_new("ab1"),
+        Err(invalid("code should match ^[a-z]+$"))
+    );
+}
+
+#[test]
+fn validated_newtype_non_empty_keeps_whitespace() {
+    assert_eq!(Name::try_new(" "), Ok(Name(" ".into())));
+    assert_eq!(Name::try_new(""), Err(invalid("name should not be blank")));
+}
+
+#[test]
+fn validated_newtype_non_blank_rejects_whitespace() {
+    assert_eq!(Title::try_new(" a "), Ok(Title(" a ".into())));
+    assert_eq!(
+        Title::try_new("  "),
+        Err(invalid("title should not be blank"))
+    );
+}
+
+#[test]
+fn validated_newtype_accessors_work() -> DomainResult<()> {
+    let code = Code::try_new("abc")?;
+    assert_eq!(code.raw(), "abc");
+    assert_eq!(code.to_string(), "abc");
+    assert_eq!(String::from(code), "abc");
+    Ok(())
+}
+
+#[test]
+fn validated_newtype_datastore_value_works() {
+    let label = Label::try_new("abc").unwrap();
+    assert_eq!(label.into_value(), Value::Strings("abc".into()));
+    assert_eq!(
+        Label::from_value(Value::Strings("abc".into())),
+        Ok(Label("abc".into()))
+    );
+    assert_eq!(
+        Label::from_value(Value::Strings("".into())),
+        Err(ConvertError::InvalidValue(format!(
+            "{:?}",
+            invalid("label should not be blank")
+        )))
+    );
+    assert_eq!(
+        Label::from_value(Value::Integer(1)),
+        Err(ConvertError::UnexpectedPropertyType)
+    );
+}
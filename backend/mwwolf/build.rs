@@ -1,6 +1,6 @@
 pub fn main() {
     tonic_build::configure()
-        .build_server(false)
+        .build_server(true)
         .compile(&["proto/google/datastore/v1/datastore.proto"], &["proto"])
         .unwrap();
 }
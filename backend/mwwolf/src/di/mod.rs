@@ -31,8 +31,6 @@ where
     Mutation: ObjectType + 'static,
     Subscription: SubscriptionType + 'static,
 {
-    let cf = create_connection_factory("xxxxxxxxxxxxxxxxxxxxxx".into());
-    Schema::build(query, mutaion, subscription)
-        .data(cf)
-        .finish()
+    // NOTE: ConnectionFactoryはテナントごとに異なるので、リクエストのデータとして渡す
+    Schema::build(query, mutaion, subscription).finish()
 }
@@ -7,9 +7,17 @@ pub type Transaction = datastore::Transaction;
 
 pub type ConnectionFactory = datastore::ConnectionFactory;
 
+pub type TenantRegistry = datastore::TenantRegistry;
+
 pub fn create_connection_factory(namespace: String) -> Arc<ConnectionFactory> {
     Arc::new(ConnectionFactory::new(
         std::env::var("GOOGLE_CLOUD_PROJECT").unwrap(),
         namespace,
     ))
 }
+
+pub fn create_tenant_registry() -> Arc<TenantRegistry> {
+    Arc::new(datastore::tenant_registry(
+        &std::env::var("GOOGLE_CLOUD_PROJECT").unwrap(),
+    ))
+}
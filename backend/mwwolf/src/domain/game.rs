@@ -27,12 +27,19 @@ impl GameMinutes {
     pub fn calc_ended_at(&self, started_at: &DateTime<Tz>) -> DateTime<Tz> {
         *started_at + self.0
     }
+
+    pub fn raw_minutes(&self) -> u32 {
+        self.0.num_minutes() as u32
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, EnumString, IntoStaticStr)]
 pub enum GameStatus {
+    #[strum(serialize = "talking")]
     Talking,
+    #[strum(serialize = "voting")]
     Voting,
+    #[strum(serialize = "ended")]
     Ended,
 }
 
@@ -109,6 +116,59 @@ pub trait GameFactory {
     ) -> DomainResult<Game>;
 }
 
+pub trait GameFactoryTypeParameters {
+    type IdGenerator: IdGenerator<Game>;
+}
+
+#[derive(new)]
+pub struct GameFactoryImpl<GFT: GameFactoryTypeParameters> {
+    id_generator: GFT::IdGenerator,
+}
+
+#[async_trait]
+impl<GFT: GameFactoryTypeParameters> GameFactory for GameFactoryImpl<GFT> {
+    async fn create(
+        &self,
+        room_id: Id<Room>,
+        theme_id: Id<Theme>,
+        ended_at: DateTime<Tz>,
+        wolf_group: WolfGroup,
+        citizen_group: CitizenGroup,
+    ) -> DomainResult<Game> {
+        let id = self.id_generator.generate().await.map_err(|e| {
+            DomainError::new_with_source(DomainErrorKind::Fail, "failed to generate game id", e)
+        })?;
+        Game::try_new(
+            id,
+            room_id,
+            theme_id,
+            ended_at,
+            wolf_group,
+            citizen_group,
+            VoteBox::new(vec![]),
+            GameStatus::Talking,
+        )
+    }
+}
+
+#[cfg_attr(test, automock(type Connection = MockConnection;))]
+#[async_trait]
+pub trait GameRepository {
+    type Connection: database::Connection;
+
+    async fn find<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        id: &Id<Game>,
+    ) -> RepositoryResult<Game>;
+
+    async fn store<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        game: &Game,
+    ) -> RepositoryResult<()>;
+}
+
 #[derive(new, Getters, Clone, Debug, PartialEq)]
 pub struct WolfGroup {
     players: Vec<Id<Player>>,
@@ -171,6 +231,7 @@ impl VoteBox {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testmww::mock::mock_libmww::id::MockIdGenerator;
     use test_case::test_case;
 
     fn datetime(year: i32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Tz> {
@@ -227,6 +288,49 @@ mod tests {
         GameMinutes::try_new(minutes)
     }
 
+    struct MockGameFactoryTypeParameters;
+
+    impl GameFactoryTypeParameters for MockGameFactoryTypeParameters {
+        type IdGenerator = MockIdGenerator<Game>;
+    }
+
+    #[test_case(
+        Ok(Id::new("game_1"))
+        => Ok(Game{
+            id: Id::new("game_1"),
+            room_id: Id::new("room_1"),
+            theme_id: Id::new("thema_1"),
+            ended_at: datetime(2021, 7, 30, 21, 19, 40),
+            wolves: WolfGroup::new(vec![Id::new("player_1")], Word::try_new("Test").unwrap()),
+            citizen: CitizenGroup::new(vec![Id::new("player_2")], Word::try_new("Test2").unwrap()),
+            vote_box: VoteBox::new(vec![]),
+            status: GameStatus::Talking,
+        })
+    )]
+    #[test_case(
+        Err(anyhow::anyhow!("unavailable"))
+        => Err(DomainError::new(DomainErrorKind::Fail, "failed to generate game id"))
+    )]
+    #[async_std::test]
+    async fn game_factory_impl_create_works(
+        generated: anyhow::Result<Id<Game>>,
+    ) -> DomainResult<Game> {
+        let mut id_generator = MockIdGenerator::new();
+        id_generator
+            .expect_generate()
+            .return_once(move || generated);
+        let game_factory = GameFactoryImpl::<MockGameFactoryTypeParameters>::new(id_generator);
+        game_factory
+            .create(
+                Id::new("room_1"),
+                Id::new("thema_1"),
+                datetime(2021, 7, 30, 21, 19, 40),
+                WolfGroup::new(vec![Id::new("player_1")], Word::try_new("Test").unwrap()),
+                CitizenGroup::new(vec![Id::new("player_2")], Word::try_new("Test2").unwrap()),
+            )
+            .await
+    }
+
     #[test_case(
         GameMinutes::try_new(3).unwrap(),
         datetime(2021,3,4,2,30,0)
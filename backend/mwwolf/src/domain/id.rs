@@ -1 +1,2 @@
 pub type Id<T> = crate::libmww::id::Id<T>;
+pub use crate::libmww::id::{IdGenerator, UlidGenerator, UuidGenerator};
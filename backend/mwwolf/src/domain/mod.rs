@@ -1,21 +1,29 @@
 mod error;
 mod game;
 mod id;
+mod page;
 mod player;
 mod result;
 mod room;
+mod tenant;
 mod theme;
+mod version;
 
 use crate::libmww::*;
 #[cfg(test)]
+use crate::testmww::mock::mock_libmww::database::MockConnection;
+#[cfg(test)]
 use crate::*;
 pub use error::*;
-pub use id::Id;
+pub use id::{Id, IdGenerator, UlidGenerator, UuidGenerator};
 
 pub use game::*;
+pub use page::*;
 pub use player::*;
 pub use player::*;
 pub use room::*;
+pub use tenant::*;
 pub use theme::*;
+pub use version::Version;
 
 pub use result::{DomainResult, RepositoryResult};
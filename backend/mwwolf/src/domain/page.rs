@@ -0,0 +1,64 @@
+use super::*;
+
+#[derive(Clone, Debug, PartialEq, Getters)]
+pub struct PageRequest {
+    first: usize,
+    after: Option<String>,
+}
+
+impl PageRequest {
+    pub const MAX_FIRST: usize = 100;
+
+    pub fn try_new(first: usize, after: Option<String>) -> DomainResult<Self> {
+        if first == 0 || first > Self::MAX_FIRST {
+            Err(DomainError::new(
+                DomainErrorKind::InvalidInput,
+                format!("first should be between 1 and {}", Self::MAX_FIRST),
+            ))
+        } else {
+            Ok(Self { first, after })
+        }
+    }
+}
+
+// NOTE: end_cursorが無ければ、これ以上の結果は無い
+#[derive(new, Clone, Debug, PartialEq, Getters)]
+pub struct Page<T> {
+    items: Vec<T>,
+    end_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn has_next_page(&self) -> bool {
+        self.end_cursor.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(1, None => Ok(PageRequest { first: 1, after: None }))]
+    #[test_case(100, Some("cursor".into()) => Ok(PageRequest { first: 100, after: Some("cursor".into()) }))]
+    #[test_case(0, None => Err(DomainError::new(
+                DomainErrorKind::InvalidInput,
+                "first should be between 1 and 100",
+            )))]
+    #[test_case(101, None => Err(DomainError::new(
+                DomainErrorKind::InvalidInput,
+                "first should be between 1 and 100",
+            )))]
+    fn page_request_try_new_works(
+        first: usize,
+        after: Option<String>,
+    ) -> DomainResult<PageRequest> {
+        PageRequest::try_new(first, after)
+    }
+
+    #[test_case(Page::<i32>::new(vec![1], Some("cursor".into())) => true)]
+    #[test_case(Page::<i32>::new(vec![1], None) => false)]
+    fn page_has_next_page_works(page: Page<i32>) -> bool {
+        page.has_next_page()
+    }
+}
@@ -8,23 +8,10 @@ pub enum PlayerKind {
     Guest,
 }
 
-#[derive(Debug, PartialEq, NamedTupleFrom)]
+#[derive(Debug, PartialEq, ValidatedNewtype)]
+#[validate(name = "name", non_empty)]
 pub struct PlayerName(String);
 
-impl PlayerName {
-    pub fn try_new(name: impl Into<String>) -> DomainResult<Self> {
-        let name = name.into();
-        if name.is_empty() {
-            Err(DomainError::new(
-                DomainErrorKind::InvalidInput,
-                "name should not be blank",
-            ))
-        } else {
-            Ok(Self(name))
-        }
-    }
-}
-
 #[derive(new, Getters)]
 pub struct Player {
     id: Id<Player>,
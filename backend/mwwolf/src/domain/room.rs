@@ -51,7 +51,7 @@ impl WolfCount {
     }
 }
 
-#[derive(Getters, Clone, Debug, PartialEq)]
+#[derive(Getters, Clone, Debug)]
 pub struct Room {
     id: Id<Self>,
     player_count: PlayerCount,
@@ -60,6 +60,20 @@ pub struct Room {
     all_players: Vec<Id<Player>>,
     game_time: GameMinutes,
     theme_kind: ThemeKind,
+    version: Version,
+}
+
+// NOTE: versionは保存されたときの値で部屋の値ではないので、比較に含めない
+impl PartialEq for Room {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.player_count == other.player_count
+            && self.wolf_count == other.wolf_count
+            && self.host_player_id == other.host_player_id
+            && self.all_players == other.all_players
+            && self.game_time == other.game_time
+            && self.theme_kind == other.theme_kind
+    }
 }
 
 impl Room {
@@ -80,11 +94,18 @@ impl Room {
             all_players,
             game_time,
             theme_kind,
+            version: Version::default(),
         };
         room.validate()?;
         Ok(room)
     }
 
+    // NOTE: 保存されていたときのバージョンを付ける。リポジトリが読み込むときに使う
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn join_player(&mut self, player_id: Id<Player>) -> DomainResult<()> {
         let mut new_room = self.clone();
         new_room.all_players.push(player_id);
@@ -151,9 +172,48 @@ impl Room {
     }
 }
 
+#[cfg_attr(test, automock(type Connection = MockConnection;))]
+#[async_trait]
+pub trait RoomRepository {
+    type Connection: database::Connection;
+
+    async fn find<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        id: &Id<Room>,
+    ) -> RepositoryResult<Room>;
+
+    async fn find_all<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        ids: &[Id<Room>],
+    ) -> RepositoryResult<Vec<Room>>;
+
+    // NOTE: 同じIDの部屋が既にあれば、上書きせずにConflictで失敗する
+    async fn create<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        room: &Room,
+    ) -> RepositoryResult<()>;
+
+    // NOTE: 読み込んだ後に他で更新されていれば、上書きせずにConflictで失敗する
+    async fn store<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        room: &Room,
+    ) -> RepositoryResult<()>;
+
+    async fn list<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        page: &PageRequest,
+    ) -> RepositoryResult<Page<Room>>;
+}
+
 pub trait RoomServiceTypeParameters {
+    type Connection: database::Connection;
     type GameFactory: GameFactory;
-    type ThemeRepository: ThemeRepository;
+    type ThemeRepository: ThemeRepository<Connection = Self::Connection>;
     type DateTimeGen: time::DateTimeGen;
     type RngCore: rand::RngCore;
 }
@@ -167,8 +227,16 @@ pub struct RoomService<RST: RoomServiceTypeParameters> {
 }
 
 impl<RST: RoomServiceTypeParameters> RoomService<RST> {
-    pub async fn start_game(&self, room: &Room) -> DomainResult<Game> {
-        match self.theme_repository.find_by_kind(room.theme_kind()).await {
+    pub async fn start_game(
+        &self,
+        executor: &mut database::Executor<'_, RST::Connection>,
+        room: &Room,
+    ) -> DomainResult<Game> {
+        match self
+            .theme_repository
+            .find_by_kind(executor, room.theme_kind())
+            .await
+        {
             Ok(themes) => {
                 let theme = themes
                     .choose(&mut *self.rng_core.borrow_mut())
@@ -231,6 +299,7 @@ mod tests {
     struct MockRoomServiceTypeParameter {}
 
     impl RoomServiceTypeParameters for MockRoomServiceTypeParameter {
+        type Connection = MockConnection;
         type ThemeRepository = MockThemeRepository;
         type GameFactory = MockGameFactory;
         type DateTimeGen = time::MockDateTimeGen;
@@ -403,8 +472,11 @@ mod tests {
         let mut mock_theme_repository = MockThemeRepository::new();
         mock_theme_repository
             .expect_find_by_kind()
-            .with(eq(room.theme_kind.clone()))
-            .returning(move |_| match &return_themes_result {
+            .withf({
+                let kind = room.theme_kind.clone();
+                move |_, k| k == &kind
+            })
+            .returning(move |_, _| match &return_themes_result {
                 Err(e) => Err(RepositoryError::new(e.kind().clone(), e.message())),
                 Ok(ref v) => Ok(v.clone()),
             });
@@ -415,7 +487,10 @@ mod tests {
             mock_date_time_gen,
             RefCell::new(step_rng),
         );
-        room_service.start_game(&room).await
+        let mut conn = MockConnection::new();
+        room_service
+            .start_game(&mut database::Executor::Connection(&mut conn), &room)
+            .await
     }
 
     #[test_case(0 => Err(DomainError::new(DomainErrorKind::InvalidInput, "raw_count should not be zero")))]
@@ -449,6 +524,7 @@ mod tests {
             all_players: vec![],
             game_time: GameMinutes::try_new(3).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         })
     )]
     #[test_case(
@@ -468,6 +544,7 @@ mod tests {
             all_players: vec![],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme2").unwrap(),
+            version: Version::default(),
         })
     )]
     #[test_case(
@@ -510,6 +587,7 @@ mod tests {
             all_players: vec![Id::new("player1"), Id::new("player2"), Id::new("player3")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         } => Ok(()) ; "success"
     )]
     #[test_case(
@@ -521,6 +599,7 @@ mod tests {
             all_players: vec![Id::new("player1"), Id::new("player2"), Id::new("player3")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         } => Err(DomainError::new(
                 DomainErrorKind::InvalidInput,
                 "player_count must be bigger than wolf count",
@@ -535,6 +614,7 @@ mod tests {
             all_players: vec![Id::new("player1"), Id::new("player2"), Id::new("player3"), Id::new("player4")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         } => Err(DomainError::new(
                 DomainErrorKind::InvalidInput,
                 "player count is begger than max player count. current player count is 4, max player count is 3",
@@ -553,6 +633,7 @@ mod tests {
             all_players: vec![Id::new("player1")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         },
         Id::new("player2"),
         &[Id::new("player1"), Id::new("player2")]
@@ -567,6 +648,7 @@ mod tests {
             all_players: vec![Id::new("player1"), Id::new("player2"), Id::new("player3")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         },
         Id::new("player4"),
         &[Id::new("player1"), Id::new("player2"), Id::new("player3")]
@@ -584,6 +666,7 @@ mod tests {
             all_players: vec![Id::new("player1"), Id::new("player2"), Id::new("player3")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         },
         Id::new("player3"),
         &[Id::new("player1"), Id::new("player2"), Id::new("player3")]
@@ -611,6 +694,7 @@ mod tests {
             all_players: vec![Id::new("player1"),Id::new("player2")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         },
         Id::new("player2"),
         &[Id::new("player1")]
@@ -625,6 +709,7 @@ mod tests {
             all_players: vec![Id::new("player1"),Id::new("player2")],
             game_time: GameMinutes::try_new(4).unwrap(),
             theme_kind: ThemeKind::try_new("theme1").unwrap(),
+            version: Version::default(),
         },
         Id::new("player1"),
         &[Id::new("player1"),Id::new("player2")]
@@ -642,4 +727,28 @@ mod tests {
         assert_eq!(expected_all_players, room.all_players());
         result
     }
+
+    #[test]
+    fn room_eq_ignores_version() {
+        let room = Room::try_new(
+            Id::new("room1"),
+            PlayerCount::try_new(3).unwrap(),
+            WolfCount::try_new(1).unwrap(),
+            Id::new("player1"),
+            vec![Id::new("player1")],
+            GameMinutes::try_new(4).unwrap(),
+            ThemeKind::try_new("theme1").unwrap(),
+        )
+        .unwrap();
+        let stored = room.clone().with_version(Version::new(Some(3)));
+        assert_eq!(room, stored);
+        assert_ne!(room.version(), stored.version());
+        assert_ne!(
+            room,
+            Room {
+                id: Id::new("room2"),
+                ..stored
+            }
+        );
+    }
 }
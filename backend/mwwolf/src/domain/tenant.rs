@@ -0,0 +1,76 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, ValidatedNewtype)]
+#[validate(
+    name = "tenant name",
+    trim,
+    non_blank,
+    max_length = 100,
+    datastore = "crate::infrastructure::datastore::value_api"
+)]
+pub struct TenantName(String);
+
+// NOTE: Datastoreの名前空間として使える文字だけを許す。`__`で始まる名前空間は予約されている
+#[derive(Debug, Clone, PartialEq, ValidatedNewtype)]
+#[validate(
+    name = "tenant namespace",
+    regex = "^[0-9A-Za-z.-][0-9A-Za-z._-]{0,99}$",
+    datastore = "crate::infrastructure::datastore::value_api"
+)]
+pub struct TenantNamespace(String);
+
+/// NOTE: コミュニティ(Discordのサーバー・部活・会社など)ごとのデータの区切り。
+/// idはリクエストで指定される識別子で、テーマ・部屋・ゲームはnamespaceに分けて保存する
+#[derive(new, Getters, Clone, PartialEq, Debug)]
+pub struct Tenant {
+    id: Id<Tenant>,
+    name: TenantName,
+    namespace: TenantNamespace,
+}
+
+#[cfg_attr(test, automock(type Connection = MockConnection;))]
+#[async_trait]
+pub trait TenantRepository {
+    type Connection: database::Connection;
+
+    async fn find<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        id: &Id<Tenant>,
+    ) -> RepositoryResult<Tenant>;
+
+    // NOTE: 既に同じidのテナントがあれば、Conflictで失敗する
+    async fn create<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        tenant: &Tenant,
+    ) -> RepositoryResult<()>;
+
+    async fn delete<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        id: &Id<Tenant>,
+    ) -> RepositoryResult<()>;
+
+    async fn list<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        page: &PageRequest,
+    ) -> RepositoryResult<Page<Tenant>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("tenant-0123abcd" => true)]
+    #[test_case("a.b_c-d" => true)]
+    #[test_case("" => false)]
+    #[test_case("__kind__" => false)]
+    #[test_case("discord:1234" => false)]
+    #[test_case(&"a".repeat(101) => false)]
+    fn tenant_namespace_try_new_works(namespace: &str) -> bool {
+        TenantNamespace::try_new(namespace).is_ok()
+    }
+}
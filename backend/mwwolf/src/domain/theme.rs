@@ -1,48 +1,21 @@
 use super::*;
 use rand::prelude::*;
 
-#[derive(Debug, Clone, PartialEq, NamedTupleFrom)]
+#[derive(Debug, Clone, PartialEq, ValidatedNewtype)]
+#[validate(
+    name = "kind",
+    non_empty,
+    datastore = "crate::infrastructure::datastore::value_api"
+)]
 pub struct ThemeKind(String);
 
-impl ThemeKind {
-    pub fn raw_kind(&self) -> &str {
-        &self.0
-    }
-}
-
-impl ThemeKind {
-    pub fn try_new(kind: impl Into<String>) -> DomainResult<Self> {
-        let kind = kind.into();
-        if kind.is_empty() {
-            Err(DomainError::new(
-                DomainErrorKind::InvalidInput,
-                "kind should not be blank",
-            ))
-        } else {
-            Ok(Self(kind))
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, NamedTupleFrom, Clone)]
+#[derive(Debug, PartialEq, Clone, ValidatedNewtype)]
+#[validate(
+    name = "word",
+    non_empty,
+    datastore = "crate::infrastructure::datastore::value_api"
+)]
 pub struct Word(String);
-impl Word {
-    pub fn try_new(word: impl Into<String>) -> DomainResult<Self> {
-        let word = word.into();
-        if word.is_empty() {
-            Err(DomainError::new(
-                DomainErrorKind::InvalidInput,
-                "word should not be blank",
-            ))
-        } else {
-            Ok(Self(word))
-        }
-    }
-
-    pub fn raw(&self) -> &str {
-        &self.0
-    }
-}
 
 #[derive(new, Getters, Clone, PartialEq, Debug)]
 pub struct Theme {
@@ -62,10 +35,28 @@ impl Theme {
     }
 }
 
-#[cfg_attr(test, automock)]
+#[cfg_attr(test, automock(type Connection = MockConnection;))]
 #[async_trait]
 pub trait ThemeRepository {
-    async fn find_by_kind(&self, kind: &ThemeKind) -> RepositoryResult<Vec<Theme>>;
+    type Connection: database::Connection;
+
+    async fn find_by_kind<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        kind: &ThemeKind,
+    ) -> RepositoryResult<Vec<Theme>>;
+
+    async fn count_by_kind<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        kind: &ThemeKind,
+    ) -> RepositoryResult<usize>;
+
+    async fn list<'a>(
+        &self,
+        executor: &mut database::Executor<'a, Self::Connection>,
+        page: &PageRequest,
+    ) -> RepositoryResult<Page<Theme>>;
 }
 
 #[cfg(test)]
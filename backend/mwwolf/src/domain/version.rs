@@ -0,0 +1,26 @@
+// NOTE: 永続化されたときのバージョンで、楽観的排他制御に使う。
+// 集約の値ではないので、集約の比較からは外すこと
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Version(Option<i64>);
+
+impl Version {
+    pub fn new(raw_version: Option<i64>) -> Self {
+        Self(raw_version)
+    }
+
+    pub fn raw_version(&self) -> Option<i64> {
+        self.0
+    }
+}
+
+impl From<Option<i64>> for Version {
+    fn from(raw_version: Option<i64>) -> Self {
+        Self::new(raw_version)
+    }
+}
+
+impl From<Version> for Option<i64> {
+    fn from(version: Version) -> Self {
+        version.0
+    }
+}
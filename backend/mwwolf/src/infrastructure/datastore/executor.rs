@@ -0,0 +1,150 @@
+use super::*;
+
+pub type Executor<'a> = database::Executor<'a, Connection>;
+
+impl Executor<'_> {
+    pub fn namespace(&self) -> &str {
+        match self {
+            database::Executor::Connection(conn) => &conn.namespace,
+            database::Executor::Transaction(tx) => &tx.namespace,
+        }
+    }
+
+    pub async fn get<T: FromEntity>(&mut self, key: Key) -> Result<Option<T>, proto_api::Error> {
+        let result = self.get_all(vec![key]).await?;
+        Ok(result.found.into_iter().next().map(|(_, value)| value))
+    }
+
+    // NOTE: エンティティは読み込んだときのバージョン付きで変換する
+    pub async fn get_all<T: FromEntity>(
+        &mut self,
+        keys: Vec<Key>,
+    ) -> Result<LookupResult<T>, proto_api::Error> {
+        let result = match self {
+            database::Executor::Connection(conn) => conn.lookup(keys).await?,
+            database::Executor::Transaction(tx) => tx.lookup(keys).await?,
+        };
+        let found = result
+            .found
+            .into_iter()
+            .map(|(key, entity)| T::from_entity(entity).map(|value| (key, value)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(LookupResult {
+            found,
+            missing: result.missing,
+        })
+    }
+
+    /// NOTE: Datastoreはトランザクション内でancestorクエリしか許可しない。
+    /// それ以外のクエリをトランザクション外で読んでよい場合は、呼び出し側でConnectionのExecutorを渡すこと
+    pub async fn query<T: FromEntity>(&mut self, query: Query) -> Result<Vec<T>, proto_api::Error> {
+        match self {
+            database::Executor::Connection(conn) => conn.query(query).await,
+            database::Executor::Transaction(tx) => tx.query(ancestor_query(query)?).await,
+        }
+    }
+
+    pub async fn query_page<T: FromEntity>(
+        &mut self,
+        query: Query,
+        cursor: Option<Cursor>,
+    ) -> Result<QueryPage<T>, proto_api::Error> {
+        match self {
+            database::Executor::Connection(conn) => conn.query_page(query, cursor).await,
+            database::Executor::Transaction(tx) => {
+                tx.query_page(ancestor_query(query)?, cursor).await
+            }
+        }
+    }
+
+    pub async fn count(&mut self, query: Query) -> Result<i64, proto_api::Error> {
+        match self {
+            database::Executor::Connection(conn) => conn.count(query).await,
+            database::Executor::Transaction(tx) => tx.count(ancestor_query(query)?).await,
+        }
+    }
+
+    // NOTE: afterはQueryPageのcursorを文字列にしたもの
+    pub async fn page<T: FromEntity>(
+        &mut self,
+        query: Query,
+        page: &domain::PageRequest,
+    ) -> Result<domain::Page<T>, proto_api::Error> {
+        let cursor = page
+            .after()
+            .as_deref()
+            .map(str::parse::<Cursor>)
+            .transpose()?;
+        let result = self
+            .query_page(query.limit(*page.first() as i32), cursor)
+            .await?;
+        Ok(domain::Page::new(
+            result.items,
+            result.cursor.map(|cursor| cursor.to_string()),
+        ))
+    }
+
+    pub async fn put(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.put_all(Some(entity)).await
+    }
+
+    pub async fn put_all<T, I>(&mut self, entities: I) -> Result<(), proto_api::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        let namespace = self.namespace().to_string();
+        let entities = entities
+            .into_iter()
+            .map(|e| entity::into_entity(e, &namespace))
+            .collect::<Result<Vec<_>, _>>()?;
+        match self {
+            database::Executor::Connection(conn) => conn.put_all(entities).await.map(|_| ()),
+            database::Executor::Transaction(tx) => tx.put_all(entities).await,
+        }
+    }
+
+    pub async fn insert(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::insert(entity)?).await
+    }
+
+    pub async fn update(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::update(entity)?).await
+    }
+
+    pub async fn upsert(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::upsert(entity)?).await
+    }
+
+    pub async fn mutate(&mut self, mutation: Mutation) -> Result<(), proto_api::Error> {
+        match self {
+            database::Executor::Connection(conn) => conn.mutate(mutation).await.map(|_| ()),
+            database::Executor::Transaction(tx) => {
+                tx.mutate(mutation);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn delete(&mut self, key: Key) -> Result<(), proto_api::Error> {
+        let key = key.namespace(self.namespace());
+        match self {
+            database::Executor::Connection(conn) => conn.delete(key).await,
+            database::Executor::Transaction(tx) => {
+                tx.delete(key);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn ancestor_query(query: Query) -> Result<Query, proto_api::Error> {
+    if query.ancestor.is_some() {
+        Ok(query)
+    } else {
+        Err(proto_api::Error::InvalidQuery(format!(
+            "query on {} without ancestor cannot run in a transaction",
+            query.kind
+        )))
+    }
+}
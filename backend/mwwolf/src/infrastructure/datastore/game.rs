@@ -0,0 +1,209 @@
+use super::*;
+
+#[derive(new)]
+pub struct GameRepository;
+
+// NOTE: 保存するプロパティの並び。変換はドメインの型に実装し、値はゲッターとコンストラクタで受け渡す
+#[derive(Entity)]
+#[entity(remote = "domain::Game", constructor = "try_new")]
+struct GameRecord {
+    #[entity(key)]
+    id: domain::Id<domain::Game>,
+    room_id: domain::Id<domain::Room>,
+    theme_id: domain::Id<domain::Theme>,
+    ended_at: chrono::DateTime<chrono_tz::Tz>,
+    #[entity(exclude_from_indexes)]
+    wolves: domain::WolfGroup,
+    #[entity(exclude_from_indexes)]
+    citizen: domain::CitizenGroup,
+    #[entity(exclude_from_indexes)]
+    vote_box: domain::VoteBox,
+    status: domain::GameStatus,
+}
+
+#[derive(Entity)]
+#[entity(remote = "domain::WolfGroup")]
+struct WolfGroupRecord {
+    players: Vec<domain::Id<domain::Player>>,
+    word: domain::Word,
+}
+
+#[derive(Entity)]
+#[entity(remote = "domain::CitizenGroup")]
+struct CitizenGroupRecord {
+    players: Vec<domain::Id<domain::Player>>,
+    word: domain::Word,
+}
+
+#[derive(Entity)]
+#[entity(remote = "domain::VoteBox")]
+struct VoteBoxRecord {
+    votes: Vec<domain::Vote>,
+}
+
+#[derive(Entity)]
+#[entity(remote = "domain::Vote")]
+struct VoteRecord {
+    target: domain::Id<domain::Player>,
+    voter: domain::Id<domain::Player>,
+}
+
+#[async_trait]
+impl domain::GameRepository for GameRepository {
+    type Connection = Connection;
+
+    #[tracing::instrument(name = "GameRepository::find", skip(self, executor, id), fields(id = %id), err)]
+    async fn find<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        id: &domain::Id<domain::Game>,
+    ) -> domain::RepositoryResult<domain::Game> {
+        executor
+            .get(Key::from(id.clone()))
+            .await
+            .map_err(|e| {
+                domain::RepositoryError::new_with_source(
+                    domain::RepositoryErrorKind::Fail,
+                    format!("failed to get game: {}", id),
+                    e.into(),
+                )
+            })?
+            .ok_or_else(|| {
+                domain::RepositoryError::new(
+                    domain::RepositoryErrorKind::NotFound,
+                    format!("game is not found: {}", id),
+                )
+            })
+    }
+
+    #[tracing::instrument(name = "GameRepository::store", skip(self, executor, game), fields(id = %game.id()), err)]
+    async fn store<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        game: &domain::Game,
+    ) -> domain::RepositoryResult<()> {
+        executor.put(game.clone()).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                format!("failed to store game: {}", game.id()),
+                e.into(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use database::ConnectionFactory as _;
+    use domain::GameRepository as _;
+    use proto_api::FromEntity;
+    use test_case::test_case;
+
+    fn new_game(id: &str, votes: Vec<domain::Vote>, status: domain::GameStatus) -> domain::Game {
+        domain::Game::try_new(
+            domain::Id::new(id),
+            domain::Id::new("room1"),
+            domain::Id::new("theme1"),
+            chrono_tz::Japan.ymd(2021, 8, 11).and_hms(12, 30, 15),
+            domain::WolfGroup::new(
+                vec![domain::Id::new("player1")],
+                domain::Word::try_new("foo").unwrap(),
+            ),
+            domain::CitizenGroup::new(
+                vec![domain::Id::new("player2"), domain::Id::new("player3")],
+                domain::Word::try_new("bar").unwrap(),
+            ),
+            domain::VoteBox::new(votes),
+            status,
+        )
+        .unwrap()
+    }
+
+    #[test_case(new_game("1", vec![], domain::GameStatus::Talking))]
+    #[test_case(new_game(
+        "2",
+        vec![
+            domain::Vote::new(domain::Id::new("player1"), domain::Id::new("player2")),
+            domain::Vote::new(domain::Id::new("player2"), domain::Id::new("player1")),
+        ],
+        domain::GameStatus::Voting,
+    ))]
+    #[test_case(new_game("3", vec![], domain::GameStatus::Ended))]
+    fn domain_game_entity_round_trip_works(game: domain::Game) {
+        let entity = proto_api::IntoEntity::into_entity(game.clone()).unwrap();
+        assert_eq!(domain::Game::from_entity(entity), Ok(game));
+    }
+
+    #[test]
+    fn domain_game_from_entity_treats_missing_votes_as_empty() {
+        let game = new_game("1", vec![], domain::GameStatus::Talking);
+        let mut entity = proto_api::IntoEntity::into_entity(game.clone()).unwrap();
+        if let proto_api::Value::Entity(properties) = entity.properties_mut() {
+            properties.insert(
+                GameFields::VOTE_BOX.into(),
+                proto_api::Value::Entity(Default::default()),
+            );
+        }
+        assert_eq!(domain::Game::from_entity(entity), Ok(game));
+    }
+
+    #[test]
+    fn domain_game_ended_at_is_stored_in_micros_and_read_in_japan_time() {
+        let ended_at = chrono_tz::Japan
+            .ymd(2021, 8, 11)
+            .and_hms_nano(12, 30, 15, 123_456_789);
+        let game = domain::Game::try_new(
+            domain::Id::new("1"),
+            domain::Id::new("room1"),
+            domain::Id::new("theme1"),
+            ended_at,
+            domain::WolfGroup::new(
+                vec![domain::Id::new("player1")],
+                domain::Word::try_new("foo").unwrap(),
+            ),
+            domain::CitizenGroup::new(
+                vec![domain::Id::new("player2"), domain::Id::new("player3")],
+                domain::Word::try_new("bar").unwrap(),
+            ),
+            domain::VoteBox::new(vec![]),
+            domain::GameStatus::Talking,
+        )
+        .unwrap();
+        let entity = proto_api::IntoEntity::into_entity(game).unwrap();
+        let properties: std::collections::HashMap<String, proto_api::Value> =
+            proto_api::FromValue::from_value(entity.properties().clone()).unwrap();
+        assert_eq!(
+            properties[GameFields::ENDED_AT],
+            proto_api::Value::Timestamp(
+                chrono::Utc
+                    .ymd(2021, 8, 11)
+                    .and_hms_micro(3, 30, 15, 123_456)
+            )
+        );
+
+        let game = domain::Game::from_entity(entity).unwrap();
+        assert_eq!(
+            game.ended_at().to_rfc3339(),
+            "2021-08-11T12:30:15.123456+09:00"
+        );
+    }
+
+    #[async_std::test]
+    async fn game_repository_store_and_find_works() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let game = new_game("1234", vec![], domain::GameStatus::Talking);
+        let game_repository = GameRepository::new();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut executor = database::Executor::Connection(&mut conn);
+
+        game_repository.store(&mut executor, &game).await.unwrap();
+        assert_eq!(
+            game_repository.find(&mut executor, game.id()).await,
+            Ok(game)
+        );
+    }
+}
@@ -1,37 +1,117 @@
 use super::*;
-// pub async fn allocate_ids<T>(
-//     connection: &Connection,
-//     keys: &[Key],
-// ) -> domain::DomainResult<Vec<domain::Id<T>>> {
-//     connection
-//         .allocate_ids(keys)
-//         .await
-//         .map_err(|e| {
-//             domain::DomainError::new_with_source(
-//                 domain::DomainErrorKind::Fail,
-//                 "failed allocated ids",
-//                 e.into(),
-//             )
-//         })
-//         .map(|keys| {
-//             keys.into_iter()
-//                 .map(|key| key_to_id(Key::from(key)))
-//                 .collect::<Vec<_>>()
-//         })
-// }
-//
-
-impl<T> From<Key> for domain::Id<T> {
-    fn from(key: Key) -> Self {
+use database::ConnectionFactory as _;
+use std::convert::TryFrom;
+
+#[derive(new)]
+pub struct DatastoreIdGenerator {
+    connection_factory: Arc<ConnectionFactory>,
+}
+
+#[async_trait]
+impl<T> domain::IdGenerator<T> for DatastoreIdGenerator {
+    async fn generate(&self) -> anyhow::Result<domain::Id<T>> {
+        let mut conn = self.connection_factory.create().await?;
+        let key = Key::new(entity::kind::<T>()).namespace(&conn.namespace);
+        let allocated = conn
+            .allocate_ids(&[key])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("no id allocated for {}", entity::kind::<T>()))?;
+        match Key::from(allocated).get_id() {
+            proto_api::KeyID::IntID(id) => Ok(domain::Id::from_integer(*id)),
+            _ => Err(anyhow!(
+                "unexpected id allocated for {}",
+                entity::kind::<T>()
+            )),
+        }
+    }
+}
+
+// NOTE: IntIDのキーから読んだIDは整数を持っているので、同じIntIDのキーに戻す
+impl<T> TryFrom<Key> for domain::Id<T> {
+    type Error = proto_api::ConvertError;
+
+    fn try_from(key: Key) -> Result<Self, Self::Error> {
         match key.get_id() {
-            proto_api::KeyID::IntID(id) => domain::Id::new(id.to_string()),
-            id => panic!("unexpected id:{:?}", id),
+            proto_api::KeyID::IntID(id) => Ok(domain::Id::from_integer(*id)),
+            proto_api::KeyID::StringID(id) => Ok(domain::Id::new(id.clone())),
+            proto_api::KeyID::Incomplete => Err(proto_api::ConvertError::InvalidValue(format!(
+                "incomplete key of {}",
+                key.get_kind()
+            ))),
         }
     }
 }
 
 impl<T> From<domain::Id<T>> for Key {
     fn from(id: domain::Id<T>) -> Self {
-        Key::new(entity::kind::<T>()).id(proto_api::KeyID::IntID(id.raw_id().parse().unwrap()))
+        let key_id = match id.integer_id() {
+            Some(int_id) => proto_api::KeyID::IntID(int_id),
+            None => proto_api::KeyID::StringID(id.raw_id().clone()),
+        };
+        Key::new(entity::kind::<T>()).id(key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::IdGenerator as _;
+    use test_case::test_case;
+
+    #[test_case("1234" => proto_api::KeyID::StringID("1234".into()))]
+    #[test_case("abc" => proto_api::KeyID::StringID("abc".into()))]
+    #[test_case("0123" => proto_api::KeyID::StringID("0123".into()))]
+    #[test_case("0" => proto_api::KeyID::StringID("0".into()))]
+    #[test_case("-1" => proto_api::KeyID::StringID("-1".into()))]
+    #[test_case("01FDZ8Q2M2ZV1N1N1X2Y3Z4A5B" => proto_api::KeyID::StringID("01FDZ8Q2M2ZV1N1N1X2Y3Z4A5B".into()))]
+    fn key_from_id_works(raw_id: &str) -> proto_api::KeyID {
+        let key = Key::from(domain::Id::<domain::Room>::new(raw_id));
+        assert_eq!(key.get_kind(), "Room");
+        key.get_id().clone()
+    }
+
+    #[test_case(Key::new("Room").id(1234) => Ok(domain::Id::from_integer(1234)))]
+    #[test_case(Key::new("Room").id("abc") => Ok(domain::Id::new("abc")))]
+    #[test_case(
+        Key::new("Room")
+        => Err(proto_api::ConvertError::InvalidValue("incomplete key of Room".into()))
+    )]
+    fn id_try_from_key_works(
+        key: Key,
+    ) -> Result<domain::Id<domain::Room>, proto_api::ConvertError> {
+        domain::Id::try_from(key)
+    }
+
+    #[test_case(Key::new("Room").id(1234))]
+    #[test_case(Key::new("Room").id("1234"))]
+    #[test_case(Key::new("Room").id("abc"))]
+    fn key_round_trip_through_id_works(key: Key) {
+        let id: domain::Id<domain::Room> = domain::Id::try_from(key.clone()).unwrap();
+        assert_eq!(Key::from(id), key);
+    }
+
+    #[test_case(domain::Id::from_integer(1234) => proto_api::Value::Integer(1234))]
+    #[test_case(domain::Id::new("1234") => proto_api::Value::Strings("1234".into()))]
+    fn id_value_round_trip_works(id: domain::Id<domain::Room>) -> proto_api::Value {
+        let value = proto_api::IntoValue::into_value(id.clone());
+        let read: domain::Id<domain::Room> = FromValue::from_value(value.clone()).unwrap();
+        assert_eq!(read.integer_id(), id.integer_id());
+        value
+    }
+
+    #[async_std::test]
+    async fn datastore_id_generator_works() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let generator = DatastoreIdGenerator::new(datastore.as_ref().clone());
+        let first: domain::Id<domain::Game> = generator.generate().await.unwrap();
+        let second: domain::Id<domain::Game> = generator.generate().await.unwrap();
+        assert_eq!(
+            Key::from(first.clone()).get_id(),
+            &proto_api::KeyID::IntID(first.raw_id().parse().unwrap())
+        );
+        assert_ne!(first, second);
     }
 }
@@ -0,0 +1,454 @@
+use super::*;
+use chrono::{DateTime, Utc};
+use database::{Connection as _, ConnectionFactory as _, Transaction as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const MIGRATION_KIND: &str = "SchemaMigration";
+
+/// NOTE: 新しい移行はここに追加する。一度適用した移行は消したり書き換えたりしない
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![]
+}
+
+/// NOTE: エンティティの形を変える移行。versionの順に、名前空間ごとに一度だけ適用する
+#[async_trait]
+pub trait Migration: Send + Sync {
+    // NOTE: 適用順と記録のキーに使う。作成日時(例: 20211001120000)を使う
+    fn version(&self) -> i64;
+
+    fn name(&self) -> &str;
+
+    // NOTE: 移行するエンティティの種類。バッチごとにキーの順で読み進める
+    fn kind(&self) -> &str;
+
+    // NOTE: バッチのトランザクションの中で1件ずつ呼ばれる。変更が無ければNoneを返す。
+    // txに追加したミューテーションも、バッチと一緒にコミットされる
+    async fn migrate(&self, tx: &mut Transaction, entity: Entity)
+        -> anyhow::Result<Option<Entity>>;
+}
+
+/// NOTE: 適用済みの移行の記録。途中で止まった場合は、次のバッチのカーソルを持つ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub version: i64,
+    pub name: String,
+    pub cursor: Option<String>,
+    pub migrated: i64,
+    pub completed: bool,
+    #[serde(with = "timestamp")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl MigrationRecord {
+    fn key(version: i64) -> Key {
+        Key::new(MIGRATION_KIND).id(version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub record: Option<MigrationRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub version: i64,
+    pub name: String,
+    // NOTE: dry-runでは、変更されるはずだったエンティティの数
+    pub migrated: i64,
+    pub completed: bool,
+}
+
+pub struct Migrator {
+    migrations: Vec<Box<dyn Migration>>,
+    batch_size: usize,
+    dry_run: bool,
+    max_batches: Option<usize>,
+}
+
+impl Migrator {
+    pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+    pub fn new(mut migrations: Vec<Box<dyn Migration>>) -> Migrator {
+        migrations.sort_by_key(|migration| migration.version());
+        Migrator {
+            migrations,
+            batch_size: Migrator::DEFAULT_BATCH_SIZE,
+            dry_run: false,
+            max_batches: None,
+        }
+    }
+
+    // NOTE: 1バッチを1トランザクションでコミットするので、記録の分を除いた上限を超えないようにする
+    pub fn batch_size(mut self, batch_size: usize) -> Migrator {
+        self.batch_size = batch_size.clamp(1, Client::MAX_MUTATIONS - 1);
+        self
+    }
+
+    // NOTE: 変更をコミットせずに、移行される件数だけを数える。
+    // 前の移行の変更も適用されないので、後の移行の件数は実際と異なることがある
+    pub fn dry_run(mut self, dry_run: bool) -> Migrator {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // NOTE: 指定したバッチ数で止める。次に実行したときは、記録したカーソルから再開する
+    pub fn max_batches(mut self, max_batches: Option<usize>) -> Migrator {
+        self.max_batches = max_batches;
+        self
+    }
+
+    pub async fn status(&self, conn: &mut Connection) -> anyhow::Result<Vec<MigrationStatus>> {
+        self.validate()?;
+        let mut records = self.records(conn).await?;
+        Ok(self
+            .migrations
+            .iter()
+            .map(|migration| MigrationStatus {
+                version: migration.version(),
+                name: String::from(migration.name()),
+                record: records.remove(&migration.version()),
+            })
+            .collect())
+    }
+
+    pub async fn run(&self, conn: &mut Connection) -> anyhow::Result<Vec<MigrationReport>> {
+        self.validate()?;
+        let mut records = self.records(conn).await?;
+        let mut reports = vec![];
+        let mut batches = 0;
+        for migration in self.migrations.iter() {
+            let mut record = match records.remove(&migration.version()) {
+                Some(record) if record.completed => continue,
+                Some(record) => record,
+                None => MigrationRecord {
+                    version: migration.version(),
+                    name: String::from(migration.name()),
+                    cursor: None,
+                    migrated: 0,
+                    completed: false,
+                    updated_at: Utc::now(),
+                },
+            };
+            let migrated_before = record.migrated;
+            while !record.completed {
+                if matches!(self.max_batches, Some(max) if batches >= max) {
+                    break;
+                }
+                self.run_batch(conn, migration.as_ref(), &mut record)
+                    .await?;
+                batches += 1;
+            }
+            tracing::info!(
+                version = migration.version(),
+                name = migration.name(),
+                migrated = record.migrated - migrated_before,
+                completed = record.completed,
+                dry_run = self.dry_run,
+                "datastore migration"
+            );
+            reports.push(MigrationReport {
+                version: migration.version(),
+                name: String::from(migration.name()),
+                migrated: record.migrated - migrated_before,
+                completed: record.completed,
+            });
+            if !record.completed {
+                break;
+            }
+        }
+        Ok(reports)
+    }
+
+    // NOTE: 移行したエンティティと記録を同じトランザクションでコミットするので、
+    // 途中で失敗してもバッチの単位で再開できる
+    async fn run_batch(
+        &self,
+        conn: &mut Connection,
+        migration: &dyn Migration,
+        record: &mut MigrationRecord,
+    ) -> anyhow::Result<()> {
+        let query = Query::new(migration.kind())
+            .keys_only()
+            .limit(self.batch_size as i32);
+        let cursor = record
+            .cursor
+            .as_deref()
+            .map(str::parse::<Cursor>)
+            .transpose()?;
+        let page = conn.query_page::<Entity>(query, cursor).await?;
+        let keys = page
+            .items
+            .into_iter()
+            .map(Entity::into_key)
+            .collect::<Vec<_>>();
+
+        let mut tx = conn.begin().await?;
+        let result = self
+            .migrate_entities(&mut tx, migration, keys, record)
+            .await;
+        let migrated = match result {
+            Ok(migrated) => migrated,
+            Err(e) => {
+                tx.rollback().await?;
+                return Err(e);
+            }
+        };
+        let next = MigrationRecord {
+            completed: page.cursor.is_none(),
+            cursor: page.cursor.map(|cursor| cursor.to_string()),
+            migrated: record.migrated + migrated,
+            updated_at: Utc::now(),
+            ..record.clone()
+        };
+        if self.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.upsert(SerdeEntity {
+                key: MigrationRecord::key(next.version),
+                value: next.clone(),
+            })?;
+            tx.commit().await?;
+        }
+        *record = next;
+        Ok(())
+    }
+
+    async fn migrate_entities(
+        &self,
+        tx: &mut Transaction,
+        migration: &dyn Migration,
+        keys: Vec<Key>,
+        record: &MigrationRecord,
+    ) -> anyhow::Result<i64> {
+        let mut migrated = 0;
+        for (_, entity) in tx.lookup(keys).await?.found {
+            let migrated_entity = migration.migrate(tx, entity).await.map_err(|e| {
+                anyhow!(
+                    "migration {} ({}) failed: {}",
+                    record.version,
+                    record.name,
+                    e
+                )
+            })?;
+            if let Some(entity) = migrated_entity {
+                tx.upsert(entity)?;
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    async fn records(
+        &self,
+        conn: &mut Connection,
+    ) -> anyhow::Result<HashMap<i64, MigrationRecord>> {
+        let records: Vec<SerdeEntity<MigrationRecord>> =
+            conn.query(Query::new(MIGRATION_KIND)).await?;
+        Ok(records
+            .into_iter()
+            .map(|record| (record.value.version, record.value))
+            .collect())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut versions = HashSet::new();
+        for migration in self.migrations.iter() {
+            if !versions.insert(migration.version()) {
+                return Err(anyhow!(
+                    "migration version {} is duplicated: {}",
+                    migration.version(),
+                    migration.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn migration_status(
+    project_id: &str,
+    namespace: &str,
+    migrator: &Migrator,
+) -> anyhow::Result<Vec<MigrationStatus>> {
+    let mut conn = ConnectionFactory::new(project_id.into(), namespace.into())
+        .create()
+        .await?;
+    migrator.status(&mut conn).await
+}
+
+pub async fn migrate_namespace(
+    project_id: &str,
+    namespace: &str,
+    migrator: &Migrator,
+) -> anyhow::Result<Vec<MigrationReport>> {
+    let mut conn = ConnectionFactory::new(project_id.into(), namespace.into())
+        .create()
+        .await?;
+    migrator.run(&mut conn).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmww;
+    use proto_api::Value;
+
+    struct AddRating;
+
+    #[async_trait]
+    impl Migration for AddRating {
+        fn version(&self) -> i64 {
+            20211001000000
+        }
+
+        fn name(&self) -> &str {
+            "add_rating"
+        }
+
+        fn kind(&self) -> &str {
+            "Item"
+        }
+
+        async fn migrate(
+            &self,
+            _tx: &mut Transaction,
+            mut entity: Entity,
+        ) -> anyhow::Result<Option<Entity>> {
+            match entity.properties_mut() {
+                Value::Entity(properties) if !properties.contains_key("rating") => {
+                    properties.insert(String::from("rating"), Value::Integer(0));
+                    Ok(Some(entity))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl Migration for Failing {
+        fn version(&self) -> i64 {
+            20211002000000
+        }
+
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn kind(&self) -> &str {
+            "Item"
+        }
+
+        async fn migrate(&self, _: &mut Transaction, _: Entity) -> anyhow::Result<Option<Entity>> {
+            Err(anyhow!("broken"))
+        }
+    }
+
+    fn item(id: i64) -> Entity {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("name"), Value::Strings(format!("item{}", id)));
+        if id % 5 == 0 {
+            properties.insert(String::from("rating"), Value::Integer(3));
+        }
+        Entity::new(Key::new("Item").id(id), Value::Entity(properties)).unwrap()
+    }
+
+    async fn ratings(conn: &mut Connection) -> Vec<Option<Value>> {
+        let items: Vec<Entity> = conn.query(Query::new("Item")).await.unwrap();
+        items
+            .into_iter()
+            .map(|item| match item.into_properties() {
+                Value::Entity(mut properties) => properties.remove("rating"),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn migrator_applies_migrations_in_batches_once() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        conn.put_all((1..=5).map(item)).await.unwrap();
+        let migrator = Migrator::new(vec![Box::new(AddRating)]).batch_size(2);
+
+        let reports = migrator.run(&mut conn).await.unwrap();
+        assert_eq!(
+            reports,
+            vec![MigrationReport {
+                version: 20211001000000,
+                name: String::from("add_rating"),
+                migrated: 4,
+                completed: true,
+            }]
+        );
+        let mut expected = vec![Some(Value::Integer(0)); 4];
+        expected.push(Some(Value::Integer(3)));
+        assert_eq!(ratings(&mut conn).await, expected);
+
+        assert_eq!(migrator.run(&mut conn).await.unwrap(), vec![]);
+        let status = migrator.status(&mut conn).await.unwrap();
+        assert_eq!(status.len(), 1);
+        let record = status[0].record.clone().unwrap();
+        assert_eq!((record.migrated, record.completed), (4, true));
+    }
+
+    #[async_std::test]
+    async fn migrator_dry_run_does_not_commit() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        conn.put_all((1..=5).map(item)).await.unwrap();
+        let migrator = Migrator::new(vec![Box::new(AddRating)])
+            .batch_size(2)
+            .dry_run(true);
+
+        let reports = migrator.run(&mut conn).await.unwrap();
+        assert_eq!((reports[0].migrated, reports[0].completed), (4, true));
+        assert_eq!(ratings(&mut conn).await.into_iter().flatten().count(), 1);
+        assert_eq!(migrator.status(&mut conn).await.unwrap()[0].record, None);
+    }
+
+    #[async_std::test]
+    async fn migrator_resumes_from_recorded_cursor() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        conn.put_all((1..=5).map(item)).await.unwrap();
+        let migrator = Migrator::new(vec![Box::new(AddRating), Box::new(Failing)])
+            .batch_size(2)
+            .max_batches(Some(1));
+
+        let reports = migrator.run(&mut conn).await.unwrap();
+        assert_eq!((reports[0].migrated, reports[0].completed), (2, false));
+        assert_eq!(reports.len(), 1);
+        let record = migrator.status(&mut conn).await.unwrap()[0]
+            .record
+            .clone()
+            .unwrap();
+        assert!(record.cursor.is_some());
+
+        let migrator = Migrator::new(vec![Box::new(AddRating), Box::new(Failing)]).batch_size(2);
+        let err = migrator.run(&mut conn).await.unwrap_err();
+        assert!(err.to_string().contains("failing"));
+        let status = migrator.status(&mut conn).await.unwrap();
+        let record = status[0].record.clone().unwrap();
+        assert_eq!((record.migrated, record.completed), (4, true));
+        assert_eq!(status[1].record, None);
+        assert_eq!(ratings(&mut conn).await.into_iter().flatten().count(), 5);
+    }
+
+    #[test]
+    fn migrator_rejects_duplicated_versions() {
+        let migrator = Migrator::new(vec![Box::new(AddRating), Box::new(AddRating)]);
+        assert!(migrator.validate().is_err());
+    }
+}
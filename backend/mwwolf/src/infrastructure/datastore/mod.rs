@@ -1,19 +1,50 @@
 use anyhow::anyhow;
-// use libmww::database::Executor;
+use futures_util::future;
+use futures_util::stream::{Stream, TryStreamExt};
+use once_cell::sync::OnceCell;
 use std::borrow::Borrow;
 
 use self::proto_api::FromEntity;
 
 use super::*;
 
-mod convert_error;
 mod entity;
+mod executor;
+mod game;
+mod migration;
+mod namespace;
+#[cfg(not(test))]
 mod proto_api;
+// NOTE: テスト用の偽サーバーは、gRPCの型を直接使う
+#[cfg(test)]
+pub(crate) mod proto_api;
+mod room;
+mod snapshot;
+mod tenant;
 mod theme;
+mod value;
 
-use convert_error::*;
-use proto_api::{api, Client, FromValue, IntoEntity, Key, Query};
+pub use executor::*;
+pub use game::*;
+pub use id::*;
+pub use migration::*;
+pub use namespace::*;
+use proto_api::{
+    api, Client, Cursor, Entity, FromValue, IntoEntity, Key, LookupResult, Mutation,
+    MutationResult, Query, QueryPage, Retry, Rpc,
+};
+// NOTE: 新しい集約はserdeで変換できるよう、serdeとの橋渡しを公開する
+pub use proto_api::{
+    from_entity, from_value, timestamp, to_entity, to_value, unindexed, SerdeEntity,
+};
+pub use room::*;
+pub use snapshot::*;
+pub use tenant::*;
 pub use theme::*;
+// NOTE: ドメインの値オブジェクトが`#[validate(datastore = "...")]`で値の変換を生成するときに使う
+pub(crate) mod value_api {
+    pub(crate) use super::proto_api::{ConvertError, FromValue, IntoValue, Value};
+}
 
 mod id;
 
@@ -22,8 +53,9 @@ pub struct Transaction {
     project_id: String,
     namespace: String,
     transaction: prost::alloc::vec::Vec<u8>,
-    client: Arc<Mutex<Client>>,
+    client: Client,
     mutations: prost::alloc::vec::Vec<api::Mutation>,
+    read_only: bool,
 }
 
 impl Transaction {
@@ -31,7 +63,8 @@ impl Transaction {
         project_id: String,
         namespace: String,
         transaction: prost::alloc::vec::Vec<u8>,
-        client: Arc<Mutex<Client>>,
+        client: Client,
+        read_only: bool,
     ) -> Result<Transaction, database::DatabaseError> {
         Ok(Transaction {
             project_id,
@@ -39,6 +72,7 @@ impl Transaction {
             transaction,
             client,
             mutations: vec![],
+            read_only,
         })
     }
 
@@ -46,23 +80,70 @@ impl Transaction {
         &mut self,
         key: impl Borrow<Key>,
     ) -> Result<Option<T>, proto_api::Error> {
+        let key = self.namespaced_key(key);
+        self.client.get(key, Some(self.transaction.clone())).await
+    }
+
+    pub async fn get_all<T, K, I>(&mut self, keys: I) -> Result<LookupResult<T>, proto_api::Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+        T: FromValue,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.namespaced_key(key))
+            .collect::<Vec<_>>();
         self.client
-            .lock()
-            .await
-            .get(key, Some(self.transaction.clone()))
+            .get_all(keys, Some(self.transaction.clone()))
             .await
     }
 
-    pub async fn get_all<T, K, I>(&mut self, keys: I) -> Result<Vec<T>, proto_api::Error>
+    pub async fn lookup<K, I>(&mut self, keys: I) -> Result<LookupResult<Entity>, proto_api::Error>
     where
         I: IntoIterator<Item = K>,
         K: Borrow<Key>,
-        T: FromValue,
     {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.namespaced_key(key))
+            .collect::<Vec<_>>();
         self.client
-            .lock()
+            .lookup(keys, Some(self.transaction.clone()))
             .await
-            .get_all(keys, Some(self.transaction.clone()))
+    }
+
+    /// NOTE: トランザクション内ではancestorを指定したクエリしか実行できない
+    pub async fn query<T: FromEntity>(&mut self, query: Query) -> Result<Vec<T>, proto_api::Error> {
+        let query = query.namespace(&self.namespace);
+        let entities = self
+            .client
+            .query(query, Some(self.transaction.clone()))
+            .await?;
+        entities
+            .into_iter()
+            .map(T::from_entity)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(proto_api::Error::Convert)
+    }
+
+    pub async fn query_page<T: FromEntity>(
+        &mut self,
+        query: Query,
+        cursor: Option<Cursor>,
+    ) -> Result<QueryPage<T>, proto_api::Error> {
+        let query = query.namespace(&self.namespace);
+        let page = self
+            .client
+            .query_page(query, cursor, Some(self.transaction.clone()))
+            .await?;
+        page.convert().map_err(proto_api::Error::Convert)
+    }
+
+    pub async fn count(&mut self, query: Query) -> Result<i64, proto_api::Error> {
+        let query = query.namespace(&self.namespace);
+        self.client
+            .count(query, Some(self.transaction.clone()))
             .await
     }
 
@@ -75,15 +156,80 @@ impl Transaction {
         I: IntoIterator<Item = T>,
         T: IntoEntity,
     {
+        let entities = entities
+            .into_iter()
+            .map(|e| entity::into_entity(e, &self.namespace))
+            .collect::<Result<Vec<_>, _>>()?;
         let mutations = proto_api::generate_mutations(&self.project_id, entities)?;
         self.mutations.extend(mutations);
         Ok(())
     }
+
+    pub fn insert(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::insert(entity)?);
+        Ok(())
+    }
+
+    pub fn update(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::update(entity)?);
+        Ok(())
+    }
+
+    pub fn upsert(&mut self, entity: impl IntoEntity) -> Result<(), proto_api::Error> {
+        self.mutate(Mutation::upsert(entity)?);
+        Ok(())
+    }
+
+    // NOTE: ミューテーションはコミットするまで送らないので、失敗はコミットのエラーになる
+    pub fn mutate(&mut self, mutation: Mutation) {
+        self.mutate_all(Some(mutation))
+    }
+
+    pub fn mutate_all(&mut self, mutations: impl IntoIterator<Item = Mutation>) {
+        let mutations = mutations.into_iter().map(|mutation| {
+            proto_api::convert_mutation(&self.project_id, mutation.namespace(&self.namespace))
+        });
+        self.mutations.extend(mutations.collect::<Vec<_>>());
+    }
+
+    pub fn delete(&mut self, key: impl Borrow<Key>) {
+        self.delete_all(Some(key))
+    }
+
+    pub fn delete_all<K, I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.namespaced_key(key))
+            .collect::<Vec<_>>();
+        let mutations = proto_api::generate_delete_mutations(&self.project_id, keys);
+        self.mutations.extend(mutations);
+    }
+
+    fn namespaced_key(&self, key: impl Borrow<Key>) -> Key {
+        key.borrow().clone().namespace(&self.namespace)
+    }
 }
 
 #[async_trait]
 impl database::Transaction for Transaction {
     async fn commit(mut self) -> Result<(), database::DatabaseError> {
+        if self.read_only && !self.mutations.is_empty() {
+            return Err(database::DatabaseError::TransactionCommit(anyhow!(
+                "read-only transaction cannot commit mutations"
+            )));
+        }
+        // NOTE: トランザクションは1回のコミットで適用しなければならないので、上限を超えたら分割せずに失敗する
+        if self.mutations.len() > Client::MAX_MUTATIONS {
+            return Err(database::DatabaseError::TransactionCommit(anyhow!(
+                "transaction has {} mutations, but at most {} mutations can be committed at once",
+                self.mutations.len(),
+                Client::MAX_MUTATIONS
+            )));
+        }
         let commit_request = api::CommitRequest {
             project_id: self.project_id,
             mode: api::commit_request::Mode::Transactional.into(),
@@ -92,22 +238,23 @@ impl database::Transaction for Transaction {
                 self.transaction,
             )),
         };
-        let response = self
-            .client
-            .lock()
-            .await
-            .service
-            .commit(commit_request)
+        // NOTE: コミットは送り直すと二重に適用されうるので、ここでは再試行しない。
+        // トランザクションごとやり直すのはrun_in_transaction_with_retryに任せる
+        let rpc = Rpc::new("commit")
+            .mutations(&commit_request.mutations)
+            .namespace(&self.namespace);
+        self.client
+            .call(
+                rpc,
+                Retry::Never,
+                commit_request,
+                |mut service, request| async move { service.commit(request).await },
+            )
             .await
-            .map_err(|e| database::DatabaseError::TransactionCommit(anyhow!("{}", e)))?;
-        let commit_response = response.into_inner();
-        for result in commit_response.mutation_results.iter() {
-            if result.conflict_detected {
-                return Err(database::DatabaseError::TransactionCommit(anyhow!(
-                    "conflit detected"
-                )));
-            }
-        }
+            .and_then(|response| proto_api::convert_mutation_results(response.mutation_results))
+            .map_err(|e| {
+                convert_error_database_error(e, database::DatabaseError::TransactionCommit)
+            })?;
         Ok(())
     }
 
@@ -116,14 +263,16 @@ impl database::Transaction for Transaction {
             project_id: self.project_id,
             transaction: self.transaction,
         };
-        let _ = self
-            .client
-            .lock()
-            .await
-            .service
-            .rollback(rollback_request)
+        let rpc = Rpc::new("rollback").namespace(&self.namespace);
+        self.client
+            .call(
+                rpc,
+                Retry::Transient,
+                rollback_request,
+                |mut service, request| async move { service.rollback(request).await },
+            )
             .await
-            .map_err(|e| database::DatabaseError::TransactionRollback(anyhow!("{}", e)))?;
+            .map_err(|e| database::DatabaseError::TransactionRollback(e.into()))?;
         Ok(())
     }
 }
@@ -132,7 +281,7 @@ impl database::Transaction for Transaction {
 pub struct Connection {
     project_id: String,
     namespace: String,
-    client: Arc<Mutex<Client>>,
+    client: Client,
 }
 
 impl Connection {
@@ -140,21 +289,38 @@ impl Connection {
         &mut self,
         key: impl Borrow<Key>,
     ) -> Result<Option<T>, proto_api::Error> {
-        self.client.lock().await.get(key, None).await
+        let key = key.borrow().clone().namespace(&self.namespace);
+        self.client.get(key, None).await
     }
 
-    pub async fn get_all<T, K, I>(&mut self, keys: I) -> Result<Vec<T>, proto_api::Error>
+    pub async fn get_all<T, K, I>(&mut self, keys: I) -> Result<LookupResult<T>, proto_api::Error>
     where
         I: IntoIterator<Item = K>,
         K: Borrow<Key>,
         T: FromValue,
     {
-        self.client.lock().await.get_all(keys, None).await
+        let keys = keys
+            .into_iter()
+            .map(|key| key.borrow().clone().namespace(&self.namespace))
+            .collect::<Vec<_>>();
+        self.client.get_all(keys, None).await
+    }
+
+    pub async fn lookup<K, I>(&mut self, keys: I) -> Result<LookupResult<Entity>, proto_api::Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.borrow().clone().namespace(&self.namespace))
+            .collect::<Vec<_>>();
+        self.client.lookup(keys, None).await
     }
 
     pub async fn query<T: FromEntity>(&mut self, query: Query) -> Result<Vec<T>, proto_api::Error> {
         let query = query.namespace(&self.namespace);
-        let entities = self.client.lock().await.query(query).await?;
+        let entities = self.client.query(query, None).await?;
         entities
             .into_iter()
             .map(T::from_entity)
@@ -162,8 +328,35 @@ impl Connection {
             .map_err(proto_api::Error::Convert)
     }
 
+    pub async fn query_page<T: FromEntity>(
+        &mut self,
+        query: Query,
+        cursor: Option<Cursor>,
+    ) -> Result<QueryPage<T>, proto_api::Error> {
+        let query = query.namespace(&self.namespace);
+        let page = self.client.query_page(query, cursor, None).await?;
+        page.convert().map_err(proto_api::Error::Convert)
+    }
+
+    pub async fn count(&mut self, query: Query) -> Result<i64, proto_api::Error> {
+        let query = query.namespace(&self.namespace);
+        self.client.count(query, None).await
+    }
+
+    // NOTE: 結果を全てメモリに載せずに、バッチ単位で読み進める
+    pub fn query_stream<T: FromEntity>(
+        &self,
+        query: Query,
+    ) -> impl Stream<Item = Result<T, proto_api::Error>> {
+        let query = query.namespace(&self.namespace);
+        self.client.query_stream(query).and_then(|entity| {
+            future::ready(T::from_entity(entity).map_err(proto_api::Error::Convert))
+        })
+    }
+
     pub async fn put(&mut self, entity: impl IntoEntity) -> Result<Option<Key>, proto_api::Error> {
-        self.client.lock().await.put(entity).await
+        let mut keys = self.put_all(Some(entity)).await?;
+        Ok(keys.remove(0))
     }
 
     pub async fn put_all<T, I>(&mut self, entities: I) -> Result<Vec<Option<Key>>, proto_api::Error>
@@ -171,64 +364,177 @@ impl Connection {
         I: IntoIterator<Item = T>,
         T: IntoEntity,
     {
-        self.client.lock().await.put_all(entities).await
+        let entities = entities
+            .into_iter()
+            .map(|e| entity::into_entity(e, &self.namespace))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.client.put_all(entities).await
+    }
+
+    pub async fn insert(
+        &mut self,
+        entity: impl IntoEntity,
+    ) -> Result<MutationResult, proto_api::Error> {
+        self.mutate(Mutation::insert(entity)?).await
+    }
+
+    pub async fn update(
+        &mut self,
+        entity: impl IntoEntity,
+    ) -> Result<MutationResult, proto_api::Error> {
+        self.mutate(Mutation::update(entity)?).await
+    }
+
+    pub async fn upsert(
+        &mut self,
+        entity: impl IntoEntity,
+    ) -> Result<MutationResult, proto_api::Error> {
+        self.mutate(Mutation::upsert(entity)?).await
+    }
+
+    pub async fn mutate(&mut self, mutation: Mutation) -> Result<MutationResult, proto_api::Error> {
+        let mut results = self.mutate_all(Some(mutation)).await?;
+        Ok(results.remove(0))
+    }
+
+    pub async fn mutate_all(
+        &mut self,
+        mutations: impl IntoIterator<Item = Mutation>,
+    ) -> Result<Vec<MutationResult>, proto_api::Error> {
+        let mutations = mutations
+            .into_iter()
+            .map(|mutation| mutation.namespace(&self.namespace))
+            .collect();
+        self.client.commit(mutations).await
+    }
+
+    pub async fn delete(&mut self, key: impl Borrow<Key>) -> Result<(), proto_api::Error> {
+        self.delete_all(Some(key)).await
+    }
+
+    pub async fn delete_all<K, I>(&mut self, keys: I) -> Result<(), proto_api::Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|key| key.borrow().clone().namespace(&self.namespace))
+            .collect::<Vec<_>>();
+        self.client.delete_all(keys).await
     }
 
-    pub async fn allocate_ids(&self, keys: &[Key]) -> Result<Vec<api::Key>, proto_api::Error> {
+    pub async fn allocate_ids(&mut self, keys: &[Key]) -> Result<Vec<api::Key>, proto_api::Error> {
         let request = api::AllocateIdsRequest {
             project_id: self.project_id.clone(),
             keys: keys
                 .iter()
-                .map(|key| proto_api::convert_key(&self.project_id, key))
+                .map(|key| key.clone().namespace(&self.namespace))
+                .map(|key| proto_api::convert_key(&self.project_id, &key))
                 .collect(),
         };
+        let rpc = Rpc::new("allocate_ids").keys(&request.keys);
         let response = self
             .client
-            .lock()
-            .await
-            .service
-            .allocate_ids(request)
+            .call(
+                rpc,
+                Retry::Always,
+                request,
+                |mut service, request| async move { service.allocate_ids(request).await },
+            )
             .await?;
-        Ok(response.into_inner().keys)
+        Ok(response.keys)
     }
 }
 
 #[async_trait]
 impl database::Connection for Connection {
     type Transaction = Transaction;
-    async fn begin(&mut self) -> Result<Self::Transaction, database::DatabaseError> {
+    async fn begin_with(
+        &mut self,
+        mode: database::TransactionMode,
+    ) -> Result<Self::Transaction, database::DatabaseError> {
+        let read_only = mode != database::TransactionMode::ReadWrite;
         let begin_transaction_request = api::BeginTransactionRequest {
             project_id: self.project_id.clone(),
-            transaction_options: Some(api::TransactionOptions {
-                mode: Some(api::transaction_options::Mode::ReadWrite(
-                    api::transaction_options::ReadWrite::default(),
-                )),
-            }),
+            transaction_options: Some(convert_transaction_mode(mode)),
         };
-        let response = self
+        let rpc = Rpc::new("begin_transaction").namespace(&self.namespace);
+        let tx_response = self
             .client
-            .lock()
-            .await
-            .service
-            .begin_transaction(begin_transaction_request)
+            .call(
+                rpc,
+                Retry::Transient,
+                begin_transaction_request,
+                |mut service, request| async move { service.begin_transaction(request).await },
+            )
             .await
-            .map_err(|e| database::DatabaseError::TransactionBegin(anyhow!("{}", e)))?;
-        let tx_response = response.into_inner();
+            .map_err(|e| {
+                convert_error_database_error(e, database::DatabaseError::TransactionBegin)
+            })?;
 
         Transaction::new(
             self.project_id.clone(),
             self.namespace.clone(),
             tx_response.transaction,
             self.client.clone(),
+            read_only,
         )
         .await
     }
 }
 
+/// NOTE: Clientは最初のcreateで一度だけ作り、全てのConnectionでChannelとトークンを共有する
 #[derive(new)]
 pub struct ConnectionFactory {
     project_id: String,
     namespace: String,
+    #[new(value = "Client::DEFAULT_MAX_CONCURRENT_BATCHES")]
+    max_concurrent_batches: usize,
+    #[new(default)]
+    retry_policy: database::RetryPolicy,
+    #[new(value = "Client::DEFAULT_TIMEOUT")]
+    timeout: std::time::Duration,
+    // NOTE: with_namespaceで作ったファクトリとも共有する
+    #[new(default)]
+    client: Arc<OnceCell<Client>>,
+}
+
+impl ConnectionFactory {
+    // NOTE: 設定とClientを共有したまま、別の名前空間に繋ぐファクトリを作る
+    pub fn with_namespace(&self, namespace: impl Into<String>) -> ConnectionFactory {
+        ConnectionFactory {
+            project_id: self.project_id.clone(),
+            namespace: namespace.into(),
+            max_concurrent_batches: self.max_concurrent_batches,
+            retry_policy: self.retry_policy.clone(),
+            timeout: self.timeout,
+            client: self.client.clone(),
+        }
+    }
+
+    pub fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn max_concurrent_batches(mut self, max_concurrent_batches: usize) -> Self {
+        self.max_concurrent_batches = max_concurrent_batches;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: database::RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
 }
 
 #[async_trait]
@@ -236,29 +542,395 @@ impl database::ConnectionFactory for ConnectionFactory {
     type Connection = Connection;
     type Transaction = Transaction;
     async fn create(&self) -> Result<Self::Connection, database::DatabaseError> {
+        let client = self.client.get_or_try_init(|| {
+            Client::new(self.project_id.clone())
+                .map(|client| {
+                    client
+                        .max_concurrent_batches(self.max_concurrent_batches)
+                        .retry_policy(self.retry_policy.clone())
+                        .timeout(self.timeout)
+                })
+                .map_err(convert_datastore_error_database_error)
+        })?;
         Ok(Connection::new(
             self.project_id.clone(),
             self.namespace.clone(),
-            Arc::new(Mutex::new(new_client(self.project_id.clone()).await?)),
+            client.clone(),
         ))
     }
 }
 
-async fn new_client(project_name: impl Into<String>) -> Result<Client, database::DatabaseError> {
-    Client::new(project_name)
-        .await
-        .map_err(convert_datastore_error_database_error)
+// NOTE: トランザクション内の書き込みはコミットまで送られないので、競合はコミットのエラーになる
+fn write_error_kind(err: &proto_api::Error) -> domain::RepositoryErrorKind {
+    if err.is_conflict() {
+        domain::RepositoryErrorKind::Conflict
+    } else if err.is_not_found() {
+        domain::RepositoryErrorKind::NotFound
+    } else {
+        domain::RepositoryErrorKind::Fail
+    }
 }
 
 fn convert_datastore_error_database_error(err: proto_api::Error) -> database::DatabaseError {
     database::DatabaseError::Open(err.into())
 }
 
-// fn namespace_from_executor<'a>(executor: &'a Executor<Connection, Transaction>) -> &'a str {
-//     match executor {
-//         Executor::<Connection, Transaction>::Connection(conn) => &conn.namespace,
-//         Executor::<Connection, Transaction>::Transaction(tx) => &tx.namespace,
-//     }
-// }
+fn convert_error_database_error(
+    err: proto_api::Error,
+    otherwise: fn(anyhow::Error) -> database::DatabaseError,
+) -> database::DatabaseError {
+    match err {
+        proto_api::Error::Aborted(_) => database::DatabaseError::Contention(err.into()),
+        proto_api::Error::AlreadyExists(_) | proto_api::Error::Conflict(_) => {
+            database::DatabaseError::Conflict(err.into())
+        }
+        err if err.is_transient() => database::DatabaseError::Transient(err.into()),
+        err => otherwise(err.into()),
+    }
+}
+
+fn convert_transaction_mode(mode: database::TransactionMode) -> api::TransactionOptions {
+    use api::transaction_options::{Mode, ReadOnly, ReadWrite};
+
+    let mode = match mode {
+        database::TransactionMode::ReadWrite => Mode::ReadWrite(ReadWrite::default()),
+        database::TransactionMode::ReadOnly => Mode::ReadOnly(ReadOnly::default()),
+        database::TransactionMode::Snapshot(read_time) => Mode::ReadOnly(ReadOnly {
+            read_time: Some(prost_types::Timestamp {
+                seconds: read_time.timestamp(),
+                nanos: read_time.timestamp_subsec_nanos() as i32,
+            }),
+        }),
+    };
+    api::TransactionOptions { mode: Some(mode) }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use chrono::TimeZone as _;
+    use test_case::test_case;
+
+    #[test_case(tonic::Code::Aborted => database::DatabaseError::Contention(anyhow!("")))]
+    #[test_case(tonic::Code::AlreadyExists => database::DatabaseError::Conflict(anyhow!("")))]
+    #[test_case(tonic::Code::Unavailable => database::DatabaseError::Transient(anyhow!("")))]
+    #[test_case(tonic::Code::DeadlineExceeded => database::DatabaseError::Transient(anyhow!("")))]
+    #[test_case(tonic::Code::Internal => database::DatabaseError::Transient(anyhow!("")))]
+    #[test_case(tonic::Code::InvalidArgument => database::DatabaseError::TransactionCommit(anyhow!("")))]
+    #[test_case(tonic::Code::PermissionDenied => database::DatabaseError::TransactionCommit(anyhow!("")))]
+    fn convert_error_database_error_works(code: tonic::Code) -> database::DatabaseError {
+        convert_error_database_error(
+            tonic::Status::new(code, "test").into(),
+            database::DatabaseError::TransactionCommit,
+        )
+    }
+
+    #[test]
+    fn query_namespace_applies_to_ancestor_path() {
+        let parent = Key::new("Parent")
+            .id("parent1")
+            .parent(Key::new("Root").id(1));
+        let query = Query::new("Child").ancestor(parent).namespace("ns");
+        let ancestor = query.ancestor.unwrap();
+        assert_eq!(ancestor.namespace.as_deref(), Some("ns"));
+        assert_eq!(
+            ancestor
+                .get_parent()
+                .and_then(|key| key.namespace.as_deref()),
+            Some("ns")
+        );
+    }
+
+    #[test_case(
+        database::TransactionMode::ReadWrite
+        => api::transaction_options::Mode::ReadWrite(Default::default())
+    )]
+    #[test_case(
+        database::TransactionMode::ReadOnly
+        => api::transaction_options::Mode::ReadOnly(Default::default())
+    )]
+    #[test_case(
+        database::TransactionMode::Snapshot(chrono::Utc.timestamp(1_600_000_000, 123_000_000))
+        => api::transaction_options::Mode::ReadOnly(api::transaction_options::ReadOnly {
+            read_time: Some(prost_types::Timestamp { seconds: 1_600_000_000, nanos: 123_000_000 }),
+        })
+    )]
+    fn convert_transaction_mode_works(
+        mode: database::TransactionMode,
+    ) -> api::transaction_options::Mode {
+        convert_transaction_mode(mode).mode.unwrap()
+    }
+
+    #[async_std::test]
+    async fn connection_factory_shares_client_without_connecting() {
+        use database::ConnectionFactory as _;
+
+        let factory = ConnectionFactory::new("project".into(), "namespace".into());
+        let first = factory.create().await.unwrap();
+        let second = factory.create().await.unwrap();
+        assert!(Arc::ptr_eq(
+            &first.client.token_manager,
+            &second.client.token_manager
+        ));
+
+        let other = factory.with_namespace("other").create().await.unwrap();
+        assert_eq!(other.namespace, "other");
+        assert!(Arc::ptr_eq(
+            &first.client.token_manager,
+            &other.client.token_manager
+        ));
+    }
+
+    #[async_std::test]
+    async fn read_only_transaction_rejects_mutations() {
+        use database::{Connection as _, ConnectionFactory as _, Transaction as _};
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut tx = conn
+            .begin_with(database::TransactionMode::ReadOnly)
+            .await
+            .unwrap();
+        let found: Option<proto_api::Value> = tx.get(Key::new("Child").id("none")).await.unwrap();
+        assert_eq!(found, None);
+        tx.delete(Key::new("Child").id("none"));
+        assert_eq!(
+            tx.commit().await,
+            Err(database::DatabaseError::TransactionCommit(anyhow!("")))
+        );
+    }
+
+    fn pair(key: Key) -> proto_api::Entity {
+        let mut properties = std::collections::HashMap::new();
+        properties.insert(String::from("word"), proto_api::Value::Strings("x".into()));
+        proto_api::Entity::new(key, properties).unwrap()
+    }
+
+    #[async_std::test]
+    async fn connection_splits_large_writes_and_reads_into_batches() {
+        use database::ConnectionFactory as _;
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let keys = (1..=2 * Client::MAX_LOOKUP_KEYS as i64 + 1)
+            .map(|id| Key::new("Pair").id(id))
+            .collect::<Vec<_>>();
+        conn.put_all(keys.iter().cloned().map(pair)).await.unwrap();
+
+        let found: LookupResult<proto_api::Value> = conn.get_all(&keys).await.unwrap();
+        assert_eq!(found.found.len(), keys.len());
+        assert!(found.missing.is_empty());
+
+        conn.delete_all(&keys).await.unwrap();
+        let found: LookupResult<proto_api::Value> = conn.get_all(&keys).await.unwrap();
+        assert_eq!(found.missing.len(), keys.len());
+    }
+
+    #[async_std::test]
+    async fn transaction_rejects_too_many_mutations() {
+        use database::{Connection as _, ConnectionFactory as _, Transaction as _};
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        tx.put_all((1..=Client::MAX_MUTATIONS as i64 + 1).map(|id| pair(Key::new("Pair").id(id))))
+            .await
+            .unwrap();
+        assert_eq!(
+            tx.commit().await,
+            Err(database::DatabaseError::TransactionCommit(anyhow!("")))
+        );
+        let count = conn.count(Query::new("Pair")).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[async_std::test]
+    async fn transaction_query_and_delete_works() {
+        use database::{Connection as _, ConnectionFactory as _, Transaction as _};
+        use std::collections::HashMap;
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let parent = Key::new("Parent").id("parent1");
+        let child = |id: &str| Key::new("Child").id(id).parent(parent.clone());
+        let entity = |key: Key| {
+            let mut properties = HashMap::new();
+            properties.insert(String::from("name"), proto_api::Value::Strings("x".into()));
+            proto_api::Entity::new(key, properties).unwrap()
+        };
+
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        tx.put_all(vec![entity(child("child1")), entity(child("child2"))])
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let mut tx = conn.begin().await.unwrap();
+        let found: Vec<proto_api::Entity> = tx
+            .query(Query::new("Child").ancestor(parent.clone()))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        tx.delete(child("child1"));
+        tx.commit().await.unwrap();
+
+        let mut tx = conn.begin().await.unwrap();
+        let deleted: Option<proto_api::Value> = tx.get(child("child1")).await.unwrap();
+        let remaining: Option<proto_api::Value> = tx.get(child("child2")).await.unwrap();
+        tx.rollback().await.unwrap();
+        assert_eq!(deleted, None);
+        assert!(remaining.is_some());
+    }
+
+    #[async_std::test]
+    async fn connection_key_filter_query_works_in_namespace() {
+        use database::ConnectionFactory as _;
+        use std::collections::HashMap;
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore
+            .as_ref()
+            .with_namespace("key_filter")
+            .create()
+            .await
+            .unwrap();
+        let entities = (1..=3).map(|i| {
+            let mut properties = HashMap::new();
+            properties.insert(String::from("index"), proto_api::Value::Integer(i));
+            proto_api::Entity::new(Key::new("Keyed").id(i), properties).unwrap()
+        });
+        conn.put_all(entities).await.unwrap();
+
+        let found: Vec<proto_api::Entity> = conn
+            .query(Query::new("Keyed").filter(proto_api::Filter::key_in(vec![
+                Key::new("Keyed").id(1),
+                Key::new("Keyed").id(3),
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found
+            .iter()
+            .all(|entity| entity.key().get_namespace() == Some("key_filter")));
+        assert_eq!(
+            conn.count(
+                Query::new("Keyed").filter(proto_api::Filter::key_equal(Key::new("Keyed").id(2)))
+            )
+            .await
+            .unwrap(),
+            1
+        );
+    }
+
+    #[async_std::test]
+    async fn connection_purge_deletes_every_page() {
+        use database::ConnectionFactory as _;
+        use std::collections::HashMap;
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore
+            .as_ref()
+            .with_namespace("purge")
+            .create()
+            .await
+            .unwrap();
+        let count = Client::MAX_MUTATIONS * 2 + 1;
+        let entities = (0..count).map(|i| {
+            let mut properties = HashMap::new();
+            properties.insert(String::from("index"), proto_api::Value::Integer(i as i64));
+            proto_api::Entity::new(Key::new("Purged").id(i as i64 + 1), properties).unwrap()
+        });
+        conn.put_all(entities).await.unwrap();
+        conn.put(
+            proto_api::Entity::new(
+                Key::new("Other").id("other"),
+                HashMap::<String, proto_api::Value>::new(),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(conn.purge().await.unwrap(), count + 1);
+        assert!(conn.kinds().await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn executor_rejects_non_ancestor_query_in_transaction() {
+        use database::{Connection as _, ConnectionFactory as _, Transaction as _};
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        let mut executor = database::Executor::Transaction(&mut tx);
+        let result: Result<Vec<proto_api::Entity>, _> = executor.query(Query::new("Child")).await;
+        assert!(matches!(result, Err(proto_api::Error::InvalidQuery(_))));
+        assert!(matches!(
+            executor.count(Query::new("Child")).await,
+            Err(proto_api::Error::InvalidQuery(_))
+        ));
+        tx.rollback().await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn connection_query_page_and_stream_works() {
+        use database::ConnectionFactory as _;
+        use std::collections::HashMap;
+
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let entities = (1..=5).map(|i| {
+            let mut properties = HashMap::new();
+            properties.insert(String::from("index"), proto_api::Value::Integer(i));
+            proto_api::Entity::new(Key::new("Paged").id(format!("paged{}", i)), properties).unwrap()
+        });
+        conn.put_all(entities).await.unwrap();
+
+        let query = Query::new("Paged").order(proto_api::Order::Asc("index".into()));
+        let first: QueryPage<proto_api::Entity> =
+            conn.query_page(query.clone().limit(3), None).await.unwrap();
+        assert_eq!(first.items.len(), 3);
+        let cursor: Cursor = first.cursor.unwrap().to_string().parse().unwrap();
+        let second: QueryPage<proto_api::Entity> = conn
+            .query_page(query.clone().limit(3), Some(cursor))
+            .await
+            .unwrap();
+        assert_eq!(second.items.len(), 2);
+        assert!(matches!(
+            conn.query_page::<proto_api::Entity>(query.clone(), None)
+                .await,
+            Err(proto_api::Error::InvalidQuery(_))
+        ));
+
+        let streamed: Vec<proto_api::Entity> =
+            conn.query_stream(query).try_collect().await.unwrap();
+        assert_eq!(streamed.len(), 5);
+
+        assert_eq!(conn.count(Query::new("Paged")).await.unwrap(), 5);
+        assert_eq!(
+            conn.count(Query::new("Paged").filter(proto_api::Filter::GreaterThan(
+                "index".into(),
+                proto_api::Value::Integer(3)
+            )))
+            .await
+            .unwrap(),
+            2
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use super::*;
+use database::ConnectionFactory as _;
+
+const KIND_KIND: &str = "__kind__";
+const NAMESPACE_KIND: &str = "__namespace__";
+
+impl Connection {
+    pub async fn kinds(&mut self) -> Result<Vec<String>, proto_api::Error> {
+        let kinds: Vec<proto_api::Entity> = self.query(Query::new(KIND_KIND).keys_only()).await?;
+        Ok(kinds
+            .into_iter()
+            .filter_map(|entity| match entity.key().get_id() {
+                proto_api::KeyID::StringID(kind) if !kind.starts_with("__") => Some(kind.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // NOTE: 名前空間内の全てのエンティティを、種類ごとにキーだけ取得して削除する。
+    // 全てのキーをメモリに載せないよう、一度のコミットで削除できる数ずつ読んでは削除する。
+    // 読んだ分は削除されているので、カーソルを使わずに毎回先頭から読む
+    pub async fn purge(&mut self) -> Result<usize, proto_api::Error> {
+        let mut deleted = 0;
+        for kind in self.kinds().await? {
+            let query = Query::new(kind)
+                .keys_only()
+                .limit(Client::MAX_MUTATIONS as i32);
+            loop {
+                let page: QueryPage<proto_api::Entity> =
+                    self.query_page(query.clone(), None).await?;
+                let keys = page
+                    .items
+                    .into_iter()
+                    .map(|entity| entity.key().clone())
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    break;
+                }
+                deleted += keys.len();
+                self.delete_all(keys).await?;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// NOTE: デフォルトの名前空間は空文字列として返す
+pub async fn list_namespaces(project_id: &str) -> anyhow::Result<Vec<String>> {
+    let mut conn = ConnectionFactory::new(project_id.into(), String::new())
+        .create()
+        .await?;
+    let namespaces: Vec<proto_api::Entity> =
+        conn.query(Query::new(NAMESPACE_KIND).keys_only()).await?;
+    Ok(namespaces
+        .into_iter()
+        .map(|entity| match entity.key().get_id() {
+            proto_api::KeyID::StringID(namespace) => namespace.clone(),
+            _ => String::new(),
+        })
+        .collect())
+}
+
+pub async fn purge_namespace(project_id: &str, namespace: &str) -> anyhow::Result<usize> {
+    let mut conn = ConnectionFactory::new(project_id.into(), namespace.into())
+        .create()
+        .await?;
+    Ok(conn.purge().await?)
+}
@@ -1,17 +1,21 @@
 use chrono::offset::Utc;
-use chrono::DateTime;
+use chrono::{DateTime, Duration};
 use hyper::client::{Client, HttpConnector};
 use hyper_rustls::HttpsConnector;
 use json::json;
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
 
-use super::error::AuthError;
+use super::error::{AuthError, Error};
 
 #[allow(unused)]
 pub(crate) const TLS_CERTS: &[u8] = include_bytes!("roots.pem");
 
 const AUTH_ENDPOINT: &str = "https://oauth2.googleapis.com/token";
+const METADATA_HOST: &str = "metadata.google.internal";
 
 #[allow(missing_docs)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -29,6 +33,80 @@ pub struct ApplicationCredentials {
     pub client_x509_cert_url: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedUserCredentials {
+    #[serde(rename = "type")]
+    pub cred_type: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    String::from(AUTH_ENDPOINT)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    ServiceAccount(ApplicationCredentials),
+    AuthorizedUser(AuthorizedUserCredentials),
+    MetadataServer { host: String },
+    Static(String),
+}
+
+impl Credentials {
+    /// NOTE: 次の順に探し、どれも見つからなければ認証なしで接続する
+    /// 1. GOOGLE_OAUTH_ACCESS_TOKEN (固定トークン)
+    /// 2. GOOGLE_APPLICATION_CREDENTIALS のJSONファイル
+    /// 3. gcloud auth application-default login のJSONファイル
+    /// 4. Cloud Run・GCEのメタデータサーバ
+    pub fn discover() -> Result<Option<Credentials>, Error> {
+        if let Ok(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            return Ok(Some(Credentials::Static(token)));
+        }
+        if let Ok(path) = env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            return Credentials::from_file(path).map(Some);
+        }
+        if let Some(path) = gcloud_credentials_path().filter(|path| path.exists()) {
+            return Credentials::from_file(path).map(Some);
+        }
+        if let Ok(host) = env::var("GCE_METADATA_HOST") {
+            return Ok(Some(Credentials::MetadataServer { host }));
+        }
+        if env::var("K_SERVICE").is_ok() {
+            return Ok(Some(Credentials::MetadataServer {
+                host: String::from(METADATA_HOST),
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Credentials, Error> {
+        let file = File::open(path.into())?;
+        Ok(Credentials::from_json(json::from_reader(file)?)?)
+    }
+
+    pub fn from_json(value: json::Value) -> Result<Credentials, AuthError> {
+        match value.get("type").and_then(json::Value::as_str) {
+            Some("service_account") => Ok(Credentials::ServiceAccount(json::from_value(value)?)),
+            Some("authorized_user") => Ok(Credentials::AuthorizedUser(json::from_value(value)?)),
+            other => Err(AuthError::UnsupportedCredentials(
+                other.unwrap_or_default().to_string(),
+            )),
+        }
+    }
+}
+
+fn gcloud_credentials_path() -> Option<PathBuf> {
+    let config_dir = match env::var("CLOUDSDK_CONFIG") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".config/gcloud"),
+    };
+    Some(config_dir.join("application_default_credentials.json"))
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum TokenValue {
     Bearer(String),
@@ -51,68 +129,236 @@ pub(crate) struct Token {
 #[derive(Debug, Clone)]
 pub(crate) struct TokenManager {
     client: Client<HttpsConnector<HttpConnector>>,
-    scopes: String,
-    creds: ApplicationCredentials,
+    scopes: Vec<String>,
+    credentials: Credentials,
     current_token: Option<Token>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct AuthResponse {
     access_token: String,
+    #[serde(default = "default_expires_in")]
+    expires_in: i64,
+}
+
+fn default_expires_in() -> i64 {
+    3600
 }
 
 impl TokenManager {
-    pub(crate) fn new(creds: ApplicationCredentials, scopes: &[&str]) -> TokenManager {
+    // NOTE: 期限切れ直前のトークンでリクエストしないよう、期限のこれだけ前に更新する
+    const REFRESH_AHEAD_SECONDS: i64 = 300;
+
+    pub(crate) fn new(credentials: Credentials, scopes: &[&str]) -> TokenManager {
         TokenManager {
-            creds,
+            credentials,
             client: Client::builder().build::<_, hyper::Body>(HttpsConnector::with_native_roots()),
-            scopes: scopes.join(" "),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
             current_token: None,
         }
     }
 
     pub(crate) async fn token(&mut self) -> Result<String, AuthError> {
-        let hour = chrono::Duration::minutes(45);
-        let current_time = chrono::Utc::now();
+        if let Credentials::Static(token) = &self.credentials {
+            return Ok(TokenValue::Bearer(token.clone()).to_string());
+        }
+        let current_time = Utc::now();
         match self.current_token {
-            Some(ref token) if token.expiry >= current_time => Ok(token.value.to_string()),
+            Some(ref token)
+                if token.expiry - Duration::seconds(Self::REFRESH_AHEAD_SECONDS) > current_time =>
+            {
+                Ok(token.value.to_string())
+            }
             _ => {
-                let expiry = current_time + hour;
+                let req = self.token_request(current_time)?;
+                let response = self.client.request(req).await?;
+                let status = response.status();
+                let data = hyper::body::to_bytes(response.into_body()).await?.to_vec();
+                if !status.is_success() {
+                    return Err(AuthError::UnexpectedResponse(
+                        status.as_u16(),
+                        String::from_utf8_lossy(&data).into_owned(),
+                    ));
+                }
+
+                let ar: AuthResponse = json::from_slice(&data)?;
+
+                let value = TokenValue::Bearer(ar.access_token);
+                let token = value.to_string();
+                self.current_token = Some(Token {
+                    value,
+                    expiry: current_time + Duration::seconds(ar.expires_in),
+                });
+
+                Ok(token)
+            }
+        }
+    }
+
+    fn token_request(
+        &self,
+        current_time: DateTime<Utc>,
+    ) -> Result<hyper::Request<hyper::Body>, AuthError> {
+        match &self.credentials {
+            Credentials::ServiceAccount(creds) => {
                 let claims = json!({
-                    "iss": self.creds.client_email.as_str(),
-                    "scope": self.scopes.as_str(),
-                    "aud": AUTH_ENDPOINT,
-                    "exp": expiry.timestamp(),
+                    "iss": creds.client_email.as_str(),
+                    "scope": self.scopes.join(" "),
+                    "aud": creds.token_uri.as_str(),
+                    "exp": (current_time + Duration::hours(1)).timestamp(),
                     "iat": current_time.timestamp(),
                 });
                 let token = jwt::encode(
                     &jwt::Header::new(jwt::Algorithm::RS256),
                     &claims,
-                    &jwt::EncodingKey::from_rsa_pem(self.creds.private_key.as_bytes())?,
+                    &jwt::EncodingKey::from_rsa_pem(creds.private_key.as_bytes())?,
                 )?;
-                let form = format!(
-                    "grant_type=urn:ietf:params:oauth:grant-type:jwt-bearer&assertion={}",
-                    token.as_str()
-                );
+                form_request(
+                    &creds.token_uri,
+                    &[
+                        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                        ("assertion", token.as_str()),
+                    ],
+                )
+            }
+            Credentials::AuthorizedUser(creds) => form_request(
+                &creds.token_uri,
+                &[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", creds.client_id.as_str()),
+                    ("client_secret", creds.client_secret.as_str()),
+                    ("refresh_token", creds.refresh_token.as_str()),
+                ],
+            ),
+            Credentials::MetadataServer { host } => Ok(hyper::Request::builder()
+                .method("GET")
+                .uri(format!(
+                    "http://{}/computeMetadata/v1/instance/service-accounts/default/token?scopes={}",
+                    host,
+                    self.scopes.join(",")
+                ))
+                .header("Metadata-Flavor", "Google")
+                .body(hyper::Body::empty())?),
+            Credentials::Static(_) => unreachable!("static token does not need a request"),
+        }
+    }
+}
 
-                let req = hyper::Request::builder()
-                    .method("POST")
-                    .uri(AUTH_ENDPOINT)
-                    .header("Content-Type", "application/x-www-form-urlencoded")
-                    .body(hyper::Body::from(form))?;
+fn form_request(
+    uri: &str,
+    params: &[(&str, &str)],
+) -> Result<hyper::Request<hyper::Body>, AuthError> {
+    let form = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    Ok(hyper::Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(hyper::Body::from(form))?)
+}
 
-                let data = hyper::body::to_bytes(self.client.request(req).await?.into_body())
-                    .await?
-                    .to_vec();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmww::stand_in_server::StandInServer;
+    use test_case::test_case;
 
-                let ar: AuthResponse = json::from_slice(&data)?;
+    const SCOPES: [&str; 1] = ["https://www.googleapis.com/auth/datastore"];
 
-                let value = TokenValue::Bearer(ar.access_token);
-                let token = value.to_string();
-                self.current_token = Some(Token { value, expiry });
+    fn authorized_user(token_uri: String) -> Credentials {
+        Credentials::AuthorizedUser(AuthorizedUserCredentials {
+            cred_type: "authorized_user".into(),
+            client_id: "client".into(),
+            client_secret: "secret".into(),
+            refresh_token: "1//refresh".into(),
+            token_uri,
+        })
+    }
 
-                Ok(token)
-            }
-        }
+    #[test_case(
+        json!({
+            "type": "authorized_user",
+            "client_id": "client",
+            "client_secret": "secret",
+            "refresh_token": "refresh",
+        })
+        => Ok(Credentials::AuthorizedUser(AuthorizedUserCredentials {
+            cred_type: "authorized_user".into(),
+            client_id: "client".into(),
+            client_secret: "secret".into(),
+            refresh_token: "refresh".into(),
+            token_uri: AUTH_ENDPOINT.into(),
+        }))
+    )]
+    #[test_case(
+        json!({"type": "external_account"})
+        => Err("unsupported credentials type: external_account".into())
+    )]
+    fn credentials_from_json_works(value: json::Value) -> Result<Credentials, String> {
+        Credentials::from_json(value).map_err(|e| e.to_string())
+    }
+
+    #[async_std::test]
+    async fn token_manager_uses_static_token() {
+        let mut manager = TokenManager::new(Credentials::Static("static".into()), &SCOPES);
+        assert_eq!(manager.token().await.unwrap(), "Bearer static");
+    }
+
+    #[test_case(3600 => 1; "cached_until_expiry")]
+    #[test_case(60 => 2; "refresh_ahead_of_expiry")]
+    #[async_std::test]
+    async fn token_manager_honours_expires_in(expires_in: i64) -> usize {
+        let server = StandInServer::start(
+            200,
+            json!({"access_token": "refreshed", "expires_in": expires_in}).to_string(),
+        )
+        .await;
+        let mut manager = TokenManager::new(authorized_user(server.url("/token")), &SCOPES);
+        assert_eq!(manager.token().await.unwrap(), "Bearer refreshed");
+        assert_eq!(manager.token().await.unwrap(), "Bearer refreshed");
+
+        let requests = server.requests();
+        assert!(requests[0].starts_with("POST /token "));
+        assert!(requests[0].ends_with(
+            "grant_type=refresh_token&client_id=client&client_secret=secret&refresh_token=1%2F%2Frefresh"
+        ));
+        requests.len()
+    }
+
+    #[async_std::test]
+    async fn token_manager_requests_metadata_server() {
+        let server = StandInServer::start(
+            200,
+            json!({"access_token": "metadata", "expires_in": 3599, "token_type": "Bearer"})
+                .to_string(),
+        )
+        .await;
+        let mut manager = TokenManager::new(
+            Credentials::MetadataServer {
+                host: server.host().into(),
+            },
+            &SCOPES,
+        );
+        assert_eq!(manager.token().await.unwrap(), "Bearer metadata");
+
+        let requests = server.requests();
+        assert!(requests[0].starts_with(&format!(
+            "GET /computeMetadata/v1/instance/service-accounts/default/token?scopes={} ",
+            SCOPES[0]
+        )));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("metadata-flavor: google"));
+    }
+
+    #[async_std::test]
+    async fn token_manager_reports_error_response() {
+        let server = StandInServer::start(401, r#"{"error":"invalid_grant"}"#.into()).await;
+        let mut manager = TokenManager::new(authorized_user(server.url("/token")), &SCOPES);
+        assert_eq!(
+            manager.token().await.map_err(|e| e.to_string()),
+            Err(r#"unexpected response from token endpoint: 401 {"error":"invalid_grant"}"#.into())
+        );
     }
 }
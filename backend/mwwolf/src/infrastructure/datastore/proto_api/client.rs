@@ -1,25 +1,38 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
-use std::fs::File;
+use std::future::Future;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_std::sync::Mutex;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
-use tonic::{IntoRequest, Request};
+use tonic::{IntoRequest, Request, Response};
+use tracing::Instrument;
+
+use crate::libmww::database::{sleep_backoff, RetryPolicy};
 
 use super::api;
 use super::api::datastore_client::DatastoreClient;
 use super::api::value::ValueType;
-use super::authorize::{ApplicationCredentials, TokenManager, TLS_CERTS};
-use super::{Entity, Error, Filter, FromValue, IntoEntity, Key, KeyID, Order, Query, Value};
+use super::authorize::{Credentials, TokenManager, TLS_CERTS};
+use super::rpc::Rpc;
+use super::{
+    ConvertError, Cursor, Entity, Error, Filter, FromValue, IntoEntity, Key, KeyID, Mutation,
+    MutationResult, Operation, Order, Query, QueryPage, Value,
+};
+use api::query_result_batch::MoreResultsType;
 use api::read_options::{ConsistencyType, ReadConsistency};
 #[derive(Clone)]
 pub struct Client {
     pub(crate) project_name: String,
     pub(crate) service: DatastoreClient<Channel>,
     pub(crate) token_manager: Arc<Mutex<Option<TokenManager>>>,
+    pub(crate) max_concurrent_batches: usize,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) timeout: Duration,
 }
 
 impl Client {
@@ -29,83 +42,158 @@ impl Client {
         "https://www.googleapis.com/auth/cloud-platform",
         "https://www.googleapis.com/auth/datastore",
     ];
+    // NOTE: 1回のリクエストに含められる数の上限
+    pub const MAX_MUTATIONS: usize = 500;
+    pub const MAX_LOOKUP_KEYS: usize = 1000;
+    pub const DEFAULT_MAX_CONCURRENT_BATCHES: usize = 4;
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
+    // NOTE: token_managerは全てのクローンで共有しているので、取得したトークンはクローン間でキャッシュされる
     pub(crate) async fn construct_request<T: IntoRequest<T>>(
         &mut self,
         request: T,
     ) -> Result<Request<T>, Error> {
         let mut request = request.into_request();
-        let token = self.token_manager.lock().await;
-        if token.is_some() {
-            let token = token.clone().unwrap().token().await?;
+        request.set_timeout(self.timeout);
+        let mut token_manager = self.token_manager.lock().await;
+        if let Some(token_manager) = token_manager.as_mut() {
+            let token = token_manager.token().await?;
             let metadata = request.metadata_mut();
             metadata.insert("authorization", token.parse().unwrap());
         }
         Ok(request)
     }
 
-    pub async fn new(project_name: impl Into<String>) -> Result<Client, Error> {
-        if let Ok(cred_file_path) = env::var("GOOGLE_APPLICATION_CREDENTIALS") {
-            let file = File::open(cred_file_path)?;
-            let creds = json::from_reader(file)?;
-            Client::from_credentials(project_name, creds).await
-        } else {
-            Client::from_without_credentials(project_name).await
+    // NOTE: エミュレータは認証しないので、エミュレータに繋ぐときは認証情報を探さない
+    pub fn new(project_name: impl Into<String>) -> Result<Client, Error> {
+        if env::var("DATASTORE_EMULATOR_HOST").is_ok() {
+            return Client::from_without_credentials(project_name);
+        }
+        match Credentials::discover()? {
+            Some(credentials) => Client::from_credentials(project_name, credentials),
+            None => Client::from_without_credentials(project_name),
         }
     }
 
-    pub async fn from_credentials(
+    pub fn from_credentials(
         project_name: impl Into<String>,
-        creds: ApplicationCredentials,
+        credentials: Credentials,
     ) -> Result<Client, Error> {
-        let channel = if let Ok(host) = std::env::var("DATASTORE_EMULATOR_HOST") {
-            let url = format!("http://{}", host);
-            Channel::builder(http::Uri::from_str(&url).unwrap())
-                .connect()
-                .await?
-        } else {
-            let tls_config = ClientTlsConfig::new()
-                .ca_certificate(Certificate::from_pem(TLS_CERTS))
-                .domain_name(Client::DOMAIN_NAME);
-            Channel::from_static(Client::ENDPOINT)
-                .tls_config(tls_config)?
-                .connect()
-                .await?
-        };
-
         Ok(Client {
             project_name: project_name.into(),
-            service: DatastoreClient::new(channel),
+            service: DatastoreClient::new(Client::connect_lazy()?),
             token_manager: Arc::new(Mutex::new(Some(TokenManager::new(
-                creds,
+                credentials,
                 Client::SCOPES.as_ref(),
             )))),
+            max_concurrent_batches: Client::DEFAULT_MAX_CONCURRENT_BATCHES,
+            retry_policy: RetryPolicy::default(),
+            timeout: Client::DEFAULT_TIMEOUT,
         })
     }
 
-    pub async fn from_without_credentials(
-        project_name: impl Into<String>,
-    ) -> Result<Client, Error> {
+    pub fn from_without_credentials(project_name: impl Into<String>) -> Result<Client, Error> {
+        Ok(Client {
+            project_name: project_name.into(),
+            service: DatastoreClient::new(Client::connect_lazy()?),
+            token_manager: Arc::new(Mutex::new(None)),
+            max_concurrent_batches: Client::DEFAULT_MAX_CONCURRENT_BATCHES,
+            retry_policy: RetryPolicy::default(),
+            timeout: Client::DEFAULT_TIMEOUT,
+        })
+    }
+
+    // NOTE: 上限を超える書き込み・読み込みを分割したときに、同時に送るリクエストの数
+    pub fn max_concurrent_batches(mut self, max_concurrent_batches: usize) -> Client {
+        self.max_concurrent_batches = max_concurrent_batches.max(1);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Client {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    // NOTE: RPCごとの期限。サーバーに伝えるだけでなく、応答が無いときはクライアント側でも打ち切る
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    // NOTE: retryで許した失敗だけを、指数バックオフで再試行する。
+    // リクエストは試行ごとに作り直すので、トークンの期限が切れていても更新される。
+    // 再試行を含めた全体を1つのspanで計測し、結果をメトリクスに記録する
+    pub(crate) async fn call<T, R, F, Fut>(
+        &mut self,
+        rpc: Rpc,
+        retry: Retry,
+        message: T,
+        f: F,
+    ) -> Result<R, Error>
+    where
+        T: Clone,
+        F: Fn(DatastoreClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, tonic::Status>>,
+    {
+        let span = rpc.span();
+        let started = Instant::now();
+        let (result, retries) = self
+            .call_with_retry(retry, message, f)
+            .instrument(span.clone())
+            .await;
+        rpc.record(&span, retries, started.elapsed(), &result);
+        result
+    }
+
+    async fn call_with_retry<T, R, F, Fut>(
+        &mut self,
+        retry: Retry,
+        message: T,
+        rpc: F,
+    ) -> (Result<R, Error>, u32)
+    where
+        T: Clone,
+        F: Fn(DatastoreClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, tonic::Status>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let request = match self.construct_request(message.clone()).await {
+                Ok(request) => request,
+                Err(e) => return (Err(e), attempt),
+            };
+            let result =
+                async_std::future::timeout(self.timeout, rpc(self.service.clone(), request))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(tonic::Status::deadline_exceeded("client deadline exceeded"))
+                    });
+            match result.map_err(Error::from) {
+                Ok(response) => return (Ok(response.into_inner()), attempt),
+                Err(e) if retry.allows(&e) && self.retry_policy.has_next_attempt(attempt) => {
+                    tracing::warn!(error = %e, attempt, "retrying datastore rpc");
+                    sleep_backoff(&self.retry_policy, attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => return (Err(e), attempt),
+            }
+        }
+    }
+
+    // NOTE: Channelは最初のRPCで接続し、クローンしても同じHTTP/2コネクションを多重化して使う
+    fn connect_lazy() -> Result<Channel, Error> {
         let channel = if let Ok(host) = std::env::var("DATASTORE_EMULATOR_HOST") {
             let url = format!("http://{}", host);
-            Channel::builder(http::Uri::from_str(&url).unwrap())
-                .connect()
-                .await?
+            Channel::builder(http::Uri::from_str(&url).unwrap()).connect_lazy()?
         } else {
             let tls_config = ClientTlsConfig::new()
                 .ca_certificate(Certificate::from_pem(TLS_CERTS))
                 .domain_name(Client::DOMAIN_NAME);
             Channel::from_static(Client::ENDPOINT)
                 .tls_config(tls_config)?
-                .connect()
-                .await?
+                .connect_lazy()?
         };
-
-        Ok(Client {
-            project_name: project_name.into(),
-            service: DatastoreClient::new(channel),
-            token_manager: Arc::new(Mutex::new(None)),
-        })
+        Ok(channel)
     }
 
     pub async fn get<T, K>(
@@ -117,27 +205,89 @@ impl Client {
         K: Borrow<Key>,
         T: FromValue,
     {
-        let results = self.get_all(Some(key.borrow()), transaction).await?;
-        Ok(results.into_iter().next().map(T::from_value).transpose()?)
+        let result = self.get_all(Some(key.borrow()), transaction).await?;
+        Ok(result.found.into_iter().next().map(|(_, value)| value))
     }
 
     pub async fn get_all<T, K, I>(
         &mut self,
         keys: I,
         transaction: Option<prost::alloc::vec::Vec<u8>>,
-    ) -> Result<Vec<T>, Error>
+    ) -> Result<LookupResult<T>, Error>
     where
         I: IntoIterator<Item = K>,
         K: Borrow<Key>,
         T: FromValue,
+    {
+        let result = self.lookup(keys, transaction).await?;
+        Ok(LookupResult {
+            found: result
+                .found
+                .into_iter()
+                .map(|(key, entity)| Ok((key, T::from_value(entity.properties)?)))
+                .collect::<Result<_, ConvertError>>()?,
+            missing: result.missing,
+        })
+    }
+
+    // NOTE: 見つかったエンティティは、読み込んだときのバージョンを持つ
+    pub async fn lookup<K, I>(
+        &mut self,
+        keys: I,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<LookupResult<Entity>, Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
     {
         let og_keys: Vec<K> = keys.into_iter().collect();
-        let mut keys: Vec<_> = og_keys
+        // NOTE: 同じキーを何度要求されても一度だけ読み込み、結果は要求した数だけ返す
+        let mut requested = HashSet::new();
+        let keys: Vec<_> = og_keys
             .iter()
-            .map(|key| convert_key(self.project_name.as_str(), key.borrow()))
+            .map(Borrow::borrow)
+            .filter(|key| requested.insert(*key))
+            .map(|key| convert_key(self.project_name.as_str(), key))
             .collect();
-        let mut found = HashMap::new();
+        let batches = into_chunks(keys, Client::MAX_LOOKUP_KEYS)
+            .into_iter()
+            .map(|keys| {
+                let mut client = self.clone();
+                let transaction = transaction.clone();
+                async move { client.lookup_batch(keys, transaction).await }
+            });
+        let found: Vec<Vec<Entity>> = stream::iter(batches)
+            .buffered(self.max_concurrent_batches)
+            .try_collect()
+            .await?;
+        let found: HashMap<Key, Entity> = found
+            .into_iter()
+            .flatten()
+            .map(|entity| (entity.key.clone(), entity))
+            .collect();
+
+        // NOTE: missingは要求したキーのうちfoundに無いものと一致するので、最後にまとめて求める
+        let mut result = LookupResult {
+            found: Vec::new(),
+            missing: Vec::new(),
+        };
+        for key in og_keys {
+            let key = key.borrow();
+            match found.get(key) {
+                Some(entity) => result.found.push((key.clone(), entity.clone())),
+                None => result.missing.push(key.clone()),
+            }
+        }
+        Ok(result)
+    }
 
+    // NOTE: deferredとして返されたキーは、読み込めるまで要求し直す
+    async fn lookup_batch(
+        &mut self,
+        mut keys: Vec<api::Key>,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<Vec<Entity>, Error> {
+        let mut found = vec![];
         while !keys.is_empty() {
             let request = api::LookupRequest {
                 keys,
@@ -146,29 +296,18 @@ impl Client {
                     consistency_type: Some(ConsistencyType::Transaction(transaction.to_vec())),
                 }),
             };
-            let request = self.construct_request(request).await?;
-            let response = self.service.lookup(request).await?;
-            let response = response.into_inner();
+            let rpc = Rpc::new("lookup").keys(&request.keys);
+            let retry = Retry::read(transaction.is_some());
+            let response = self
+                .call(rpc, retry, request, |mut service, request| async move {
+                    service.lookup(request).await
+                })
+                .await?;
 
-            found.extend(
-                response
-                    .found
-                    .into_iter()
-                    .map(|val| val.entity.unwrap())
-                    .map(Entity::from)
-                    .map(|entity| (entity.key, entity.properties)),
-            );
-            // let missing = response.missing;
+            found.extend(response.found.into_iter().map(convert_entity_result));
             keys = response.deferred;
         }
-
-        let values: Vec<T> = og_keys
-            .into_iter()
-            .flat_map(|key| found.remove(key.borrow()))
-            .map(FromValue::from_value)
-            .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(values)
+        Ok(found)
     }
 
     pub async fn put(&mut self, entity: impl IntoEntity) -> Result<Option<Key>, Error> {
@@ -181,24 +320,12 @@ impl Client {
         I: IntoIterator<Item = T>,
         T: IntoEntity,
     {
-        let mutations = generate_mutations(&self.project_name, entities)?;
-
-        let request = api::CommitRequest {
-            mutations,
-            mode: api::commit_request::Mode::NonTransactional as i32,
-            transaction_selector: None,
-            project_id: self.project_name.clone(),
-        };
-        let request = self.construct_request(request).await?;
-        let response = self.service.commit(request).await?;
-        let response = response.into_inner();
-        let keys = response
-            .mutation_results
+        let mutations = entities
             .into_iter()
-            .map(|result| result.key.map(Key::from))
-            .collect();
-
-        Ok(keys)
+            .map(Mutation::put)
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = self.commit(mutations).await?;
+        Ok(results.into_iter().map(|result| result.key).collect())
     }
 
     pub async fn delete(&mut self, key: impl Borrow<Key>) -> Result<(), Error> {
@@ -210,113 +337,247 @@ impl Client {
         I: IntoIterator<Item = T>,
         T: Borrow<Key>,
     {
-        let mutations = keys
+        let mutations = keys.into_iter().map(Mutation::delete).collect();
+        self.commit(mutations).await?;
+        Ok(())
+    }
+
+    // NOTE: トランザクションを使わずにミューテーションをまとめてコミットする。
+    // 上限を超える分は分割して並行にコミットするので、途中で失敗するとそれまでのコミットは適用されたままになる
+    pub async fn commit(&mut self, mutations: Vec<Mutation>) -> Result<Vec<MutationResult>, Error> {
+        let mutations = mutations
             .into_iter()
-            .map(|key| convert_key(self.project_name.as_str(), key.borrow()))
-            .map(|key| api::Mutation {
-                operation: Some(api::mutation::Operation::Delete(key)),
-                conflict_detection_strategy: None,
-            })
+            .map(|mutation| convert_mutation(&self.project_name, mutation))
             .collect();
+        let batches = into_chunks(mutations, Client::MAX_MUTATIONS)
+            .into_iter()
+            .map(|mutations| {
+                let mut client = self.clone();
+                async move { client.commit_batch(mutations).await }
+            });
+        let results: Vec<Vec<MutationResult>> = stream::iter(batches)
+            .buffered(self.max_concurrent_batches)
+            .try_collect()
+            .await?;
+        Ok(results.concat())
+    }
 
+    async fn commit_batch(
+        &mut self,
+        mutations: Vec<api::Mutation>,
+    ) -> Result<Vec<MutationResult>, Error> {
+        let retry = if is_retryable_commit(&mutations) {
+            Retry::Always
+        } else {
+            Retry::Never
+        };
+        let rpc = Rpc::new("commit").mutations(&mutations);
         let request = api::CommitRequest {
             mutations,
             mode: api::commit_request::Mode::NonTransactional as i32,
             transaction_selector: None,
             project_id: self.project_name.clone(),
         };
-        let request = self.construct_request(request).await?;
-        self.service.commit(request).await?;
+        let response = self
+            .call(rpc, retry, request, |mut service, request| async move {
+                service.commit(request).await
+            })
+            .await?;
+        convert_mutation_results(response.mutation_results)
+    }
 
-        Ok(())
+    pub async fn query(
+        &mut self,
+        query: Query,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<Vec<Entity>, Error> {
+        Ok(self.read_page(query, None, transaction).await?.items)
     }
 
-    pub async fn query(&mut self, query: Query) -> Result<Vec<Entity>, Error> {
-        let mut output = Vec::new();
+    // NOTE: limitが無いと種類の全件を1ページとして読んでしまうので、ページングではlimitを必須にする。
+    // cursorを渡した場合、offsetは前のページで適用済みとして無視する
+    pub async fn query_page(
+        &mut self,
+        query: Query,
+        cursor: Option<Cursor>,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<QueryPage<Entity>, Error> {
+        if query.limit.is_none() {
+            return Err(Error::InvalidQuery("paged query needs a limit".into()));
+        }
+        self.read_page(query, cursor, transaction).await
+    }
 
-        let mut cur_query = query.clone();
-        let mut cursor = Vec::new();
+    async fn read_page(
+        &mut self,
+        mut query: Query,
+        cursor: Option<Cursor>,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<QueryPage<Entity>, Error> {
+        let mut items = Vec::new();
+        let mut start_cursor = match cursor {
+            Some(cursor) => {
+                query.offset = 0;
+                cursor.0
+            }
+            None => Vec::new(),
+        };
         loop {
-            let projection = cur_query
-                .projections
-                .into_iter()
-                .map(|name| api::Projection {
-                    property: Some(api::PropertyReference { name }),
-                })
-                .collect();
-            let filter = convert_filter(self.project_name.as_str(), cur_query.filters);
-            let order = cur_query
-                .ordering
-                .into_iter()
-                .map(|order| {
-                    use api::property_order::Direction;
-                    let (name, direction) = match order {
-                        Order::Asc(name) => (name, Direction::Ascending),
-                        Order::Desc(name) => (name, Direction::Descending),
-                    };
-                    api::PropertyOrder {
-                        property: Some(api::PropertyReference { name }),
-                        direction: direction as i32,
-                    }
-                })
-                .collect();
-            let api_query = api::Query {
-                kind: vec![api::KindExpression {
-                    name: cur_query.kind,
-                }],
-                projection,
-                filter,
-                order,
-                offset: cur_query.offset,
-                limit: cur_query.limit,
-                start_cursor: cursor,
-                end_cursor: Vec::new(),
-                distinct_on: cur_query
-                    .distinct_on
-                    .into_iter()
-                    .map(|name| api::PropertyReference { name })
-                    .collect(),
-            };
-            let request = api::RunQueryRequest {
-                partition_id: Some(api::PartitionId {
-                    project_id: self.project_name.clone(),
-                    namespace_id: cur_query.namespace.unwrap_or_else(String::new),
-                }),
-                query_type: Some(api::run_query_request::QueryType::Query(api_query)),
-                read_options: Some({
-                    api::ReadOptions {
-                        consistency_type: Some(ConsistencyType::ReadConsistency(
-                            if cur_query.eventual {
-                                ReadConsistency::Eventual as i32
-                            } else {
-                                ReadConsistency::Strong as i32
-                            },
-                        )),
-                    }
-                }),
-                project_id: self.project_name.clone(),
-            };
-            let request = self.construct_request(request).await?;
-            let results = self.service.run_query(request).await?;
-            let results = results.into_inner().batch.unwrap();
+            let batch = self
+                .run_query(&mut query, start_cursor, transaction.as_deref())
+                .await?;
+            let more_results = batch.more_results;
+            items.extend(batch.entities);
+            start_cursor = batch.end_cursor;
+            if more_results != MoreResultsType::NotFinished as i32 {
+                let cursor = if more_results == MoreResultsType::NoMoreResults as i32 {
+                    None
+                } else {
+                    Some(Cursor(start_cursor))
+                };
+                break Ok(QueryPage { items, cursor });
+            }
+        }
+    }
 
-            output.extend(
-                results
-                    .entity_results
-                    .into_iter()
-                    .map(|el| Entity::from(el.entity.unwrap())),
-            );
+    pub fn query_stream(&self, query: Query) -> impl Stream<Item = Result<Entity, Error>> {
+        stream::try_unfold(
+            (self.clone(), query, Some(Vec::new()), VecDeque::new()),
+            |(mut client, mut query, mut start_cursor, mut buffer)| async move {
+                loop {
+                    if let Some(entity) = buffer.pop_front() {
+                        return Ok(Some((entity, (client, query, start_cursor, buffer))));
+                    }
+                    let cursor = match start_cursor {
+                        Some(cursor) => cursor,
+                        None => return Ok(None),
+                    };
+                    let batch = client.run_query(&mut query, cursor, None).await?;
+                    buffer.extend(batch.entities);
+                    start_cursor = if batch.more_results == MoreResultsType::NotFinished as i32 {
+                        Some(batch.end_cursor)
+                    } else {
+                        None
+                    };
+                }
+            },
+        )
+    }
 
-            if results.more_results
-                != (api::query_result_batch::MoreResultsType::NotFinished as i32)
-            {
-                break Ok(output);
+    pub async fn count(
+        &mut self,
+        query: Query,
+        transaction: Option<prost::alloc::vec::Vec<u8>>,
+    ) -> Result<i64, Error> {
+        query.validate()?;
+        match self.run_count_query(&query, transaction.as_deref()).await {
+            // NOTE: エミュレータが集計クエリに対応していない場合は、keys-onlyクエリの件数で代用する
+            Err(Error::Status(status)) if status.code() == tonic::Code::Unimplemented => {
+                Ok(self.query(query.keys_only(), transaction).await?.len() as i64)
             }
+            result => result,
+        }
+    }
 
-            cur_query = query.clone();
-            cursor = results.end_cursor;
+    async fn run_count_query(
+        &mut self,
+        query: &Query,
+        transaction: Option<&[u8]>,
+    ) -> Result<i64, Error> {
+        use api::aggregation_query::aggregation::{Count, Operator};
+
+        const ALIAS: &str = "count";
+        let aggregation_query = api::AggregationQuery {
+            query_type: Some(api::aggregation_query::QueryType::NestedQuery(
+                convert_query(&self.project_name, query, Vec::new()),
+            )),
+            aggregations: vec![api::aggregation_query::Aggregation {
+                operator: Some(Operator::Count(Count { up_to: None })),
+                alias: String::from(ALIAS),
+            }],
+        };
+        let request = api::RunAggregationQueryRequest {
+            partition_id: Some(convert_partition_id(&self.project_name, query)),
+            query_type: Some(
+                api::run_aggregation_query_request::QueryType::AggregationQuery(aggregation_query),
+            ),
+            read_options: Some(convert_read_options(query, transaction)),
+            project_id: self.project_name.clone(),
+        };
+        let rpc = Rpc::new("run_aggregation_query").query(query);
+        let retry = Retry::read(transaction.is_some());
+        let response = self
+            .call(rpc, retry, request, |mut service, request| async move {
+                service.run_aggregation_query(request).await
+            })
+            .await?;
+        let count = response
+            .batch
+            .and_then(|batch| batch.aggregation_results.into_iter().next())
+            .and_then(|mut result| result.aggregate_properties.remove(ALIAS))
+            .and_then(|value| value.value_type);
+        match count {
+            Some(ValueType::IntegerValue(count)) => Ok(count),
+            _ => Err(Error::Convert(ConvertError::MissingProperty(String::from(
+                ALIAS,
+            )))),
         }
     }
+
+    // NOTE: 次のバッチに向けて、queryのoffsetとlimitを消費した分だけ減らす
+    async fn run_query(
+        &mut self,
+        query: &mut Query,
+        start_cursor: Vec<u8>,
+        transaction: Option<&[u8]>,
+    ) -> Result<QueryBatch, Error> {
+        query.validate()?;
+        let request = api::RunQueryRequest {
+            partition_id: Some(convert_partition_id(&self.project_name, query)),
+            query_type: Some(api::run_query_request::QueryType::Query(convert_query(
+                &self.project_name,
+                query,
+                start_cursor,
+            ))),
+            read_options: Some(convert_read_options(query, transaction)),
+            project_id: self.project_name.clone(),
+        };
+        let rpc = Rpc::new("run_query").query(query);
+        let retry = Retry::read(transaction.is_some());
+        let results = self
+            .call(rpc, retry, request, |mut service, request| async move {
+                service.run_query(request).await
+            })
+            .await?;
+        let results = results.batch.unwrap();
+
+        query.offset = (query.offset - results.skipped_results).max(0);
+        if let Some(limit) = query.limit.as_mut() {
+            *limit -= results.entity_results.len() as i32;
+        }
+        Ok(QueryBatch {
+            entities: results
+                .entity_results
+                .into_iter()
+                .map(convert_entity_result)
+                .collect(),
+            end_cursor: results.end_cursor,
+            more_results: results.more_results,
+        })
+    }
+}
+
+/// NOTE: found・missingはどちらも要求したキーの順に並ぶ
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult<T> {
+    pub found: Vec<(Key, T)>,
+    pub missing: Vec<Key>,
+}
+
+struct QueryBatch {
+    entities: Vec<Entity>,
+    end_cursor: Vec<u8>,
+    more_results: i32,
 }
 
 pub(crate) fn convert_key(project_id: &str, key: &Key) -> api::Key {
@@ -349,13 +610,17 @@ pub(crate) fn convert_key(project_id: &str, key: &Key) -> api::Key {
 
 fn convert_entity(project_name: &str, entity: Entity) -> api::Entity {
     let key = convert_key(project_name, &entity.key);
+    let indexed = entity.indexed;
     let properties = match entity.properties {
         Value::Entity(properties) => properties,
         _ => panic!("unexpected non-entity datastore value"),
     };
     let properties = properties
         .into_iter()
-        .map(|(k, v)| (k, convert_value(project_name, v)))
+        .map(|(k, v)| {
+            let value = convert_property(project_name, &indexed, &k, v, false);
+            (k, value)
+        })
         .collect();
     api::Entity {
         key: Some(key),
@@ -363,8 +628,67 @@ fn convert_entity(project_name: &str, entity: Entity) -> api::Entity {
     }
 }
 
+// NOTE: 配列値自体にはexclude_from_indexesを付けられないので要素に付け、
+// インデックスしない入れ子のエンティティは、そのプロパティも全てインデックスしない
+fn convert_property(
+    project_name: &str,
+    indexed: &HashMap<String, bool>,
+    path: &str,
+    value: Value,
+    parent_excluded: bool,
+) -> api::Value {
+    let explicit = indexed.get(path).copied();
+    let value_type = match value {
+        Value::Array(values) => {
+            return api::Value {
+                meaning: 0,
+                exclude_from_indexes: false,
+                value_type: Some(ValueType::ArrayValue(api::ArrayValue {
+                    values: values
+                        .into_iter()
+                        .map(|value| {
+                            let excluded = parent_excluded
+                                || super::entity::value_excluded_from_indexes(&value, explicit);
+                            let mut value =
+                                convert_property(project_name, indexed, path, value, excluded);
+                            value.exclude_from_indexes = excluded;
+                            value
+                        })
+                        .collect(),
+                })),
+            }
+        }
+        Value::Entity(properties) => {
+            let excluded = parent_excluded || explicit != Some(true);
+            return api::Value {
+                meaning: 0,
+                exclude_from_indexes: excluded,
+                value_type: Some(ValueType::EntityValue(api::Entity {
+                    key: None,
+                    properties: properties
+                        .into_iter()
+                        .map(|(k, v)| {
+                            let path = format!("{}.{}", path, k);
+                            let value = convert_property(project_name, indexed, &path, v, excluded);
+                            (k, value)
+                        })
+                        .collect(),
+                })),
+            };
+        }
+        value => value,
+    };
+    let exclude_from_indexes =
+        parent_excluded || super::entity::value_excluded_from_indexes(&value_type, explicit);
+    api::Value {
+        exclude_from_indexes,
+        ..convert_value(project_name, value_type)
+    }
+}
+
 fn convert_value(project_name: &str, value: Value) -> api::Value {
     let value_type = match value {
+        Value::Null => ValueType::NullValue(0),
         Value::Boolean(val) => ValueType::BooleanValue(val),
         Value::Integer(val) => ValueType::IntegerValue(val),
         Value::Double(val) => ValueType::DoubleValue(val),
@@ -402,6 +726,17 @@ fn convert_value(project_name: &str, value: Value) -> api::Value {
     }
 }
 
+pub fn generate_delete_mutations<T, I>(project_name: &str, keys: I) -> Vec<api::Mutation>
+where
+    I: IntoIterator<Item = T>,
+    T: Borrow<Key>,
+{
+    keys.into_iter()
+        .map(Mutation::delete)
+        .map(|mutation| convert_mutation(project_name, mutation))
+        .collect()
+}
+
 pub fn generate_mutations<T, I>(
     project_name: impl AsRef<str>,
     entities: I,
@@ -412,56 +747,197 @@ where
 {
     let mutations: Vec<api::Mutation> = entities
         .into_iter()
-        .map(IntoEntity::into_entity)
-        .map(|entity| match entity {
-            Err(e) => Err(e),
-            Ok(entity) => {
-                let is_incomplete = entity.key.is_incomplete();
-                let entity = convert_entity(project_name.as_ref(), entity);
-                Ok(api::Mutation {
-                    operation: if is_incomplete {
-                        Some(api::mutation::Operation::Insert(entity))
-                    } else {
-                        Some(api::mutation::Operation::Upsert(entity))
-                    },
-                    conflict_detection_strategy: None,
+        .map(Mutation::put)
+        .map(|mutation| mutation.map(|mutation| convert_mutation(project_name.as_ref(), mutation)))
+        .collect::<Result<_, _>>()?;
+    Ok(mutations)
+}
+
+pub fn convert_mutation(project_name: &str, mutation: Mutation) -> api::Mutation {
+    use api::mutation::{ConflictDetectionStrategy, Operation as ApiOperation};
+
+    let operation = match mutation.operation {
+        Operation::Insert(entity) => ApiOperation::Insert(convert_entity(project_name, entity)),
+        Operation::Update(entity) => ApiOperation::Update(convert_entity(project_name, entity)),
+        Operation::Upsert(entity) => ApiOperation::Upsert(convert_entity(project_name, entity)),
+        Operation::Delete(key) => ApiOperation::Delete(convert_key(project_name, &key)),
+    };
+    api::Mutation {
+        operation: Some(operation),
+        conflict_detection_strategy: mutation
+            .base_version
+            .map(ConflictDetectionStrategy::BaseVersion),
+    }
+}
+
+// NOTE: base_versionが一致しなかったミューテーションは適用されないので、エラーにする
+pub(crate) fn convert_mutation_results(
+    results: Vec<api::MutationResult>,
+) -> Result<Vec<MutationResult>, Error> {
+    results
+        .into_iter()
+        .map(|result| {
+            let key = result.key.map(Key::from);
+            if result.conflict_detected {
+                Err(Error::Conflict(match &key {
+                    Some(key) => format!("{:?}", key),
+                    None => String::from("base version is outdated"),
+                }))
+            } else {
+                Ok(MutationResult {
+                    key,
+                    version: result.version,
                 })
             }
         })
-        .collect::<Result<_, _>>()?;
-    Ok(mutations)
+        .collect()
 }
 
-fn convert_filter(project_name: &str, filters: Vec<Filter>) -> Option<api::Filter> {
-    use api::filter::FilterType;
+// NOTE: upsertだけのコミットは、送り直しても同じ状態になるので再試行する。
+// insertは最初の試行が適用されていると、不完全キーなら重複して作られ、完全キーならALREADY_EXISTSになる。
+// base_versionを指定したものも、最初の試行が適用されていると競合になるので再試行しない
+fn is_retryable_commit(mutations: &[api::Mutation]) -> bool {
+    mutations.iter().all(|mutation| {
+        matches!(
+            mutation.operation,
+            Some(api::mutation::Operation::Upsert(_))
+        ) && mutation.conflict_detection_strategy.is_none()
+    })
+}
 
-    if !filters.is_empty() {
-        let filters = filters
-            .into_iter()
-            .map(|filter| {
-                use api::property_filter::Operator;
-                let (name, op, value) = match filter {
-                    Filter::Equal(name, value) => (name, Operator::Equal, value),
-                    Filter::GreaterThan(name, value) => (name, Operator::GreaterThan, value),
-                    Filter::LesserThan(name, value) => (name, Operator::LessThan, value),
-                    Filter::GreaterThanOrEqual(name, value) => {
-                        (name, Operator::GreaterThanOrEqual, value)
-                    }
-                    Filter::LesserThanEqual(name, value) => {
-                        (name, Operator::LessThanOrEqual, value)
-                    }
-                };
+/// NOTE: RPCが失敗したときに、同じリクエストを送り直してよい範囲
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Retry {
+    // NOTE: 送り直すと二重に適用されうるもの
+    Never,
+    // NOTE: トランザクションを使うRPC。ABORTEDのトランザクションは送り直しても使えないので、
+    // 呼び出し元に返してトランザクションごとやり直させる
+    Transient,
+    Always,
+}
 
-                api::Filter {
-                    filter_type: Some(FilterType::PropertyFilter(api::PropertyFilter {
-                        op: op as i32,
-                        property: Some(api::PropertyReference { name }),
-                        value: Some(convert_value(project_name, value)),
-                    })),
-                }
+impl Retry {
+    pub(crate) fn read(in_transaction: bool) -> Retry {
+        if in_transaction {
+            Retry::Transient
+        } else {
+            Retry::Always
+        }
+    }
+
+    fn allows(self, err: &Error) -> bool {
+        match self {
+            Retry::Never => false,
+            Retry::Transient => err.is_transient(),
+            Retry::Always => err.is_transient() || matches!(err, Error::Aborted(_)),
+        }
+    }
+}
+
+fn into_chunks<T>(items: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut chunks = vec![];
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(size).collect());
+    }
+    chunks
+}
+
+fn convert_entity_result(result: api::EntityResult) -> Entity {
+    Entity::from(result.entity.unwrap()).with_version(Some(result.version))
+}
+
+fn convert_partition_id(project_name: &str, query: &Query) -> api::PartitionId {
+    api::PartitionId {
+        project_id: String::from(project_name),
+        namespace_id: query.namespace.clone().unwrap_or_default(),
+    }
+}
+
+fn convert_read_options(query: &Query, transaction: Option<&[u8]>) -> api::ReadOptions {
+    api::ReadOptions {
+        consistency_type: Some(match transaction {
+            Some(transaction) => ConsistencyType::Transaction(transaction.to_vec()),
+            None => ConsistencyType::ReadConsistency(if query.eventual {
+                ReadConsistency::Eventual as i32
+            } else {
+                ReadConsistency::Strong as i32
+            }),
+        }),
+    }
+}
+
+fn convert_query(project_name: &str, query: &Query, start_cursor: Vec<u8>) -> api::Query {
+    let projection = if query.keys_only {
+        vec![String::from("__key__")]
+    } else {
+        query.projections.clone()
+    };
+    let order = query
+        .ordering
+        .iter()
+        .map(|order| {
+            use api::property_order::Direction;
+            let (name, direction) = match order {
+                Order::Asc(name) => (name, Direction::Ascending),
+                Order::Desc(name) => (name, Direction::Descending),
+            };
+            api::PropertyOrder {
+                property: Some(api::PropertyReference { name: name.clone() }),
+                direction: direction as i32,
+            }
+        })
+        .collect();
+    api::Query {
+        kind: vec![api::KindExpression {
+            name: query.kind.clone(),
+        }],
+        projection: projection
+            .into_iter()
+            .map(|name| api::Projection {
+                property: Some(api::PropertyReference { name }),
             })
-            .collect();
+            .collect(),
+        filter: convert_filter(project_name, query.ancestor.clone(), query.filters.clone()),
+        order,
+        offset: query.offset,
+        limit: query.limit,
+        start_cursor,
+        end_cursor: Vec::new(),
+        distinct_on: query
+            .distinct_on
+            .iter()
+            .map(|name| api::PropertyReference { name: name.clone() })
+            .collect(),
+    }
+}
 
+fn convert_filter(
+    project_name: &str,
+    ancestor: Option<Key>,
+    filters: Vec<Filter>,
+) -> Option<api::Filter> {
+    use api::filter::FilterType;
+    use api::property_filter::Operator;
+
+    let ancestor = ancestor.map(|key| api::Filter {
+        filter_type: Some(FilterType::PropertyFilter(api::PropertyFilter {
+            op: Operator::HasAncestor as i32,
+            property: Some(api::PropertyReference {
+                name: String::from("__key__"),
+            }),
+            value: Some(convert_value(project_name, Value::Key(key))),
+        })),
+    });
+    if ancestor.is_some() || !filters.is_empty() {
+        let filters = ancestor
+            .into_iter()
+            .chain(
+                filters
+                    .into_iter()
+                    .map(|filter| convert_query_filter(project_name, filter)),
+            )
+            .collect();
         Some(api::Filter {
             filter_type: Some(FilterType::CompositeFilter(api::CompositeFilter {
                 op: api::composite_filter::Operator::And as i32,
@@ -472,3 +948,184 @@ fn convert_filter(project_name: &str, filters: Vec<Filter>) -> Option<api::Filte
         None
     }
 }
+
+fn convert_composite_filter(
+    project_name: &str,
+    op: api::composite_filter::Operator,
+    filters: Vec<Filter>,
+) -> api::Filter {
+    api::Filter {
+        filter_type: Some(api::filter::FilterType::CompositeFilter(
+            api::CompositeFilter {
+                op: op as i32,
+                filters: filters
+                    .into_iter()
+                    .map(|filter| convert_query_filter(project_name, filter))
+                    .collect(),
+            },
+        )),
+    }
+}
+
+fn convert_query_filter(project_name: &str, filter: Filter) -> api::Filter {
+    use api::composite_filter::Operator as CompositeOperator;
+    use api::filter::FilterType;
+    use api::property_filter::Operator;
+
+    let (name, op, value) = match filter {
+        Filter::And(filters) => {
+            return convert_composite_filter(project_name, CompositeOperator::And, filters)
+        }
+        Filter::Or(filters) => {
+            return convert_composite_filter(project_name, CompositeOperator::Or, filters)
+        }
+        Filter::Equal(name, value) => (name, Operator::Equal, value),
+        Filter::GreaterThan(name, value) => (name, Operator::GreaterThan, value),
+        Filter::LesserThan(name, value) => (name, Operator::LessThan, value),
+        Filter::GreaterThanOrEqual(name, value) => (name, Operator::GreaterThanOrEqual, value),
+        Filter::LesserThanEqual(name, value) => (name, Operator::LessThanOrEqual, value),
+        Filter::NotEqual(name, value) => (name, Operator::NotEqual, value),
+        Filter::In(name, values) => (name, Operator::In, Value::Array(values)),
+        Filter::NotIn(name, values) => (name, Operator::NotIn, Value::Array(values)),
+    };
+    api::Filter {
+        filter_type: Some(FilterType::PropertyFilter(api::PropertyFilter {
+            op: op as i32,
+            property: Some(api::PropertyReference { name }),
+            value: Some(convert_value(project_name, value)),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use test_case::test_case;
+
+    fn client() -> Client {
+        Client::from_without_credentials("project")
+            .unwrap()
+            .retry_policy(RetryPolicy::new(
+                3,
+                Duration::from_millis(0),
+                Duration::from_millis(0),
+            ))
+            .timeout(Duration::from_millis(50))
+    }
+
+    #[test_case(Retry::Always, vec![tonic::Code::Unavailable, tonic::Code::Internal] => (true, 3))]
+    #[test_case(Retry::Always, vec![tonic::Code::Aborted; 3] => (false, 3))]
+    #[test_case(Retry::Transient, vec![tonic::Code::DeadlineExceeded] => (true, 2))]
+    #[test_case(Retry::Transient, vec![tonic::Code::Aborted] => (false, 1))]
+    #[test_case(Retry::Never, vec![tonic::Code::Unavailable] => (false, 1))]
+    #[test_case(Retry::Always, vec![tonic::Code::InvalidArgument] => (false, 1))]
+    #[test_case(Retry::Always, vec![] => (true, 1))]
+    fn call_retries_only_retryable_errors(retry: Retry, codes: Vec<tonic::Code>) -> (bool, usize) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let result = async_std::task::block_on(async {
+            client()
+                .call(Rpc::new("test"), retry, (), |_, _| {
+                    let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                    let result = match codes.get(attempt) {
+                        Some(code) => Err(tonic::Status::new(*code, "test")),
+                        None => Ok(Response::new(())),
+                    };
+                    futures_util::future::ready(result)
+                })
+                .await
+        });
+        (result.is_ok(), attempts.load(Ordering::SeqCst))
+    }
+
+    #[async_std::test]
+    async fn call_gives_up_when_deadline_exceeded() {
+        let result = client()
+            .call(Rpc::new("test"), Retry::Never, (), |_, _| async {
+                async_std::task::sleep(Duration::from_secs(10)).await;
+                Ok(Response::new(()))
+            })
+            .await;
+        assert!(matches!(result, Err(Error::DeadlineExceeded(_))));
+    }
+
+    #[test_case(vec![Mutation::upsert(entity()).unwrap()] => true)]
+    #[test_case(vec![Mutation::upsert(entity()).unwrap(), Mutation::put(entity()).unwrap()] => true)]
+    #[test_case(vec![Mutation::insert(entity()).unwrap()] => false)]
+    #[test_case(vec![Mutation::upsert(entity()).unwrap().base_version(1)] => false)]
+    #[test_case(vec![Mutation::upsert(entity()).unwrap(), Mutation::update(entity()).unwrap()] => false)]
+    #[test_case(vec![Mutation::delete(entity().key())] => false)]
+    fn is_retryable_commit_works(mutations: Vec<Mutation>) -> bool {
+        let mutations = mutations
+            .into_iter()
+            .map(|mutation| convert_mutation("project", mutation))
+            .collect::<Vec<_>>();
+        is_retryable_commit(&mutations)
+    }
+
+    fn entity() -> Entity {
+        let mut nested = HashMap::new();
+        nested.insert(String::from("name"), Value::Strings("inner".into()));
+        let mut properties = HashMap::new();
+        properties.insert(String::from("short"), Value::Strings("x".into()));
+        properties.insert(
+            String::from("long"),
+            Value::Strings("x".repeat(Value::MAX_INDEXED_BYTES + 1)),
+        );
+        properties.insert(String::from("blob"), Value::Blob(vec![1, 2, 3]));
+        properties.insert(String::from("nested"), Value::Entity(nested.clone()));
+        properties.insert(String::from("indexed_nested"), Value::Entity(nested));
+        properties.insert(
+            String::from("tags"),
+            Value::Array(vec![
+                Value::Strings("a".into()),
+                Value::Strings("x".repeat(Value::MAX_INDEXED_BYTES + 1)),
+            ]),
+        );
+        Entity::new(Key::new("Indexed").id("e1"), properties)
+            .unwrap()
+            .include_in_indexes(&["long", "indexed_nested"])
+    }
+
+    fn excluded(value: &api::Value) -> Vec<bool> {
+        match &value.value_type {
+            Some(ValueType::ArrayValue(array)) => array
+                .values
+                .iter()
+                .map(|v| v.exclude_from_indexes)
+                .collect(),
+            _ => vec![value.exclude_from_indexes],
+        }
+    }
+
+    fn nested_excluded(value: &api::Value, name: &str) -> bool {
+        match &value.value_type {
+            Some(ValueType::EntityValue(entity)) => entity.properties[name].exclude_from_indexes,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn convert_entity_uses_index_defaults() {
+        let converted = convert_entity("project", entity());
+        let properties = &converted.properties;
+        assert_eq!(excluded(&properties["short"]), vec![false]);
+        assert_eq!(excluded(&properties["long"]), vec![true]);
+        assert_eq!(excluded(&properties["blob"]), vec![true]);
+        assert_eq!(excluded(&properties["nested"]), vec![true]);
+        assert!(nested_excluded(&properties["nested"], "name"));
+        assert_eq!(excluded(&properties["indexed_nested"]), vec![false]);
+        assert!(!nested_excluded(&properties["indexed_nested"], "name"));
+        assert_eq!(excluded(&properties["tags"]), vec![false, true]);
+    }
+
+    #[test]
+    fn index_settings_round_trip_from_api_entity() {
+        let converted = convert_entity("project", entity().exclude_from_indexes(&["short"]));
+        let restored = Entity::from(converted.clone());
+        assert!(restored.is_excluded_from_indexes("short"));
+        assert!(restored.is_excluded_from_indexes("nested.name"));
+        assert!(!restored.is_excluded_from_indexes("indexed_nested.name"));
+        assert_eq!(convert_entity("project", restored), converted);
+    }
+}
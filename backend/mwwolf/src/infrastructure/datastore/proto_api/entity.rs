@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use super::api;
 use super::error::ConvertError;
 use super::{IntoValue, Key, Value};
@@ -6,13 +8,22 @@ use super::{IntoValue, Key, Value};
 pub struct Entity {
     pub(crate) key: Key,
     pub(crate) properties: Value,
+    // NOTE: 明示されたインデックスの有無。入れ子のエンティティのプロパティは `a.b` で表す
+    pub(crate) indexed: HashMap<String, bool>,
+    // NOTE: 読み込んだときのバージョン。update・upsertするときに競合の検出に使う
+    pub(crate) version: Option<i64>,
 }
 
 impl Entity {
     pub fn new(key: Key, value: impl IntoValue) -> Result<Entity, ConvertError> {
         let properties = value.into_value();
         match properties {
-            Value::Entity(_) => Ok(Entity { key, properties }),
+            Value::Entity(_) => Ok(Entity {
+                key,
+                properties,
+                indexed: HashMap::new(),
+                version: None,
+            }),
             _ => Err(ConvertError::UnexpectedPropertyType {
                 expected: String::from("entity"),
                 got: String::from(properties.type_name()),
@@ -39,6 +50,78 @@ impl Entity {
     pub fn properties_mut(&mut self) -> &mut Value {
         &mut self.properties
     }
+
+    pub fn version(&self) -> Option<i64> {
+        self.version
+    }
+
+    pub fn with_version(mut self, version: Option<i64>) -> Entity {
+        self.version = version;
+        self
+    }
+
+    pub fn exclude_from_indexes(mut self, names: &[&str]) -> Entity {
+        self.indexed
+            .extend(names.iter().map(|name| (String::from(*name), false)));
+        self
+    }
+
+    // NOTE: Blobや入れ子のエンティティなど、既定でインデックスしない値をインデックスする
+    pub fn include_in_indexes(mut self, names: &[&str]) -> Entity {
+        self.indexed
+            .extend(names.iter().map(|name| (String::from(*name), true)));
+        self
+    }
+
+    // NOTE: インデックスしない入れ子のエンティティの中のプロパティも、インデックスされない
+    pub fn is_excluded_from_indexes(&self, name: &str) -> bool {
+        let mut values = vec![&self.properties];
+        let mut path = String::new();
+        for (depth, segment) in name.split('.').enumerate() {
+            if depth > 0 {
+                if values.iter().any(|value| matches!(value, Value::Entity(_)))
+                    && self.indexed.get(&path) != Some(&true)
+                {
+                    return true;
+                }
+                path.push('.');
+            }
+            path.push_str(segment);
+            values = values
+                .into_iter()
+                .filter_map(|value| match value {
+                    Value::Entity(properties) => properties.get(segment),
+                    _ => None,
+                })
+                .flat_map(|value| match value {
+                    Value::Array(values) => values.iter().collect(),
+                    value => vec![value],
+                })
+                .collect();
+        }
+        let explicit = self.indexed.get(name).copied();
+        if values.is_empty() {
+            return explicit == Some(false);
+        }
+        values
+            .into_iter()
+            .all(|value| value_excluded_from_indexes(value, explicit))
+    }
+}
+
+// NOTE: 1500バイトを超える文字列はインデックスできないので、指定に関わらず除外する
+pub(crate) fn value_excluded_from_indexes(value: &Value, indexed: Option<bool>) -> bool {
+    match (value, indexed) {
+        (Value::Strings(value), _) if value.len() > Value::MAX_INDEXED_BYTES => true,
+        (Value::Array(values), _) => {
+            !values.is_empty()
+                && values
+                    .iter()
+                    .all(|value| value_excluded_from_indexes(value, indexed))
+        }
+        (_, Some(indexed)) => !indexed,
+        (value, None) => value.excluded_from_indexes_by_default(),
+    }
 }
 
 pub trait IntoEntity {
@@ -54,6 +137,12 @@ impl IntoEntity for Entity {
     }
 }
 
+impl FromEntity for Entity {
+    fn from_entity(e: Entity) -> Result<Entity, ConvertError> {
+        Ok(e)
+    }
+}
+
 impl<V> IntoEntity for (Key, V)
 where
     V: IntoValue,
@@ -67,14 +156,48 @@ where
 impl From<api::Entity> for Entity {
     fn from(entity: api::Entity) -> Entity {
         let key = Key::from(entity.key.unwrap());
-        let properties = entity.properties;
-
-        let properties = properties
+        let mut indexed = HashMap::new();
+        for (name, value) in entity.properties.iter() {
+            collect_indexed(name, value, &mut indexed);
+        }
+        let properties = entity
+            .properties
             .into_iter()
             .map(|(k, v)| (k, Value::from(v.value_type.unwrap())))
             .collect();
         let properties = Value::Entity(properties);
 
-        Entity { key, properties }
+        Entity {
+            key,
+            properties,
+            indexed,
+            version: None,
+        }
+    }
+}
+
+// NOTE: 書き戻したときに同じ指定になるよう、読み込んだ値のインデックス有無を全て記録する
+// 配列は要素ごとにexclude_from_indexesが付くので、全ての要素で揃っている場合だけ記録する
+fn collect_indexed(path: &str, value: &api::Value, indexed: &mut HashMap<String, bool>) {
+    match &value.value_type {
+        Some(api::value::ValueType::ArrayValue(array)) => {
+            let excluded = array
+                .values
+                .iter()
+                .map(|v| v.exclude_from_indexes)
+                .collect::<Vec<_>>();
+            if !excluded.is_empty() && excluded.iter().all(|e| *e == excluded[0]) {
+                indexed.insert(String::from(path), !excluded[0]);
+            }
+        }
+        Some(api::value::ValueType::EntityValue(entity)) => {
+            indexed.insert(String::from(path), !value.exclude_from_indexes);
+            for (name, value) in entity.properties.iter() {
+                collect_indexed(&format!("{}.{}", path, name), value, indexed);
+            }
+        }
+        _ => {
+            indexed.insert(String::from(path), !value.exclude_from_indexes);
+        }
     }
 }
@@ -5,8 +5,29 @@ use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("entity not found: {0}")]
+    NotFound(tonic::Status),
+
+    #[error("entity already exists: {0}")]
+    AlreadyExists(tonic::Status),
+
+    #[error("aborted by contention: {0}")]
+    Aborted(tonic::Status),
+
+    #[error("deadline exceeded: {0}")]
+    DeadlineExceeded(tonic::Status),
+
+    #[error("service unavailable: {0}")]
+    Unavailable(tonic::Status),
+
+    #[error("invalid argument: {0}")]
+    InvalidArgument(tonic::Status),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(tonic::Status),
+
     #[error("unexpected status from GCP: {0}")]
-    Status(#[from] tonic::Status),
+    Status(tonic::Status),
 
     #[error("transport error: {0}")]
     Transport(#[from] tonic::transport::Error),
@@ -27,10 +48,55 @@ pub enum Error {
     #[error("conversion error: {0}")]
     Convert(#[from] ConvertError),
 
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+
+    #[error("conflict detected: {0}")]
+    Conflict(String),
+
     #[error("authentication error: {0}")]
     Auth(#[from] AuthError),
 }
 
+// NOTE: 再試行の判断に使えるよう、ステータスコードをエラーの種類に分ける
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Error {
+        use tonic::Code;
+
+        match status.code() {
+            Code::NotFound => Error::NotFound(status),
+            Code::AlreadyExists => Error::AlreadyExists(status),
+            Code::Aborted => Error::Aborted(status),
+            Code::DeadlineExceeded => Error::DeadlineExceeded(status),
+            Code::Unavailable | Code::Internal | Code::ResourceExhausted => {
+                Error::Unavailable(status)
+            }
+            Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+                Error::InvalidArgument(status)
+            }
+            Code::PermissionDenied | Code::Unauthenticated => Error::PermissionDenied(status),
+            _ => Error::Status(status),
+        }
+    }
+}
+
+impl Error {
+    // NOTE: insertで既にエンティティがある場合と、base_versionが一致しない場合
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Error::Conflict(_) | Error::AlreadyExists(_))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound(_))
+    }
+
+    // NOTE: 同じリクエストを送り直せば成功しうる一時的な障害。冪等でないRPCは再試行しないこと。
+    // ABORTEDはトランザクションの中では無効になったことを表すので、ここには含めない
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::DeadlineExceeded(_) | Error::Unavailable(_))
+    }
+}
+
 #[derive(Debug, Error, PartialEq)]
 pub enum ConvertError {
     #[error("expected property `{0}` was missing")]
@@ -38,6 +104,9 @@ pub enum ConvertError {
 
     #[error("expected property type `{expected}`, got `{got}`")]
     UnexpectedPropertyType { expected: String, got: String },
+
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
 }
 
 #[derive(Debug, Error)]
@@ -53,4 +122,10 @@ pub enum AuthError {
 
     #[error("Hyper error: {0}")]
     Hyper(#[from] hyper::Error),
+
+    #[error("unexpected response from token endpoint: {0} {1}")]
+    UnexpectedResponse(u16, String),
+
+    #[error("unsupported credentials type: {0}")]
+    UnsupportedCredentials(String),
 }
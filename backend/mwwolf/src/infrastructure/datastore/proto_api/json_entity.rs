@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::error::ConvertError;
+use super::{Entity, Key, KeyID, Value};
+
+/// NOTE: エクスポート用のJSON表現。値の型が失われないよう、型名をタグにして持つ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonEntity {
+    key: JsonKey,
+    properties: BTreeMap<String, JsonValue>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    indexed: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    // NOTE: 祖先から順に並べる
+    path: Vec<JsonPathElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonPathElement {
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JsonValue {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Double(f64),
+    // NOTE: RFC3339形式のUTCの時刻をマイクロ秒まで持つ
+    Timestamp(String),
+    Key(JsonKey),
+    String(String),
+    // NOTE: 標準のbase64で持つ
+    Blob(String),
+    GeoPoint { latitude: f64, longitude: f64 },
+    Entity(BTreeMap<String, JsonValue>),
+    Array(Vec<JsonValue>),
+}
+
+impl JsonEntity {
+    pub fn to_line(&self) -> Result<String, ConvertError> {
+        json::to_string(self).map_err(|e| ConvertError::InvalidValue(e.to_string()))
+    }
+
+    pub fn from_line(line: &str) -> Result<JsonEntity, ConvertError> {
+        json::from_str(line).map_err(|e| ConvertError::InvalidValue(e.to_string()))
+    }
+}
+
+// NOTE: バージョンは書き戻すときの競合検出にしか使わないので、エクスポートしない
+impl TryFrom<Entity> for JsonEntity {
+    type Error = ConvertError;
+
+    fn try_from(entity: Entity) -> Result<JsonEntity, ConvertError> {
+        let properties = match entity.properties {
+            Value::Entity(properties) => convert_properties(properties)?,
+            value => {
+                return Err(ConvertError::UnexpectedPropertyType {
+                    expected: String::from("entity"),
+                    got: String::from(value.type_name()),
+                })
+            }
+        };
+        Ok(JsonEntity {
+            key: JsonKey::try_from(&entity.key)?,
+            properties,
+            indexed: entity.indexed.into_iter().collect(),
+        })
+    }
+}
+
+impl TryFrom<JsonEntity> for Entity {
+    type Error = ConvertError;
+
+    fn try_from(entity: JsonEntity) -> Result<Entity, ConvertError> {
+        let properties = entity
+            .properties
+            .into_iter()
+            .map(|(name, value)| Ok((name, Value::try_from(value)?)))
+            .collect::<Result<HashMap<_, _>, ConvertError>>()?;
+        Ok(Entity {
+            key: Key::try_from(entity.key)?,
+            properties: Value::Entity(properties),
+            indexed: entity.indexed.into_iter().collect(),
+            version: None,
+        })
+    }
+}
+
+impl TryFrom<&Key> for JsonKey {
+    type Error = ConvertError;
+
+    fn try_from(key: &Key) -> Result<JsonKey, ConvertError> {
+        let mut path = vec![];
+        let mut current = Some(key);
+        while let Some(key) = current {
+            let (id, name) = match &key.id {
+                KeyID::IntID(id) => (Some(*id), None),
+                KeyID::StringID(name) => (None, Some(name.clone())),
+                KeyID::Incomplete => {
+                    return Err(ConvertError::InvalidValue(format!(
+                        "incomplete key cannot be exported: {}",
+                        key.kind
+                    )))
+                }
+            };
+            path.push(JsonPathElement {
+                kind: key.kind.clone(),
+                id,
+                name,
+            });
+            current = key.get_parent();
+        }
+        path.reverse();
+        Ok(JsonKey {
+            namespace: key.namespace.clone(),
+            path,
+        })
+    }
+}
+
+impl TryFrom<JsonKey> for Key {
+    type Error = ConvertError;
+
+    fn try_from(key: JsonKey) -> Result<Key, ConvertError> {
+        let mut result: Option<Key> = None;
+        for element in key.path {
+            let id = match (element.id, element.name) {
+                (Some(id), None) => KeyID::IntID(id),
+                (None, Some(name)) => KeyID::StringID(name),
+                _ => {
+                    return Err(ConvertError::InvalidValue(format!(
+                        "key path element must have either id or name: {}",
+                        element.kind
+                    )))
+                }
+            };
+            let child = Key::new(element.kind).id(id);
+            result = Some(match result {
+                Some(parent) => child.parent(parent),
+                None => child,
+            });
+        }
+        let result =
+            result.ok_or_else(|| ConvertError::InvalidValue(String::from("key path is empty")))?;
+        Ok(match key.namespace {
+            Some(namespace) => result.namespace(namespace),
+            None => result,
+        })
+    }
+}
+
+fn convert_properties(
+    properties: HashMap<String, Value>,
+) -> Result<BTreeMap<String, JsonValue>, ConvertError> {
+    properties
+        .into_iter()
+        .map(|(name, value)| Ok((name, JsonValue::try_from(value)?)))
+        .collect()
+}
+
+impl TryFrom<Value> for JsonValue {
+    type Error = ConvertError;
+
+    fn try_from(value: Value) -> Result<JsonValue, ConvertError> {
+        Ok(match value {
+            Value::Null => JsonValue::Null,
+            Value::Boolean(value) => JsonValue::Boolean(value),
+            Value::Integer(value) => JsonValue::Integer(value),
+            // NOTE: JSONではNaNや無限大を表せない
+            Value::Double(value) if !value.is_finite() => {
+                return Err(ConvertError::InvalidValue(format!(
+                    "non-finite double cannot be exported: {}",
+                    value
+                )))
+            }
+            Value::Double(value) => JsonValue::Double(value),
+            Value::Timestamp(value) => {
+                JsonValue::Timestamp(value.to_rfc3339_opts(SecondsFormat::Micros, true))
+            }
+            Value::Key(key) => JsonValue::Key(JsonKey::try_from(&key)?),
+            Value::Strings(value) => JsonValue::String(value),
+            Value::Blob(value) => JsonValue::Blob(base64::encode(value)),
+            Value::GeoPoint(latitude, longitude) => JsonValue::GeoPoint {
+                latitude,
+                longitude,
+            },
+            Value::Entity(properties) => JsonValue::Entity(convert_properties(properties)?),
+            Value::Array(values) => JsonValue::Array(
+                values
+                    .into_iter()
+                    .map(JsonValue::try_from)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        })
+    }
+}
+
+impl TryFrom<JsonValue> for Value {
+    type Error = ConvertError;
+
+    fn try_from(value: JsonValue) -> Result<Value, ConvertError> {
+        Ok(match value {
+            JsonValue::Null => Value::Null,
+            JsonValue::Boolean(value) => Value::Boolean(value),
+            JsonValue::Integer(value) => Value::Integer(value),
+            JsonValue::Double(value) => Value::Double(value),
+            JsonValue::Timestamp(value) => Value::Timestamp(
+                DateTime::parse_from_rfc3339(&value)
+                    .map_err(|e| ConvertError::InvalidValue(format!("{}: {}", value, e)))?
+                    .with_timezone(&Utc),
+            ),
+            JsonValue::Key(key) => Value::Key(Key::try_from(key)?),
+            JsonValue::String(value) => Value::Strings(value),
+            JsonValue::Blob(value) => Value::Blob(
+                base64::decode(&value)
+                    .map_err(|e| ConvertError::InvalidValue(format!("{}: {}", value, e)))?,
+            ),
+            JsonValue::GeoPoint {
+                latitude,
+                longitude,
+            } => Value::GeoPoint(latitude, longitude),
+            JsonValue::Entity(properties) => Value::Entity(
+                properties
+                    .into_iter()
+                    .map(|(name, value)| Ok((name, Value::try_from(value)?)))
+                    .collect::<Result<HashMap<_, _>, ConvertError>>()?,
+            ),
+            JsonValue::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use test_case::test_case;
+
+    fn entity() -> Entity {
+        let parent = Key::new("Room").id("room1");
+        let mut nested = HashMap::new();
+        nested.insert(String::from("name"), Value::Strings(String::from("x")));
+        nested.insert(String::from("score"), Value::Double(1.5));
+        let mut properties = HashMap::new();
+        properties.insert(String::from("flag"), Value::Boolean(true));
+        properties.insert(String::from("none"), Value::Null);
+        properties.insert(String::from("count"), Value::Integer(i64::MAX));
+        properties.insert(
+            String::from("at"),
+            Value::Timestamp(Utc.timestamp(1_600_000_000, 123_456_000)),
+        );
+        properties.insert(
+            String::from("ref"),
+            Value::Key(parent.clone().namespace("ns")),
+        );
+        properties.insert(String::from("data"), Value::Blob(vec![0, 1, 255]));
+        properties.insert(String::from("point"), Value::GeoPoint(35.6, 139.7));
+        properties.insert(String::from("nested"), Value::Entity(nested));
+        properties.insert(
+            String::from("list"),
+            Value::Array(vec![Value::Integer(1), Value::Strings(String::from("a"))]),
+        );
+        Entity::new(
+            Key::new("Game").id(42).parent(parent).namespace("ns"),
+            Value::Entity(properties),
+        )
+        .unwrap()
+        .exclude_from_indexes(&["data"])
+        .include_in_indexes(&["nested"])
+    }
+
+    #[test]
+    fn json_entity_round_trip_works() {
+        let line = JsonEntity::try_from(entity().with_version(Some(3)))
+            .unwrap()
+            .to_line()
+            .unwrap();
+        let restored = Entity::try_from(JsonEntity::from_line(&line).unwrap()).unwrap();
+        assert_eq!(restored, entity());
+    }
+
+    #[test]
+    fn json_key_keeps_ancestors_and_namespace() {
+        let key =
+            JsonKey::try_from(&Key::new("Game").id(42).parent(Key::new("Room").id("r1"))).unwrap();
+        assert_eq!(
+            json::to_value(&key).unwrap(),
+            json::json!({"path": [{"kind": "Room", "name": "r1"}, {"kind": "Game", "id": 42}]})
+        );
+    }
+
+    #[test_case(r#"{"key":{"path":[]},"properties":{}}"# ; "empty key path")]
+    #[test_case(r#"{"key":{"path":[{"kind":"Room","id":1,"name":"x"}]},"properties":{}}"# ; "ambiguous key")]
+    #[test_case(r#"{"key":{"path":[{"kind":"Room","id":1}]},"properties":{"at":{"timestamp":"x"}}}"# ; "invalid timestamp")]
+    #[test_case(r#"{"key":{"path":[{"kind":"Room","id":1}]},"properties":{"at":{"unknown":1}}}"# ; "unknown type")]
+    fn json_entity_rejects_invalid_line(line: &str) {
+        let result = JsonEntity::from_line(line).and_then(Entity::try_from);
+        assert!(matches!(result, Err(ConvertError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn json_entity_rejects_incomplete_key() {
+        let entity = Entity::new(Key::new("Room"), Value::Entity(HashMap::new())).unwrap();
+        assert!(JsonEntity::try_from(entity).is_err());
+    }
+}
@@ -85,8 +85,13 @@ impl Key {
         self.parent.as_ref().map(|inner| inner.borrow())
     }
 
+    // NOTE: 祖先のキーも同じパーティションに属するので、親にも同じ名前空間を付ける
     pub fn namespace(mut self, namespace: impl Into<String>) -> Key {
-        self.namespace = Some(namespace.into());
+        let namespace = namespace.into();
+        self.parent = self
+            .parent
+            .map(|parent| Box::new(parent.namespace(namespace.clone())));
+        self.namespace = Some(namespace);
         self
     }
 
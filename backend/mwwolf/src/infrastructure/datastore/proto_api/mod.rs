@@ -15,15 +15,24 @@ mod authorize;
 mod client;
 mod entity;
 mod error;
+mod json_entity;
 mod key;
+mod mutation;
 mod query;
+mod rpc;
+mod serde_value;
 mod value;
 
 pub use self::client::*;
 pub use self::entity::*;
+pub use self::json_entity::*;
 pub use self::key::*;
+pub use self::mutation::*;
 pub use self::query::*;
+pub use self::serde_value::*;
 pub use self::value::*;
 
+pub(crate) use self::rpc::Rpc;
+
 pub type Error = error::Error;
 pub type ConvertError = error::ConvertError;
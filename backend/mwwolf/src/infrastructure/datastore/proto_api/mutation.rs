@@ -0,0 +1,95 @@
+use std::borrow::Borrow;
+
+use super::error::ConvertError;
+use super::{Entity, IntoEntity, Key};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Insert(Entity),
+    Update(Entity),
+    Upsert(Entity),
+    Delete(Key),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutation {
+    pub(crate) operation: Operation,
+    pub(crate) base_version: Option<i64>,
+}
+
+impl Mutation {
+    // NOTE: 既に同じキーのエンティティがあれば、コミットがALREADY_EXISTSで失敗する
+    pub fn insert(entity: impl IntoEntity) -> Result<Mutation, ConvertError> {
+        Ok(Mutation {
+            operation: Operation::Insert(entity.into_entity()?),
+            base_version: None,
+        })
+    }
+
+    // NOTE: 同じキーのエンティティが無ければ、コミットがNOT_FOUNDで失敗する
+    pub fn update(entity: impl IntoEntity) -> Result<Mutation, ConvertError> {
+        let entity = entity.into_entity()?;
+        Ok(Mutation {
+            base_version: entity.version,
+            operation: Operation::Update(entity),
+        })
+    }
+
+    pub fn upsert(entity: impl IntoEntity) -> Result<Mutation, ConvertError> {
+        let entity = entity.into_entity()?;
+        Ok(Mutation {
+            base_version: entity.version,
+            operation: Operation::Upsert(entity),
+        })
+    }
+
+    // NOTE: 不完全なキーはinsertし、それ以外はupsertする
+    pub fn put(entity: impl IntoEntity) -> Result<Mutation, ConvertError> {
+        let entity = entity.into_entity()?;
+        if entity.key.is_incomplete() {
+            Mutation::insert(entity)
+        } else {
+            Mutation::upsert(entity)
+        }
+    }
+
+    pub fn delete(key: impl Borrow<Key>) -> Mutation {
+        Mutation {
+            operation: Operation::Delete(key.borrow().clone()),
+            base_version: None,
+        }
+    }
+
+    // NOTE: 保存されているエンティティのバージョンが異なれば、ミューテーションは適用されず競合になる
+    // 読み込んだエンティティをupdate・upsertするときは、読み込んだときのバージョンが使われる
+    pub fn base_version(mut self, version: i64) -> Mutation {
+        self.base_version = Some(version);
+        self
+    }
+
+    pub fn key(&self) -> &Key {
+        match &self.operation {
+            Operation::Insert(entity) | Operation::Update(entity) | Operation::Upsert(entity) => {
+                entity.key()
+            }
+            Operation::Delete(key) => key,
+        }
+    }
+
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Mutation {
+        match &mut self.operation {
+            Operation::Insert(entity) | Operation::Update(entity) | Operation::Upsert(entity) => {
+                entity.key = entity.key.clone().namespace(namespace);
+            }
+            Operation::Delete(key) => *key = key.clone().namespace(namespace),
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationResult {
+    // NOTE: 不完全なキーをinsertしたときだけ、割り当てられたキーが返る
+    pub key: Option<Key>,
+    pub version: i64,
+}
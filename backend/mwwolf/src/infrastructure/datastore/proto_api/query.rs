@@ -1,4 +1,9 @@
-use super::{Key, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::{ConvertError, Error};
+use super::{Entity, FromEntity, Key, Value};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Order {
@@ -18,6 +23,138 @@ pub enum Filter {
     GreaterThanOrEqual(String, Value),
 
     LesserThanEqual(String, Value),
+
+    NotEqual(String, Value),
+
+    In(String, Vec<Value>),
+
+    NotIn(String, Vec<Value>),
+
+    And(Vec<Filter>),
+
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub const KEY: &'static str = "__key__";
+    pub const MAX_IN_VALUES: usize = 30;
+    pub const MAX_NOT_IN_VALUES: usize = 10;
+
+    pub fn key_equal(key: Key) -> Filter {
+        Filter::Equal(Self::KEY.into(), Value::Key(key))
+    }
+
+    pub fn key_in(keys: impl IntoIterator<Item = Key>) -> Filter {
+        Filter::In(Self::KEY.into(), keys.into_iter().map(Value::Key).collect())
+    }
+
+    // NOTE: `__key__`で比べるキーはクエリと同じパーティションに属するので、同じ名前空間を付ける。
+    // それ以外のプロパティに入っているキーは保存されたままの値と比べるので変えない
+    fn namespace(self, namespace: &str) -> Filter {
+        let key_value = |value: Value| match value {
+            Value::Key(key) => Value::Key(key.namespace(namespace)),
+            value => value,
+        };
+        match self {
+            Filter::And(filters) => Filter::And(
+                filters
+                    .into_iter()
+                    .map(|filter| filter.namespace(namespace))
+                    .collect(),
+            ),
+            Filter::Or(filters) => Filter::Or(
+                filters
+                    .into_iter()
+                    .map(|filter| filter.namespace(namespace))
+                    .collect(),
+            ),
+            Filter::Equal(name, value) if name == Self::KEY => {
+                Filter::Equal(name, key_value(value))
+            }
+            Filter::NotEqual(name, value) if name == Self::KEY => {
+                Filter::NotEqual(name, key_value(value))
+            }
+            Filter::GreaterThan(name, value) if name == Self::KEY => {
+                Filter::GreaterThan(name, key_value(value))
+            }
+            Filter::LesserThan(name, value) if name == Self::KEY => {
+                Filter::LesserThan(name, key_value(value))
+            }
+            Filter::GreaterThanOrEqual(name, value) if name == Self::KEY => {
+                Filter::GreaterThanOrEqual(name, key_value(value))
+            }
+            Filter::LesserThanEqual(name, value) if name == Self::KEY => {
+                Filter::LesserThanEqual(name, key_value(value))
+            }
+            Filter::In(name, values) if name == Self::KEY => {
+                Filter::In(name, values.into_iter().map(key_value).collect())
+            }
+            Filter::NotIn(name, values) if name == Self::KEY => {
+                Filter::NotIn(name, values.into_iter().map(key_value).collect())
+            }
+            filter => filter,
+        }
+    }
+
+    fn validate(
+        &self,
+        inequalities: &mut BTreeSet<String>,
+        not_filters: &mut usize,
+    ) -> Result<(), String> {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) => {
+                if filters.is_empty() {
+                    return Err("composite filter must contain at least one filter".into());
+                }
+                filters
+                    .iter()
+                    .try_for_each(|filter| filter.validate(inequalities, not_filters))
+            }
+            Filter::In(name, values) | Filter::NotIn(name, values) => {
+                let (op, max) = match self {
+                    Filter::In(..) => ("IN", Self::MAX_IN_VALUES),
+                    _ => ("NOT_IN", Self::MAX_NOT_IN_VALUES),
+                };
+                if values.is_empty() || values.len() > max {
+                    return Err(format!(
+                        "{} filter on `{}` must have 1 to {} values",
+                        op, name, max
+                    ));
+                }
+                if let Filter::NotIn(..) = self {
+                    inequalities.insert(name.clone());
+                    *not_filters += 1;
+                }
+                values
+                    .iter()
+                    .try_for_each(|value| validate_key_value(name, value))
+            }
+            Filter::Equal(name, value) => validate_key_value(name, value),
+            Filter::GreaterThan(name, value)
+            | Filter::LesserThan(name, value)
+            | Filter::GreaterThanOrEqual(name, value)
+            | Filter::LesserThanEqual(name, value)
+            | Filter::NotEqual(name, value) => {
+                inequalities.insert(name.clone());
+                if let Filter::NotEqual(..) = self {
+                    *not_filters += 1;
+                }
+                validate_key_value(name, value)
+            }
+        }
+    }
+}
+
+fn validate_key_value(name: &str, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Key(_) => Ok(()),
+        value if name == Filter::KEY => Err(format!(
+            "`{}` filter requires key values, got {}",
+            Filter::KEY,
+            value.type_name()
+        )),
+        _ => Ok(()),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -78,7 +215,14 @@ impl Query {
     }
 
     pub fn namespace(mut self, namespace: impl Into<String>) -> Query {
-        self.namespace = Some(namespace.into());
+        let namespace = namespace.into();
+        self.ancestor = self.ancestor.map(|key| key.namespace(&namespace));
+        self.filters = self
+            .filters
+            .into_iter()
+            .map(|filter| filter.namespace(&namespace))
+            .collect();
+        self.namespace = Some(namespace);
         self
     }
 
@@ -112,4 +256,161 @@ impl Query {
         self.ordering.push(order);
         self
     }
+
+    // NOTE: Datastoreに送る前に、Datastoreが拒否するフィルタの組み合わせを弾く
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut inequalities = BTreeSet::new();
+        let mut not_filters = 0;
+        self.filters
+            .iter()
+            .try_for_each(|filter| filter.validate(&mut inequalities, &mut not_filters))
+            .map_err(Error::InvalidQuery)?;
+        if inequalities.len() > 1 {
+            return Err(Error::InvalidQuery(format!(
+                "inequality filters must be on a single property, got {}",
+                inequalities
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        if not_filters > 1 {
+            return Err(Error::InvalidQuery(
+                "only one NOT_EQUAL or NOT_IN filter is allowed".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// NOTE: Datastoreのカーソルをそのまま外に渡せるよう、URLセーフなbase64文字列にする
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(pub(crate) Vec<u8>);
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&base64::encode_config(&self.0, base64::URL_SAFE_NO_PAD))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = ConvertError;
+
+    fn from_str(s: &str) -> Result<Cursor, ConvertError> {
+        base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .map(Cursor)
+            .map_err(|e| ConvertError::InvalidValue(format!("invalid cursor: {}", e)))
+    }
+}
+
+/// `cursor`が`None`なら、これ以上の結果は無い
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPage<T> {
+    pub items: Vec<T>,
+    pub cursor: Option<Cursor>,
+}
+
+impl QueryPage<Entity> {
+    pub fn convert<T: FromEntity>(self) -> Result<QueryPage<T>, ConvertError> {
+        Ok(QueryPage {
+            items: self
+                .items
+                .into_iter()
+                .map(T::from_entity)
+                .collect::<Result<Vec<_>, _>>()?,
+            cursor: self.cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn strings(values: &[&str]) -> Vec<Value> {
+        values
+            .iter()
+            .map(|v| Value::Strings(v.to_string()))
+            .collect()
+    }
+
+    #[test_case(Filter::In("kind".into(), strings(&["a", "b"])) => Ok(()))]
+    #[test_case(
+        Filter::Or(vec![
+            Filter::Equal("status".into(), Value::Strings("Talking".into())),
+            Filter::key_in(vec![Key::new("Room").id("room1")]),
+        ])
+        => Ok(())
+    )]
+    #[test_case(
+        Filter::And(vec![
+            Filter::GreaterThan("count".into(), Value::Integer(1)),
+            Filter::NotEqual("count".into(), Value::Integer(3)),
+        ])
+        => Ok(())
+    )]
+    #[test_case(
+        Filter::Or(vec![])
+        => Err("invalid query: composite filter must contain at least one filter".into())
+    )]
+    #[test_case(
+        Filter::In("kind".into(), vec![])
+        => Err("invalid query: IN filter on `kind` must have 1 to 30 values".into())
+    )]
+    #[test_case(
+        Filter::NotIn("kind".into(), strings(&["a"; 11]))
+        => Err("invalid query: NOT_IN filter on `kind` must have 1 to 10 values".into())
+    )]
+    #[test_case(
+        Filter::Equal(Filter::KEY.into(), Value::Strings("room1".into()))
+        => Err("invalid query: `__key__` filter requires key values, got string".into())
+    )]
+    #[test_case(
+        Filter::And(vec![
+            Filter::GreaterThan("a".into(), Value::Integer(1)),
+            Filter::LesserThan("b".into(), Value::Integer(1)),
+        ])
+        => Err("invalid query: inequality filters must be on a single property, got `a`, `b`".into())
+    )]
+    #[test_case(
+        Filter::And(vec![
+            Filter::NotEqual("a".into(), Value::Integer(1)),
+            Filter::NotIn("a".into(), vec![Value::Integer(2)]),
+        ])
+        => Err("invalid query: only one NOT_EQUAL or NOT_IN filter is allowed".into())
+    )]
+    fn query_validate_works(filter: Filter) -> Result<(), String> {
+        Query::new("Room")
+            .filter(filter)
+            .validate()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn query_namespace_applies_to_key_filters() {
+        let key = Key::new("Room").id("room1");
+        let query = Query::new("Room")
+            .filter(Filter::Or(vec![
+                Filter::key_equal(key.clone()),
+                Filter::key_in(vec![key.clone().parent(Key::new("Tenant").id("t1"))]),
+            ]))
+            .filter(Filter::Equal("owner".into(), Value::Key(key.clone())))
+            .namespace("ns");
+        let namespaced = key.clone().namespace("ns");
+        assert_eq!(
+            query.filters,
+            vec![
+                Filter::Or(vec![
+                    Filter::key_equal(namespaced.clone()),
+                    Filter::key_in(vec![key
+                        .clone()
+                        .parent(Key::new("Tenant").id("t1"))
+                        .namespace("ns")]),
+                ]),
+                Filter::Equal("owner".into(), Value::Key(key)),
+            ]
+        );
+    }
 }
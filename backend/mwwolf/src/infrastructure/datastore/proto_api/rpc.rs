@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+use tracing::field;
+
+use super::api;
+use super::{Error, Query};
+use crate::infrastructure::metrics::{metrics, Metrics};
+
+/// NOTE: トレースとメトリクスに付ける、RPCの対象の情報
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Rpc {
+    method: &'static str,
+    kind: String,
+    namespace: String,
+    keys: usize,
+}
+
+impl Rpc {
+    pub(crate) fn new(method: &'static str) -> Rpc {
+        Rpc {
+            method,
+            ..Default::default()
+        }
+    }
+
+    // NOTE: 複数の種類のキーを含む場合は、先頭のキーの種類と名前空間を使う
+    pub(crate) fn keys<'a>(mut self, keys: impl IntoIterator<Item = &'a api::Key>) -> Rpc {
+        for key in keys {
+            if self.keys == 0 {
+                self.kind = key
+                    .path
+                    .last()
+                    .map(|element| element.kind.clone())
+                    .unwrap_or_default();
+                self.namespace = key
+                    .partition_id
+                    .as_ref()
+                    .map(|partition| partition.namespace_id.clone())
+                    .unwrap_or_default();
+            }
+            self.keys += 1;
+        }
+        self
+    }
+
+    pub(crate) fn mutations(self, mutations: &[api::Mutation]) -> Rpc {
+        use api::mutation::Operation;
+
+        self.keys(
+            mutations
+                .iter()
+                .filter_map(|mutation| match &mutation.operation {
+                    Some(Operation::Insert(entity))
+                    | Some(Operation::Update(entity))
+                    | Some(Operation::Upsert(entity)) => entity.key.as_ref(),
+                    Some(Operation::Delete(key)) => Some(key),
+                    None => None,
+                }),
+        )
+    }
+
+    pub(crate) fn query(mut self, query: &Query) -> Rpc {
+        self.kind = query.kind.clone();
+        self.namespace = query.namespace.clone().unwrap_or_default();
+        self
+    }
+
+    pub(crate) fn namespace(mut self, namespace: impl Into<String>) -> Rpc {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub(crate) fn span(&self) -> tracing::Span {
+        tracing::info_span!(
+            "datastore",
+            method = self.method,
+            kind = %self.kind,
+            namespace = %self.namespace,
+            keys = self.keys,
+            retries = field::Empty,
+            latency_ms = field::Empty,
+            result = field::Empty,
+        )
+    }
+
+    pub(crate) fn record<T>(
+        &self,
+        span: &tracing::Span,
+        retries: u32,
+        latency: Duration,
+        result: &Result<T, Error>,
+    ) {
+        self.record_to(metrics(), span, retries, latency, result)
+    }
+
+    fn record_to<T>(
+        &self,
+        metrics: &dyn Metrics,
+        span: &tracing::Span,
+        retries: u32,
+        latency: Duration,
+        result: &Result<T, Error>,
+    ) {
+        let result = match result {
+            Ok(_) => "ok",
+            Err(e) => error_label(e),
+        };
+        span.record("retries", &retries);
+        span.record("latency_ms", &(latency.as_secs_f64() * 1000.0));
+        span.record("result", result);
+
+        metrics.increment_counter(
+            "datastore_rpc_total",
+            &[
+                ("method", self.method),
+                ("kind", &self.kind),
+                ("result", result),
+            ],
+            1,
+        );
+        metrics.observe_histogram(
+            "datastore_rpc_duration_seconds",
+            &[("method", self.method), ("kind", &self.kind)],
+            latency.as_secs_f64(),
+        );
+        if retries > 0 {
+            metrics.increment_counter(
+                "datastore_rpc_retries_total",
+                &[("method", self.method), ("kind", &self.kind)],
+                retries as u64,
+            );
+        }
+    }
+}
+
+fn error_label(err: &Error) -> &'static str {
+    match err {
+        Error::NotFound(_) => "not_found",
+        Error::AlreadyExists(_) => "already_exists",
+        Error::Aborted(_) => "aborted",
+        Error::DeadlineExceeded(_) => "deadline_exceeded",
+        Error::Unavailable(_) => "unavailable",
+        Error::InvalidArgument(_) => "invalid_argument",
+        Error::PermissionDenied(_) => "permission_denied",
+        Error::Conflict(_) => "conflict",
+        _ => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::metrics::PrometheusMetrics;
+
+    fn key(kind: &str) -> api::Key {
+        api::Key {
+            partition_id: Some(api::PartitionId {
+                project_id: String::from("project"),
+                namespace_id: String::from("ns"),
+            }),
+            path: vec![api::key::PathElement {
+                kind: String::from(kind),
+                id_type: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn rpc_record_exports_metrics() {
+        let prometheus = PrometheusMetrics::new(vec![1.0]);
+
+        let rpc = Rpc::new("record_test").keys(&[key("Room"), key("Game")]);
+        rpc.record_to(
+            &prometheus,
+            &rpc.span(),
+            2,
+            Duration::from_millis(10),
+            &Ok::<_, Error>(()),
+        );
+        rpc.record_to(
+            &prometheus,
+            &rpc.span(),
+            0,
+            Duration::from_secs(2),
+            &Err::<(), _>(Error::Conflict(String::from("test"))),
+        );
+
+        let rendered = prometheus.render();
+        let lines = rendered
+            .lines()
+            .filter(|line| line.contains("record_test"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                r#"datastore_rpc_retries_total{kind="Room",method="record_test"} 2"#,
+                r#"datastore_rpc_total{kind="Room",method="record_test",result="conflict"} 1"#,
+                r#"datastore_rpc_total{kind="Room",method="record_test",result="ok"} 1"#,
+                r#"datastore_rpc_duration_seconds_bucket{kind="Room",method="record_test",le="1"} 1"#,
+                r#"datastore_rpc_duration_seconds_bucket{kind="Room",method="record_test",le="+Inf"} 2"#,
+                r#"datastore_rpc_duration_seconds_sum{kind="Room",method="record_test"} 2.01"#,
+                r#"datastore_rpc_duration_seconds_count{kind="Room",method="record_test"} 2"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,1009 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize, Serializer};
+
+use super::error::ConvertError;
+use super::{Entity, FromEntity, IntoEntity, IntoValue, Key, Value};
+
+// NOTE: serdeのデータモデルに無い型は、この名前のnewtype structとして受け渡す
+const TIMESTAMP: &str = "$proto_api::Timestamp";
+const UNINDEXED: &str = "$proto_api::Unindexed";
+
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, ConvertError> {
+    value
+        .serialize(ValueSerializer)?
+        .value
+        .ok_or_else(|| ConvertError::InvalidValue(String::from("null is not supported")))
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, ConvertError> {
+    T::deserialize(ValueDeserializer(value))
+}
+
+pub fn to_entity<T: Serialize + ?Sized>(key: Key, value: &T) -> Result<Entity, ConvertError> {
+    let Serialized { value, indexed, .. } = value.serialize(ValueSerializer)?;
+    let properties =
+        value.ok_or_else(|| ConvertError::InvalidValue(String::from("null is not supported")))?;
+    let names = |included: bool| {
+        indexed
+            .iter()
+            .filter(|(_, value)| **value == included)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+    };
+    Ok(Entity::new(key, properties)?
+        .exclude_from_indexes(&names(false))
+        .include_in_indexes(&names(true)))
+}
+
+pub fn from_entity<T: DeserializeOwned>(entity: Entity) -> Result<T, ConvertError> {
+    from_value(entity.into_properties())
+}
+
+/// NOTE: Serialize・Deserializeを実装した型を、そのままエンティティとして読み書きする
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerdeEntity<T> {
+    pub key: Key,
+    pub value: T,
+}
+
+impl<T: Serialize> IntoEntity for SerdeEntity<T> {
+    fn into_entity(self) -> Result<Entity, ConvertError> {
+        to_entity(self.key, &self.value)
+    }
+}
+
+impl<T: DeserializeOwned> FromEntity for SerdeEntity<T> {
+    fn from_entity(e: Entity) -> Result<Self, ConvertError> {
+        Ok(SerdeEntity {
+            key: e.key().clone(),
+            value: from_entity(e)?,
+        })
+    }
+}
+
+/// `#[serde(with = "proto_api::timestamp")]` でタイムスタンプとして保存する
+pub mod timestamp {
+    use super::*;
+
+    pub trait AsTimestamp: Sized {
+        fn to_utc(&self) -> DateTime<Utc>;
+        fn from_utc(utc: DateTime<Utc>) -> Self;
+    }
+
+    impl AsTimestamp for NaiveDateTime {
+        fn to_utc(&self) -> DateTime<Utc> {
+            DateTime::from_utc(*self, Utc)
+        }
+
+        fn from_utc(utc: DateTime<Utc>) -> Self {
+            utc.naive_utc()
+        }
+    }
+
+    impl AsTimestamp for DateTime<Utc> {
+        fn to_utc(&self) -> DateTime<Utc> {
+            *self
+        }
+
+        fn from_utc(utc: DateTime<Utc>) -> Self {
+            utc
+        }
+    }
+
+    // NOTE: タイムゾーンは保存されないので、ドメインのタイムゾーンで読み出す
+    impl AsTimestamp for DateTime<chrono_tz::Tz> {
+        fn to_utc(&self) -> DateTime<Utc> {
+            self.with_timezone(&Utc)
+        }
+
+        fn from_utc(utc: DateTime<Utc>) -> Self {
+            utc.with_timezone(&crate::libmww::time::TIMEZONE)
+        }
+    }
+
+    pub fn serialize<T: AsTimestamp, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let utc = value.to_utc();
+        serializer
+            .serialize_newtype_struct(TIMESTAMP, &(utc.timestamp(), utc.timestamp_subsec_nanos()))
+    }
+
+    pub fn deserialize<'de, T: AsTimestamp, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        deserializer
+            .deserialize_newtype_struct(TIMESTAMP, TimestampVisitor(std::marker::PhantomData))
+    }
+
+    struct Timestamp<'a, T>(&'a T);
+
+    impl<'a, T: AsTimestamp> Serialize for Timestamp<'a, T> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize(self.0, serializer)
+        }
+    }
+
+    struct TimestampVisitor<T>(std::marker::PhantomData<T>);
+
+    impl<'de, T: AsTimestamp> Visitor<'de> for TimestampVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("timestamp")
+        }
+
+        fn visit_newtype_struct<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<T, D::Error> {
+            let (seconds, nanos) = <(i64, u32) as de::Deserialize>::deserialize(deserializer)?;
+            NaiveDateTime::from_timestamp_opt(seconds, nanos)
+                .map(|naive| T::from_utc(DateTime::from_utc(naive, Utc)))
+                .ok_or_else(|| de::Error::custom("timestamp is out of range"))
+        }
+    }
+
+    /// NOTE: 値が無い場合に備えて `#[serde(default)]` と一緒に使う
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<T: AsTimestamp, S: Serializer>(
+            value: &Option<T>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => serializer.serialize_some(&Timestamp(value)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T: AsTimestamp, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<T>, D::Error> {
+            deserializer.deserialize_option(OptionVisitor(std::marker::PhantomData))
+        }
+
+        struct OptionVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: AsTimestamp> Visitor<'de> for OptionVisitor<T> {
+            type Value = Option<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("optional timestamp")
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<Option<T>, E> {
+                Ok(None)
+            }
+
+            fn visit_some<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Option<T>, D::Error> {
+                super::deserialize(deserializer).map(Some)
+            }
+        }
+    }
+}
+
+/// `#[serde(with = "proto_api::unindexed")]` でインデックスしないプロパティにする
+pub mod unindexed {
+    use super::*;
+
+    pub fn serialize<T: Serialize, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(UNINDEXED, value)
+    }
+
+    pub fn deserialize<'de, T: de::Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        T::deserialize(deserializer)
+    }
+}
+
+impl ser::Error for ConvertError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ConvertError::InvalidValue(msg.to_string())
+    }
+}
+
+impl de::Error for ConvertError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ConvertError::InvalidValue(msg.to_string())
+    }
+
+    fn invalid_type(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        ConvertError::UnexpectedPropertyType {
+            expected: exp.to_string(),
+            got: unexp.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ConvertError::MissingProperty(String::from(field))
+    }
+}
+
+// NOTE: valueがNoneならNULL(プロパティを書かない)。
+// indexedは入れ子のプロパティのパスごとに、インデックスするかどうかを持つ
+struct Serialized {
+    value: Option<Value>,
+    exclude_from_indexes: bool,
+    indexed: HashMap<String, bool>,
+}
+
+impl Serialized {
+    fn value(value: Value) -> Serialized {
+        Serialized {
+            value: Some(value),
+            exclude_from_indexes: false,
+            indexed: HashMap::new(),
+        }
+    }
+
+    fn null() -> Serialized {
+        Serialized {
+            value: None,
+            exclude_from_indexes: false,
+            indexed: HashMap::new(),
+        }
+    }
+}
+
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::value(Value::Boolean(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::value(Value::Integer(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Serialized, ConvertError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Serialized, ConvertError> {
+        let v = i64::try_from(v).map_err(|_| {
+            ConvertError::InvalidValue(format!("{} is out of range for integer", v))
+        })?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Serialized, ConvertError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::value(Value::Double(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Serialized, ConvertError> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::value(Value::Strings(String::from(v))))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::value(Value::Blob(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::null())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Serialized, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::null())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Serialized, ConvertError> {
+        Ok(Serialized::null())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Serialized, ConvertError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Serialized, ConvertError> {
+        match name {
+            TIMESTAMP => {
+                let (seconds, nanos): (i64, u32) = from_value(to_value(value)?)?;
+                let timestamp = NaiveDateTime::from_timestamp_opt(seconds, nanos)
+                    .ok_or_else(|| ConvertError::InvalidValue(String::from("invalid timestamp")))?;
+                Ok(Serialized::value(
+                    DateTime::<Utc>::from_utc(timestamp, Utc).into_value(),
+                ))
+            }
+            UNINDEXED => Ok(Serialized {
+                exclude_from_indexes: true,
+                ..value.serialize(self)?
+            }),
+            _ => value.serialize(self),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Serialized, ConvertError> {
+        Ok(VariantSerializer::<()>::finish(
+            variant,
+            value.serialize(self)?,
+        ))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, ConvertError> {
+        Ok(SeqSerializer {
+            values: Vec::with_capacity(len.unwrap_or_default()),
+            indexed: HashMap::new(),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<SeqSerializer>, ConvertError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, ConvertError> {
+        Ok(MapSerializer {
+            properties: HashMap::new(),
+            indexed: HashMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, ConvertError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<MapSerializer>, ConvertError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SeqSerializer {
+    values: Vec<Value>,
+    indexed: HashMap<String, bool>,
+}
+
+impl SeqSerializer {
+    // NOTE: 要素は全て配列と同じパスを持つので、要素の中のインデックス指定はそのまま引き継ぐ
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        let serialized = value.serialize(ValueSerializer)?;
+        let value = serialized.value.ok_or_else(|| {
+            ConvertError::InvalidValue(String::from("null is not supported in arrays"))
+        })?;
+        self.values.push(value);
+        self.indexed.extend(serialized.indexed);
+        Ok(())
+    }
+
+    fn finish(self) -> Serialized {
+        Serialized {
+            value: Some(Value::Array(self.values)),
+            exclude_from_indexes: false,
+            indexed: self.indexed,
+        }
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(self.finish())
+    }
+}
+
+struct MapSerializer {
+    properties: HashMap<String, Value>,
+    indexed: HashMap<String, bool>,
+    next_key: Option<String>,
+}
+
+impl MapSerializer {
+    fn insert<T: Serialize + ?Sized>(
+        &mut self,
+        key: String,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        let serialized = value.serialize(ValueSerializer)?;
+        self.insert_serialized(key, serialized);
+        Ok(())
+    }
+
+    // NOTE: 入れ子のエンティティは既定でインデックスされないので、
+    // unindexedでなければインデックスして、中のプロパティで検索できるようにする
+    fn insert_serialized(&mut self, key: String, serialized: Serialized) {
+        if let Some(value) = serialized.value {
+            if serialized.exclude_from_indexes {
+                self.indexed.insert(key.clone(), false);
+            } else if contains_entity(&value) {
+                self.indexed.insert(key.clone(), true);
+            }
+            self.indexed.extend(
+                serialized
+                    .indexed
+                    .into_iter()
+                    .map(|(name, indexed)| (format!("{}.{}", key, name), indexed)),
+            );
+            self.properties.insert(key, value);
+        }
+    }
+
+    fn finish(self) -> Serialized {
+        Serialized {
+            value: Some(Value::Entity(self.properties)),
+            exclude_from_indexes: false,
+            indexed: self.indexed,
+        }
+    }
+}
+
+fn contains_entity(value: &Value) -> bool {
+    match value {
+        Value::Entity(_) => true,
+        Value::Array(values) => values.iter().any(contains_entity),
+        _ => false,
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ConvertError> {
+        match to_value(key)? {
+            Value::Strings(key) => {
+                self.next_key = Some(key);
+                Ok(())
+            }
+            key => Err(ConvertError::UnexpectedPropertyType {
+                expected: String::from("string"),
+                got: String::from(key.type_name()),
+            }),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        let key = self.next_key.take().ok_or_else(|| {
+            ConvertError::InvalidValue(String::from("serialize_value called before serialize_key"))
+        })?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.insert(String::from(key), value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(self.finish())
+    }
+}
+
+// NOTE: データを持つenumのバリアントは `{バリアント名: 値}` のエンティティにする
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> VariantSerializer<S> {
+    fn finish(variant: &'static str, inner: Serialized) -> Serialized {
+        let mut map = MapSerializer {
+            properties: HashMap::new(),
+            indexed: HashMap::new(),
+            next_key: None,
+        };
+        map.insert_serialized(String::from(variant), inner);
+        map.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(Self::finish(self.variant, self.inner.finish()))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = Serialized;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.inner.insert(String::from(key), value)
+    }
+
+    fn end(self) -> Result<Serialized, ConvertError> {
+        Ok(Self::finish(self.variant, self.inner.finish()))
+    }
+}
+
+struct ValueDeserializer(Value);
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::Null => visitor.visit_unit(),
+            Value::Boolean(v) => visitor.visit_bool(v),
+            Value::Integer(v) => visitor.visit_i64(v),
+            Value::Double(v) => visitor.visit_f64(v),
+            Value::Timestamp(v) => visitor.visit_string(v.to_rfc3339()),
+            Value::Key(_) => Err(de::Error::invalid_type(
+                de::Unexpected::Other("key"),
+                &visitor,
+            )),
+            Value::Strings(v) => visitor.visit_string(v),
+            Value::Blob(v) => visitor.visit_byte_buf(v),
+            Value::GeoPoint(latitude, longitude) => visitor.visit_seq(SeqDeserializer(
+                vec![Value::Double(latitude), Value::Double(longitude)].into_iter(),
+            )),
+            Value::Entity(properties) => visitor.visit_map(MapDeserializer {
+                properties: properties.into_iter(),
+                next_value: None,
+            }),
+            Value::Array(values) => visitor.visit_seq(SeqDeserializer(values.into_iter())),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(ValueDeserializer(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        match (name, self.0) {
+            (TIMESTAMP, Value::Timestamp(v)) => {
+                visitor.visit_newtype_struct(ValueDeserializer(Value::Array(vec![
+                    Value::Integer(v.timestamp()),
+                    Value::Integer(v.timestamp_subsec_nanos() as i64),
+                ])))
+            }
+            (TIMESTAMP, value) => Err(ConvertError::UnexpectedPropertyType {
+                expected: String::from("timestamp"),
+                got: String::from(value.type_name()),
+            }),
+            (_, value) => visitor.visit_newtype_struct(ValueDeserializer(value)),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::Strings(variant) => {
+                visitor.visit_enum(IntoDeserializer::<ConvertError>::into_deserializer(variant))
+            }
+            Value::Entity(properties) if properties.len() == 1 => {
+                let (variant, value) = properties.into_iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            value => Err(ConvertError::UnexpectedPropertyType {
+                expected: String::from("enum"),
+                got: String::from(value.type_name()),
+            }),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct SeqDeserializer(std::vec::IntoIter<Value>);
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = ConvertError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ConvertError> {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(ValueDeserializer(value)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapDeserializer {
+    properties: std::collections::hash_map::IntoIter<String, Value>,
+    next_value: Option<Value>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = ConvertError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ConvertError> {
+        match self.properties.next() {
+            Some((key, value)) => {
+                self.next_value = Some(value);
+                seed.deserialize(IntoDeserializer::<ConvertError>::into_deserializer(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ConvertError> {
+        let value = self.next_value.take().ok_or_else(|| {
+            ConvertError::InvalidValue(String::from("next_value called before next_key"))
+        })?;
+        seed.deserialize(ValueDeserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.properties.len())
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = ConvertError;
+    type Variant = ValueDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, ValueDeserializer), ConvertError> {
+        let variant = seed.deserialize(IntoDeserializer::<ConvertError>::into_deserializer(
+            self.variant,
+        ))?;
+        Ok((variant, ValueDeserializer(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueDeserializer {
+    type Error = ConvertError;
+
+    fn unit_variant(self) -> Result<(), ConvertError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ConvertError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Villager,
+        Werewolf { partner: Option<String> },
+        Seer(Vec<String>),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Player {
+        name: String,
+        age: u8,
+        #[serde(with = "unindexed")]
+        note: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Game {
+        title: String,
+        players: Vec<Player>,
+        roles: Vec<Role>,
+        #[serde(with = "timestamp")]
+        started_at: DateTime<Utc>,
+        #[serde(default, with = "timestamp::option")]
+        finished_at: Option<NaiveDateTime>,
+        #[serde(with = "unindexed")]
+        description: String,
+        memo: Option<String>,
+    }
+
+    fn game() -> Game {
+        Game {
+            title: String::from("village"),
+            players: vec![Player {
+                name: String::from("alice"),
+                age: 20,
+                note: String::from("quiet"),
+            }],
+            roles: vec![
+                Role::Villager,
+                Role::Werewolf { partner: None },
+                Role::Seer(vec![String::from("bob")]),
+            ],
+            started_at: DateTime::from_utc(
+                NaiveDateTime::from_timestamp_opt(1_600_000_000, 123_456_000).unwrap(),
+                Utc,
+            ),
+            finished_at: None,
+            description: String::from("long text"),
+            memo: None,
+        }
+    }
+
+    #[test]
+    fn to_entity_works() {
+        let entity = to_entity(Key::new("Game").id("g1"), &game()).unwrap();
+        assert!(entity.is_excluded_from_indexes("description"));
+        assert!(!entity.is_excluded_from_indexes("title"));
+        assert!(entity.is_excluded_from_indexes("players.note"));
+        assert!(!entity.is_excluded_from_indexes("players"));
+        assert!(!entity.is_excluded_from_indexes("players.name"));
+        assert!(!entity.is_excluded_from_indexes("players.age"));
+
+        let properties: HashMap<String, Value> =
+            super::super::FromValue::from_value(entity.into_properties()).unwrap();
+        assert_eq!(
+            properties["started_at"],
+            Value::Timestamp(Utc.timestamp(1_600_000_000, 123_456_000))
+        );
+        assert!(!properties.contains_key("memo"));
+        assert!(!properties.contains_key("finished_at"));
+        assert_eq!(
+            properties["roles"],
+            Value::Array(vec![
+                Value::Strings(String::from("Villager")),
+                Value::Entity(
+                    vec![(String::from("Werewolf"), Value::Entity(HashMap::new()))]
+                        .into_iter()
+                        .collect()
+                ),
+                Value::Entity(
+                    vec![(
+                        String::from("Seer"),
+                        Value::Array(vec![Value::Strings(String::from("bob"))])
+                    )]
+                    .into_iter()
+                    .collect()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn serde_entity_round_trip_works() {
+        let entity = SerdeEntity {
+            key: Key::new("Game").id("g1"),
+            value: game(),
+        };
+        let restored =
+            SerdeEntity::<Game>::from_entity(entity.clone().into_entity().unwrap()).unwrap();
+        assert_eq!(restored, entity);
+    }
+
+    #[test]
+    fn null_is_read_as_none() {
+        let mut entity = to_entity(Key::new("Game").id("g1"), &game()).unwrap();
+        if let Value::Entity(properties) = entity.properties_mut() {
+            properties.insert(String::from("memo"), Value::Null);
+        }
+        assert_eq!(from_entity::<Game>(entity), Ok(game()));
+    }
+
+    #[test]
+    fn bytes_are_stored_as_blob() {
+        let value = ValueSerializer.serialize_bytes(b"abc").unwrap().value;
+        assert_eq!(value, Some(Value::Blob(b"abc".to_vec())));
+    }
+
+    #[test_case(Value::Strings("x".into()) => Err(ConvertError::UnexpectedPropertyType {
+        expected: String::from("struct Player"),
+        got: String::from("string \"x\""),
+    }))]
+    #[test_case(
+        Value::Entity(vec![(String::from("name"), Value::Strings("x".into()))].into_iter().collect())
+        => Err(ConvertError::MissingProperty(String::from("age")))
+    )]
+    fn from_value_fails(value: Value) -> Result<Player, ConvertError> {
+        from_value(value)
+    }
+}
@@ -1,23 +1,27 @@
 use std::collections::HashMap;
 use std::iter::FromIterator;
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
 
 use bytes::Bytes;
 
 use super::api::value::ValueType;
 use super::error::ConvertError;
 use super::Key;
+use crate::libmww::time;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
+    // NOTE: このアプリケーションはNULLを書かずにプロパティを省くが、他のクライアントが書いたものを読むことがある
+    Null,
+
     Boolean(bool),
 
     Integer(i64),
 
     Double(f64),
 
-    Timestamp(chrono::NaiveDateTime),
+    Timestamp(DateTime<Utc>),
 
     Key(Key),
 
@@ -33,8 +37,12 @@ pub enum Value {
 }
 
 impl Value {
+    // NOTE: Datastoreがインデックスできる文字列の最大バイト数
+    pub const MAX_INDEXED_BYTES: usize = 1500;
+
     pub fn type_name(&self) -> &'static str {
         match self {
+            Value::Null => "null",
             Value::Boolean(_) => "bool",
             Value::Integer(_) => "integer",
             Value::Double(_) => "double",
@@ -47,6 +55,15 @@ impl Value {
             Value::Array(_) => "array",
         }
     }
+
+    // NOTE: Blobと入れ子のエンティティは検索に使うことがほぼ無いので、既定ではインデックスしない
+    pub fn excluded_from_indexes_by_default(&self) -> bool {
+        match self {
+            Value::Blob(_) | Value::Entity(_) => true,
+            Value::Strings(value) => value.len() > Value::MAX_INDEXED_BYTES,
+            _ => false,
+        }
+    }
 }
 
 pub trait IntoValue {
@@ -123,9 +140,19 @@ impl IntoValue for Key {
     }
 }
 
+// NOTE: Datastoreはマイクロ秒までしか保存しないので、読み戻した値と一致するよう切り捨てる
+impl<T: TimeZone> IntoValue for DateTime<T> {
+    fn into_value(self) -> Value {
+        let utc = self.with_timezone(&Utc);
+        let sub_micros = Duration::nanoseconds((utc.timestamp_subsec_nanos() % 1_000) as i64);
+        Value::Timestamp(utc - sub_micros)
+    }
+}
+
+// NOTE: タイムゾーンを持たない日時はUTCとして扱う
 impl IntoValue for NaiveDateTime {
     fn into_value(self) -> Value {
-        Value::Timestamp(self)
+        Utc.from_utc_datetime(&self).into_value()
     }
 }
 
@@ -231,8 +258,8 @@ impl FromValue for Key {
     }
 }
 
-impl FromValue for NaiveDateTime {
-    fn from_value(value: Value) -> Result<NaiveDateTime, ConvertError> {
+impl FromValue for DateTime<Utc> {
+    fn from_value(value: Value) -> Result<DateTime<Utc>, ConvertError> {
         match value {
             Value::Timestamp(value) => Ok(value),
             _ => Err(ConvertError::UnexpectedPropertyType {
@@ -243,6 +270,19 @@ impl FromValue for NaiveDateTime {
     }
 }
 
+// NOTE: タイムゾーンは保存されないので、ドメインのタイムゾーンで読み出す
+impl FromValue for DateTime<chrono_tz::Tz> {
+    fn from_value(value: Value) -> Result<DateTime<chrono_tz::Tz>, ConvertError> {
+        DateTime::<Utc>::from_value(value).map(|value| value.with_timezone(&time::TIMEZONE))
+    }
+}
+
+impl FromValue for NaiveDateTime {
+    fn from_value(value: Value) -> Result<NaiveDateTime, ConvertError> {
+        DateTime::<Utc>::from_value(value).map(|value| value.naive_utc())
+    }
+}
+
 impl FromValue for Bytes {
     fn from_value(value: Value) -> Result<Bytes, ConvertError> {
         match value {
@@ -303,12 +343,12 @@ where
 impl From<ValueType> for Value {
     fn from(value: ValueType) -> Value {
         match value {
-            ValueType::NullValue(_) => unreachable!(),
+            ValueType::NullValue(_) => Value::Null,
             ValueType::BooleanValue(val) => Value::Boolean(val),
             ValueType::IntegerValue(val) => Value::Integer(val),
             ValueType::DoubleValue(val) => Value::Double(val),
             ValueType::TimestampValue(val) => {
-                Value::Timestamp(NaiveDateTime::from_timestamp(val.seconds, val.nanos as u32))
+                Value::Timestamp(Utc.timestamp(val.seconds, val.nanos as u32))
             }
             ValueType::KeyValue(key) => Value::Key(Key::from(key)),
             ValueType::StringValue(val) => Value::Strings(val),
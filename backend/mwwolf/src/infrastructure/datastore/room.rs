@@ -0,0 +1,325 @@
+use super::*;
+use std::convert::TryFrom;
+
+#[derive(new)]
+pub struct RoomRepository;
+
+#[derive(Entity)]
+#[entity(remote = "domain::Room", constructor = "try_new")]
+struct RoomRecord {
+    #[entity(key)]
+    id: domain::Id<domain::Room>,
+    player_count: domain::PlayerCount,
+    wolf_count: domain::WolfCount,
+    host_player_id: domain::Id<domain::Player>,
+    all_players: Vec<domain::Id<domain::Player>>,
+    game_time: domain::GameMinutes,
+    theme_kind: domain::ThemeKind,
+    #[entity(version)]
+    version: domain::Version,
+}
+
+#[async_trait]
+impl domain::RoomRepository for RoomRepository {
+    type Connection = Connection;
+
+    #[tracing::instrument(name = "RoomRepository::find", skip(self, executor, id), fields(id = %id), err)]
+    async fn find<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        id: &domain::Id<domain::Room>,
+    ) -> domain::RepositoryResult<domain::Room> {
+        executor
+            .get(Key::from(id.clone()))
+            .await
+            .map_err(|e| {
+                domain::RepositoryError::new_with_source(
+                    domain::RepositoryErrorKind::Fail,
+                    format!("failed to get room: {}", id),
+                    e.into(),
+                )
+            })?
+            .ok_or_else(|| {
+                domain::RepositoryError::new(
+                    domain::RepositoryErrorKind::NotFound,
+                    format!("room is not found: {}", id),
+                )
+            })
+    }
+
+    #[tracing::instrument(name = "RoomRepository::find_all", skip(self, executor, ids), fields(ids = ids.len() as u64), err)]
+    async fn find_all<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        ids: &[domain::Id<domain::Room>],
+    ) -> domain::RepositoryResult<Vec<domain::Room>> {
+        let keys = ids.iter().cloned().map(Key::from).collect();
+        let result = executor.get_all(keys).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                "failed to get rooms",
+                e.into(),
+            )
+        })?;
+        if !result.missing.is_empty() {
+            let missing = result
+                .missing
+                .into_iter()
+                .filter_map(|key| domain::Id::<domain::Room>::try_from(key).ok())
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            return Err(domain::RepositoryError::new(
+                domain::RepositoryErrorKind::NotFound,
+                format!("rooms are not found: {}", missing.join(", ")),
+            ));
+        }
+        Ok(result.found.into_iter().map(|(_, room)| room).collect())
+    }
+
+    #[tracing::instrument(name = "RoomRepository::create", skip(self, executor, room), fields(id = %room.id()), err)]
+    async fn create<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        room: &domain::Room,
+    ) -> domain::RepositoryResult<()> {
+        executor.insert(room.clone()).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                write_error_kind(&e),
+                format!("failed to create room: {}", room.id()),
+                e.into(),
+            )
+        })
+    }
+
+    #[tracing::instrument(name = "RoomRepository::store", skip(self, executor, room), fields(id = %room.id()), err)]
+    async fn store<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        room: &domain::Room,
+    ) -> domain::RepositoryResult<()> {
+        executor.upsert(room.clone()).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                write_error_kind(&e),
+                format!("failed to store room: {}", room.id()),
+                e.into(),
+            )
+        })
+    }
+
+    #[tracing::instrument(name = "RoomRepository::list", skip(self, executor, page), fields(first = *page.first() as u64), err)]
+    async fn list<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        page: &domain::PageRequest,
+    ) -> domain::RepositoryResult<domain::Page<domain::Room>> {
+        let query = proto_api::Query::new(entity::kind::<domain::Room>());
+        executor.page(query, page).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                "failed to list rooms",
+                e.into(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::Connection as _;
+    use database::ConnectionFactory as _;
+    use database::Transaction as _;
+    use domain::RoomRepository as _;
+    use proto_api::{FromEntity, IntoValue};
+    use test_case::test_case;
+
+    fn new_room(id: &str, all_players: &[&str], theme_kind: &str) -> domain::Room {
+        domain::Room::try_new(
+            domain::Id::new(id),
+            domain::PlayerCount::try_new(5).unwrap(),
+            domain::WolfCount::try_new(2).unwrap(),
+            domain::Id::new(all_players[0]),
+            all_players.iter().map(|id| domain::Id::new(*id)).collect(),
+            domain::GameMinutes::try_new(5).unwrap(),
+            domain::ThemeKind::try_new(theme_kind).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test_case(new_room("1", &["player1"], "kind1"))]
+    #[test_case(new_room("2", &["player1", "player2", "player3"], "kind2"))]
+    fn domain_room_entity_round_trip_works(room: domain::Room) {
+        let entity = proto_api::IntoEntity::into_entity(room.clone()).unwrap();
+        assert_eq!(domain::Room::from_entity(entity), Ok(room));
+    }
+
+    #[test]
+    fn domain_room_from_entity_fails_with_invalid_value() {
+        let mut properties = match new_room("1", &["player1"], "kind1").into_value() {
+            proto_api::Value::Entity(properties) => properties,
+            _ => unreachable!(),
+        };
+        properties.insert(RoomFields::WOLF_COUNT.into(), 0i64.into_value());
+        let entity =
+            proto_api::Entity::new(Key::new(entity::kind::<domain::Room>()).id(1), properties)
+                .unwrap();
+        assert_eq!(
+            domain::Room::from_entity(entity),
+            Err(proto_api::ConvertError::InvalidValue(
+                "raw_count should not be zero".into()
+            ))
+        );
+    }
+
+    #[async_std::test]
+    async fn room_repository_store_and_find_in_transaction_works() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let room = new_room("1234", &["player1", "player2"], "kind1");
+        let room_repository = RoomRepository::new();
+
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        room_repository
+            .store(&mut database::Executor::Transaction(&mut tx), &room)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let mut tx = conn.begin().await.unwrap();
+        let found = room_repository
+            .find(&mut database::Executor::Transaction(&mut tx), room.id())
+            .await;
+        tx.rollback().await.unwrap();
+        assert_eq!(found, Ok(room));
+    }
+
+    #[async_std::test]
+    async fn room_repository_create_does_not_overwrite_existing_room() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let room = new_room("1234", &["player1"], "kind1");
+        let room_repository = RoomRepository::new();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut executor = database::Executor::Connection(&mut conn);
+        room_repository.create(&mut executor, &room).await.unwrap();
+
+        let taken = new_room("1234", &["player2"], "kind2");
+        let created = room_repository.create(&mut executor, &taken).await;
+        assert_eq!(
+            created.map_err(|e| e.kind().clone()),
+            Err(domain::RepositoryErrorKind::Conflict)
+        );
+        assert_eq!(
+            room_repository.find(&mut executor, room.id()).await,
+            Ok(room)
+        );
+    }
+
+    #[async_std::test]
+    async fn room_repository_store_fails_when_room_is_outdated() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let room = new_room("1234", &["player1"], "kind1");
+        let room_repository = RoomRepository::new();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut executor = database::Executor::Connection(&mut conn);
+        room_repository.create(&mut executor, &room).await.unwrap();
+
+        let mut first = room_repository
+            .find(&mut executor, room.id())
+            .await
+            .unwrap();
+        let mut second = room_repository
+            .find(&mut executor, room.id())
+            .await
+            .unwrap();
+        assert!(first.version().raw_version().is_some());
+        first.join_player(domain::Id::new("player2")).unwrap();
+        room_repository.store(&mut executor, &first).await.unwrap();
+
+        second.join_player(domain::Id::new("player3")).unwrap();
+        let stored = room_repository.store(&mut executor, &second).await;
+        assert_eq!(
+            stored.map_err(|e| e.kind().clone()),
+            Err(domain::RepositoryErrorKind::Conflict)
+        );
+        assert_eq!(
+            room_repository
+                .find(&mut executor, room.id())
+                .await
+                .map(|room| room.all_players().clone()),
+            Ok(vec![domain::Id::new("player1"), domain::Id::new("player2")])
+        );
+    }
+
+    #[async_std::test]
+    async fn room_repository_find_returns_not_found() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let found = RoomRepository::new()
+            .find(
+                &mut database::Executor::Connection(&mut conn),
+                &domain::Id::new("404"),
+            )
+            .await;
+        assert_eq!(
+            found.map_err(|e| e.kind().clone()),
+            Err(domain::RepositoryErrorKind::NotFound)
+        );
+    }
+
+    #[async_std::test]
+    async fn room_repository_find_all_reports_missing_ids() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let first = new_room("1", &["player1"], "kind1");
+        let second = new_room("2", &["player2"], "kind1");
+        let room_repository = RoomRepository::new();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let mut executor = database::Executor::Connection(&mut conn);
+        room_repository.store(&mut executor, &first).await.unwrap();
+        room_repository.store(&mut executor, &second).await.unwrap();
+
+        let found = room_repository
+            .find_all(&mut executor, &[second.id().clone(), first.id().clone()])
+            .await;
+        assert_eq!(found, Ok(vec![second.clone(), first.clone()]));
+
+        let duplicated = room_repository
+            .find_all(
+                &mut executor,
+                &[first.id().clone(), second.id().clone(), first.id().clone()],
+            )
+            .await;
+        assert_eq!(
+            duplicated,
+            Ok(vec![first.clone(), second.clone(), first.clone()])
+        );
+
+        let missing = room_repository
+            .find_all(
+                &mut executor,
+                &[
+                    first.id().clone(),
+                    domain::Id::new("404"),
+                    domain::Id::new("405"),
+                ],
+            )
+            .await
+            .map_err(|e| (e.kind().clone(), e.message().clone()));
+        assert_eq!(
+            missing,
+            Err((
+                domain::RepositoryErrorKind::NotFound,
+                "rooms are not found: 404, 405".into()
+            ))
+        );
+    }
+}
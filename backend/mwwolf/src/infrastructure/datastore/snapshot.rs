@@ -0,0 +1,157 @@
+use super::*;
+use database::ConnectionFactory as _;
+use proto_api::JsonEntity;
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+
+impl Connection {
+    // NOTE: 1行に1エンティティずつ、改行区切りのJSONで書き出す。kindsが空なら全ての種類を対象にする
+    pub async fn export(
+        &mut self,
+        kinds: &[String],
+        mut writer: impl Write,
+    ) -> anyhow::Result<usize> {
+        let kinds = if kinds.is_empty() {
+            self.kinds().await?
+        } else {
+            kinds.to_vec()
+        };
+        let mut exported = 0;
+        for kind in kinds {
+            let mut entities = Box::pin(self.query_stream::<Entity>(Query::new(kind)));
+            while let Some(entity) = entities.try_next().await? {
+                writeln!(writer, "{}", JsonEntity::try_from(entity)?.to_line()?)?;
+                exported += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(exported)
+    }
+
+    // NOTE: エクスポートした時の名前空間に関わらず、このコネクションの名前空間に書き込む。
+    // 同じキーのエンティティは上書きする
+    pub async fn import(&mut self, reader: impl BufRead) -> anyhow::Result<usize> {
+        let mut imported = 0;
+        let mut entities = vec![];
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entity = JsonEntity::from_line(&line)
+                .and_then(Entity::try_from)
+                .map_err(|e| anyhow!("line {}: {}", index + 1, e))?;
+            entities.push(entity);
+            if entities.len() == Client::MAX_MUTATIONS {
+                imported += entities.len();
+                self.put_all(entities.split_off(0)).await?;
+            }
+        }
+        imported += entities.len();
+        self.put_all(entities).await?;
+        Ok(imported)
+    }
+}
+
+pub async fn export_namespace(
+    project_id: &str,
+    namespace: &str,
+    kinds: &[String],
+    writer: impl Write,
+) -> anyhow::Result<usize> {
+    let mut conn = ConnectionFactory::new(project_id.into(), namespace.into())
+        .create()
+        .await?;
+    conn.export(kinds, writer).await
+}
+
+pub async fn import_namespace(
+    project_id: &str,
+    namespace: &str,
+    reader: impl BufRead,
+) -> anyhow::Result<usize> {
+    let mut conn = ConnectionFactory::new(project_id.into(), namespace.into())
+        .create()
+        .await?;
+    conn.import(reader).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmww;
+    use proto_api::Value;
+    use std::collections::HashMap;
+
+    fn entity(key: Key, name: &str) -> Entity {
+        let mut properties = HashMap::new();
+        properties.insert(String::from("name"), Value::Strings(String::from(name)));
+        properties.insert(String::from("data"), Value::Blob(vec![0, 1, 2]));
+        properties.insert(String::from("memo"), Value::Null);
+        Entity::new(key, Value::Entity(properties)).unwrap()
+    }
+
+    #[async_std::test]
+    async fn export_and_import_round_trip_works() {
+        let source = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = source.as_ref().create().await.unwrap();
+        let room = Key::new("Room").id("room1");
+        let game = Key::new("Game").id(1).parent(room.clone());
+        conn.put_all(vec![
+            entity(room.clone(), "room"),
+            entity(game.clone(), "game"),
+            entity(Key::new("Theme").id("theme1"), "theme"),
+        ])
+        .await
+        .unwrap();
+
+        let mut exported = vec![];
+        let count = conn
+            .export(&[String::from("Room"), String::from("Game")], &mut exported)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(String::from_utf8_lossy(&exported).lines().count(), 2);
+
+        let destination = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = destination.as_ref().create().await.unwrap();
+        let count = conn.import(exported.as_slice()).await.unwrap();
+        assert_eq!(count, 2);
+
+        let found = conn
+            .lookup(vec![
+                room.clone(),
+                game.clone(),
+                Key::new("Theme").id("theme1"),
+            ])
+            .await
+            .unwrap();
+        let mut found = found
+            .found
+            .into_iter()
+            .map(|(key, entity)| (key.get_kind().to_string(), entity.into_properties()))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            vec![
+                (String::from("Game"), entity(game, "game").into_properties()),
+                (String::from("Room"), entity(room, "room").into_properties()),
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn import_reports_invalid_line() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let result = conn.import("\n{\"key\":{\"path\":[]}}\n".as_bytes()).await;
+        assert!(result.unwrap_err().to_string().starts_with("line 2:"));
+    }
+}
@@ -0,0 +1,373 @@
+use super::*;
+use database::ConnectionFactory as _;
+use domain::TenantRepository as _;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// NOTE: サーバーと管理ツールは、テナントの一覧をデフォルトの名前空間に保存する
+pub const TENANT_REGISTRY_NAMESPACE: &str = "";
+
+#[derive(new)]
+pub struct TenantRepository;
+
+#[derive(Entity)]
+#[entity(remote = "domain::Tenant")]
+struct TenantRecord {
+    #[entity(key)]
+    id: domain::Id<domain::Tenant>,
+    name: domain::TenantName,
+    namespace: domain::TenantNamespace,
+}
+
+#[async_trait]
+impl domain::TenantRepository for TenantRepository {
+    type Connection = Connection;
+
+    #[tracing::instrument(name = "TenantRepository::find", skip(self, executor, id), fields(id = %id), err)]
+    async fn find<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        id: &domain::Id<domain::Tenant>,
+    ) -> domain::RepositoryResult<domain::Tenant> {
+        executor
+            .get(Key::from(id.clone()))
+            .await
+            .map_err(|e| {
+                domain::RepositoryError::new_with_source(
+                    domain::RepositoryErrorKind::Fail,
+                    format!("failed to get tenant: {}", id),
+                    e.into(),
+                )
+            })?
+            .ok_or_else(|| {
+                domain::RepositoryError::new(
+                    domain::RepositoryErrorKind::NotFound,
+                    format!("tenant is not found: {}", id),
+                )
+            })
+    }
+
+    #[tracing::instrument(name = "TenantRepository::create", skip(self, executor, tenant), fields(id = %tenant.id()), err)]
+    async fn create<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        tenant: &domain::Tenant,
+    ) -> domain::RepositoryResult<()> {
+        executor.insert(tenant.clone()).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                write_error_kind(&e),
+                format!("failed to create tenant: {}", tenant.id()),
+                e.into(),
+            )
+        })
+    }
+
+    #[tracing::instrument(name = "TenantRepository::delete", skip(self, executor, id), fields(id = %id), err)]
+    async fn delete<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        id: &domain::Id<domain::Tenant>,
+    ) -> domain::RepositoryResult<()> {
+        executor.delete(Key::from(id.clone())).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                format!("failed to delete tenant: {}", id),
+                e.into(),
+            )
+        })
+    }
+
+    #[tracing::instrument(name = "TenantRepository::list", skip(self, executor, page), fields(first = *page.first() as u64), err)]
+    async fn list<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        page: &domain::PageRequest,
+    ) -> domain::RepositoryResult<domain::Page<domain::Tenant>> {
+        let query = proto_api::Query::new(entity::kind::<domain::Tenant>());
+        executor.page(query, page).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                "failed to list tenants",
+                e.into(),
+            )
+        })
+    }
+}
+
+/// NOTE: リクエストのテナントの識別子から、そのテナントの名前空間に繋ぐファクトリを返す。
+/// テナントの一覧はregistryの名前空間に保存し、各テナントのデータは自分の名前空間にだけ読み書きする
+pub struct TenantRegistry {
+    registry: Arc<ConnectionFactory>,
+    // NOTE: 解決したファクトリはttlの間だけ使い回す。
+    // 他のプロセスで削除されたテナントも、ttlが過ぎれば解決できなくなる
+    tenants: Mutex<HashMap<String, (Instant, Arc<ConnectionFactory>)>>,
+    ttl: Duration,
+}
+
+impl TenantRegistry {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+    pub fn new(registry: Arc<ConnectionFactory>) -> TenantRegistry {
+        TenantRegistry {
+            registry,
+            tenants: Mutex::new(HashMap::new()),
+            ttl: Self::DEFAULT_TTL,
+        }
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> TenantRegistry {
+        self.ttl = ttl;
+        self
+    }
+
+    pub async fn resolve(
+        &self,
+        tenant_id: &str,
+    ) -> domain::RepositoryResult<Arc<ConnectionFactory>> {
+        if let Some((resolved_at, factory)) = self.tenants.lock().unwrap().get(tenant_id) {
+            if resolved_at.elapsed() < self.ttl {
+                return Ok(factory.clone());
+            }
+        }
+        let tenant = match self.find(tenant_id).await {
+            Ok(tenant) => tenant,
+            Err(e) => {
+                self.tenants.lock().unwrap().remove(tenant_id);
+                return Err(e);
+            }
+        };
+        let factory = Arc::new(self.registry.with_namespace(tenant.namespace().raw()));
+        self.tenants
+            .lock()
+            .unwrap()
+            .insert(String::from(tenant_id), (Instant::now(), factory.clone()));
+        Ok(factory)
+    }
+
+    pub async fn find(&self, tenant_id: &str) -> domain::RepositoryResult<domain::Tenant> {
+        let mut conn = self.registry.create().await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                "failed to connect to tenant registry",
+                e.into(),
+            )
+        })?;
+        TenantRepository
+            .find(
+                &mut database::Executor::Connection(&mut conn),
+                &domain::Id::new(tenant_id),
+            )
+            .await
+    }
+
+    // NOTE: 名前空間は識別子から作らずに割り当てるので、識別子にはどんな文字を使ってもよい
+    pub async fn register(&self, tenant_id: &str, name: &str) -> anyhow::Result<domain::Tenant> {
+        let namespace = format!("tenant-{}", Uuid::new_v4().to_simple());
+        let tenant = domain::Tenant::new(
+            domain::Id::new(tenant_id),
+            domain::TenantName::try_new(name)?,
+            domain::TenantNamespace::try_new(namespace)?,
+        );
+        let mut conn = self.registry.create().await?;
+        TenantRepository
+            .create(&mut database::Executor::Connection(&mut conn), &tenant)
+            .await?;
+        Ok(tenant)
+    }
+
+    pub async fn tenants(&self) -> anyhow::Result<Vec<domain::Tenant>> {
+        let mut conn = self.registry.create().await?;
+        let query = Query::new(entity::kind::<domain::Tenant>());
+        Ok(conn.query(query).await?)
+    }
+
+    // NOTE: テナントのデータを全て削除してから、一覧から外す。
+    // 他のプロセスが解決済みのファクトリは残るが、名前空間は空になっている
+    pub async fn remove(&self, tenant_id: &str) -> anyhow::Result<usize> {
+        let tenant = self.find(tenant_id).await?;
+        let mut conn = self
+            .registry
+            .with_namespace(tenant.namespace().raw())
+            .create()
+            .await?;
+        let purged = conn.purge().await?;
+        let mut conn = self.registry.create().await?;
+        TenantRepository
+            .delete(&mut database::Executor::Connection(&mut conn), tenant.id())
+            .await?;
+        self.tenants.lock().unwrap().remove(tenant_id);
+        Ok(purged)
+    }
+
+    // NOTE: テナントの名前空間にある種類ごとのエンティティ数
+    pub async fn stats(&self, tenant: &domain::Tenant) -> anyhow::Result<Vec<(String, i64)>> {
+        let mut conn = self
+            .registry
+            .with_namespace(tenant.namespace().raw())
+            .create()
+            .await?;
+        let mut stats = vec![];
+        for kind in conn.kinds().await? {
+            let count = conn.count(Query::new(kind.clone()).keys_only()).await?;
+            stats.push((kind, count));
+        }
+        Ok(stats)
+    }
+
+    // NOTE: 全てのテナントに順に移行を適用する。途中のテナントで失敗したら、そこで止める
+    pub async fn migrate(
+        &self,
+        migrator: &Migrator,
+    ) -> anyhow::Result<Vec<(domain::Tenant, Vec<MigrationReport>)>> {
+        let mut results = vec![];
+        for tenant in self.tenants().await? {
+            let mut conn = self
+                .registry
+                .with_namespace(tenant.namespace().raw())
+                .create()
+                .await?;
+            let reports = migrator
+                .run(&mut conn)
+                .await
+                .map_err(|e| anyhow!("tenant {}: {}", tenant.id(), e))?;
+            results.push((tenant, reports));
+        }
+        Ok(results)
+    }
+}
+
+pub fn tenant_registry(project_id: &str) -> TenantRegistry {
+    TenantRegistry::new(Arc::new(ConnectionFactory::new(
+        project_id.into(),
+        TENANT_REGISTRY_NAMESPACE.into(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmww;
+    use domain::ThemeRepository as _;
+
+    fn new_theme(id: &str, kind: &str) -> domain::Theme {
+        domain::Theme::new(
+            domain::Id::new(id),
+            domain::ThemeKind::try_new(kind).unwrap(),
+            domain::Word::try_new("first").unwrap(),
+            domain::Word::try_new("second").unwrap(),
+        )
+    }
+
+    #[async_std::test]
+    async fn tenant_registry_isolates_tenant_data() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let registry = TenantRegistry::new(datastore.as_ref().clone());
+        registry.register("discord:1", "server").await.unwrap();
+        registry.register("club:2", "club").await.unwrap();
+
+        let mut conn_a = registry
+            .resolve("discord:1")
+            .await
+            .unwrap()
+            .create()
+            .await
+            .unwrap();
+        let mut conn_b = registry
+            .resolve("club:2")
+            .await
+            .unwrap()
+            .create()
+            .await
+            .unwrap();
+        assert_ne!(conn_a.namespace, conn_b.namespace);
+        assert_ne!(conn_a.namespace, datastore.as_ref().namespace());
+
+        let theme = new_theme("theme1", "animal");
+        let theme_repository = ThemeRepository::new();
+        conn_a.put(theme.clone()).await.unwrap();
+
+        let mut executor_b = database::Executor::Connection(&mut conn_b);
+        let kind = domain::ThemeKind::try_new("animal").unwrap();
+        assert_eq!(
+            theme_repository.find_by_kind(&mut executor_b, &kind).await,
+            Ok(vec![])
+        );
+        assert_eq!(
+            theme_repository.count_by_kind(&mut executor_b, &kind).await,
+            Ok(0)
+        );
+        let found: Option<proto_api::Value> =
+            conn_b.get(Key::from(theme.id().clone())).await.unwrap();
+        assert_eq!(found, None);
+        assert!(conn_b.kinds().await.unwrap().is_empty());
+
+        let mut executor_a = database::Executor::Connection(&mut conn_a);
+        assert_eq!(
+            theme_repository.find_by_kind(&mut executor_a, &kind).await,
+            Ok(vec![theme])
+        );
+
+        let stats = registry
+            .stats(&registry.find("discord:1").await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(stats, vec![(entity::kind::<domain::Theme>(), 1)]);
+
+        assert_eq!(registry.remove("discord:1").await.unwrap(), 1);
+        assert_eq!(registry.remove("club:2").await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn tenant_registry_rejects_unknown_and_duplicated_tenants() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let registry = TenantRegistry::new(datastore.as_ref().clone());
+        assert_eq!(
+            registry
+                .resolve("unknown")
+                .await
+                .map(|_| ())
+                .map_err(|e| e.kind().clone()),
+            Err(domain::RepositoryErrorKind::NotFound)
+        );
+
+        let tenant = registry.register("discord:1", "server").await.unwrap();
+        assert!(registry.register("discord:1", "other").await.is_err());
+        assert!(registry.register("club:2", " ").await.is_err());
+        assert_eq!(registry.tenants().await.unwrap(), vec![tenant]);
+
+        registry.resolve("discord:1").await.unwrap();
+        registry.remove("discord:1").await.unwrap();
+        assert!(registry.resolve("discord:1").await.is_err());
+        assert_eq!(registry.tenants().await.unwrap(), vec![]);
+    }
+
+    #[async_std::test]
+    async fn tenant_registry_expires_resolved_tenants() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let cached = TenantRegistry::new(datastore.as_ref().clone());
+        let expiring = TenantRegistry::new(datastore.as_ref().clone()).ttl(Duration::from_secs(0));
+        let admin = TenantRegistry::new(datastore.as_ref().clone());
+        admin.register("discord:1", "server").await.unwrap();
+        cached.resolve("discord:1").await.unwrap();
+        expiring.resolve("discord:1").await.unwrap();
+
+        admin.remove("discord:1").await.unwrap();
+        assert!(cached.resolve("discord:1").await.is_ok());
+        assert_eq!(
+            expiring
+                .resolve("discord:1")
+                .await
+                .map(|_| ())
+                .map_err(|e| e.kind().clone()),
+            Err(domain::RepositoryErrorKind::NotFound)
+        );
+    }
+}
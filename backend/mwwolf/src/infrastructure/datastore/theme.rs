@@ -1,92 +1,84 @@
 pub(crate) use super::*;
-use database::ConnectionFactory as _;
-use std::collections::HashMap;
 
 #[derive(new)]
-pub struct ThemeRepository {
-    connection_factory: Arc<ConnectionFactory>,
-}
+pub struct ThemeRepository;
 
-// FIXME: remove this for name_of macro!
-struct ThemeFields;
-impl ThemeFields {
-    const KIND: &'static str = "kind";
-    const FIRST: &'static str = "first";
-    const SECOND: &'static str = "second";
+#[derive(Entity)]
+#[entity(remote = "domain::Theme")]
+struct ThemeRecord {
+    #[entity(key)]
+    id: domain::Id<domain::Theme>,
+    kind: domain::ThemeKind,
+    #[entity(exclude_from_indexes)]
+    first: domain::Word,
+    #[entity(exclude_from_indexes)]
+    second: domain::Word,
 }
+
 #[async_trait]
 impl domain::ThemeRepository for ThemeRepository {
-    async fn find_by_kind(
+    type Connection = Connection;
+
+    #[tracing::instrument(name = "ThemeRepository::find_by_kind", skip(self, executor, kind), fields(kind = %kind.raw()), err)]
+    async fn find_by_kind<'a>(
         &self,
+        executor: &mut Executor<'a>,
         kind: &domain::ThemeKind,
     ) -> domain::RepositoryResult<Vec<domain::Theme>> {
         let query = proto_api::Query::new(entity::kind::<domain::Theme>()).filter(
             proto_api::Filter::Equal(
                 ThemeFields::KIND.into(),
-                proto_api::Value::Strings(kind.raw_kind().into()),
+                proto_api::Value::Strings(kind.raw().into()),
             ),
         );
-        let mut conn = self
-            .connection_factory
-            .create()
-            .await
-            .map_err(to_repository_error)?;
-        conn.query(query).await.map_err(|e| {
+        executor.query(query).await.map_err(|e| {
             domain::RepositoryError::new_with_source(
                 domain::RepositoryErrorKind::Fail,
-                format!("failed to search theme by kind: {}", kind.raw_kind()),
+                format!("failed to search theme by kind: {}", kind.raw()),
                 e.into(),
             )
         })
     }
-}
 
-impl proto_api::FromEntity for domain::Theme {
-    fn from_entity(e: proto_api::Entity) -> std::result::Result<Self, proto_api::ConvertError> {
-        let id: domain::Id<domain::Theme> = e.key().clone().into();
-        let props = e.into_properties();
-        if let proto_api::Value::Entity(vmap) = props {
-            let (vmap, kind) = entity::remove_value::<String>(vmap, ThemeFields::KIND)?;
-            let (vmap, first) = entity::remove_value::<String>(vmap, ThemeFields::FIRST)?;
-            let (_, second) = entity::remove_value::<String>(vmap, ThemeFields::SECOND)?;
-            Ok(domain::Theme::new(
-                id,
-                domain::ThemeKind::try_new(kind).unwrap(),
-                domain::Word::try_new(first).unwrap(),
-                domain::Word::try_new(second).unwrap(),
-            ))
-        } else {
-            Err(proto_api::ConvertError::UnexpectedPropertyType {
-                expected: "entity".into(),
-                got: props.type_name().into(),
+    #[tracing::instrument(name = "ThemeRepository::count_by_kind", skip(self, executor, kind), fields(kind = %kind.raw()), err)]
+    async fn count_by_kind<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        kind: &domain::ThemeKind,
+    ) -> domain::RepositoryResult<usize> {
+        let query = proto_api::Query::new(entity::kind::<domain::Theme>()).filter(
+            proto_api::Filter::Equal(
+                ThemeFields::KIND.into(),
+                proto_api::Value::Strings(kind.raw().into()),
+            ),
+        );
+        executor
+            .count(query)
+            .await
+            .map(|count| count as usize)
+            .map_err(|e| {
+                domain::RepositoryError::new_with_source(
+                    domain::RepositoryErrorKind::Fail,
+                    format!("failed to count theme by kind: {}", kind.raw()),
+                    e.into(),
+                )
             })
-        }
     }
-}
-
-impl proto_api::IntoEntity for domain::Theme {
-    fn into_entity(self) -> Result<proto_api::Entity, proto_api::ConvertError> {
-        let key: proto_api::Key = self.id().clone().into();
-        proto_api::Entity::new(key, self)
-    }
-}
 
-impl proto_api::IntoValue for domain::Theme {
-    fn into_value(self) -> proto_api::Value {
-        let mut properties = HashMap::new();
-        properties.insert(
-            name_of!(const kind in Self).into(),
-            self.kind().raw_kind().into_value(),
-        );
-        properties.insert(
-            name_of!(const first in Self).into(),
-            self.first().raw().into_value(),
-        );
-        properties.insert(
-            name_of!(const second in Self).into(),
-            self.second().raw().into_value(),
-        );
-        proto_api::Value::Entity(properties)
+    #[tracing::instrument(name = "ThemeRepository::list", skip(self, executor, page), fields(first = *page.first() as u64), err)]
+    async fn list<'a>(
+        &self,
+        executor: &mut Executor<'a>,
+        page: &domain::PageRequest,
+    ) -> domain::RepositoryResult<domain::Page<domain::Theme>> {
+        let query = proto_api::Query::new(entity::kind::<domain::Theme>());
+        executor.page(query, page).await.map_err(|e| {
+            domain::RepositoryError::new_with_source(
+                domain::RepositoryErrorKind::Fail,
+                "failed to list themes",
+                e.into(),
+            )
+        })
     }
 }
 
@@ -95,6 +87,7 @@ mod tests {
     use std::{collections::HashMap, iter::FromIterator};
 
     use super::*;
+    use database::ConnectionFactory as _;
     use domain::ThemeRepository as _;
     use test_case::test_case;
 
@@ -137,12 +130,26 @@ mod tests {
         =>
         Ok(new_theme("2", "kind_test_2", "first_word_2", "second_word_2"))
     )]
+    #[test_case(
+        new_theme_entity(3, "", "first_word", "second_word")
+        =>
+        Err(proto_api::ConvertError::InvalidValue("kind should not be blank".into()))
+    )]
     fn domain_theme_from_entity_works(
         entity: proto_api::Entity,
     ) -> Result<domain::Theme, proto_api::ConvertError> {
         domain::Theme::from_entity(entity)
     }
 
+    #[test]
+    fn domain_theme_into_entity_excludes_words_from_indexes() {
+        let entity =
+            proto_api::IntoEntity::into_entity(new_theme("1", "kind", "first", "second")).unwrap();
+        assert!(!entity.is_excluded_from_indexes(ThemeFields::KIND));
+        assert!(entity.is_excluded_from_indexes(ThemeFields::FIRST));
+        assert!(entity.is_excluded_from_indexes(ThemeFields::SECOND));
+    }
+
     #[test_case(domain::ThemeKind::try_new("hoge").unwrap(),
     vec![]=>Ok(vec![]))]
     #[test_case(domain::ThemeKind::try_new("hoge").unwrap(),
@@ -228,8 +235,11 @@ mod tests {
             .await
             .unwrap();
         theme_repository_find_by_kind_fixtures(givens, datastore.as_ref()).await;
-        let theme_repository = ThemeRepository::new(datastore.as_ref().clone());
-        theme_repository.find_by_kind(&kind).await
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let theme_repository = ThemeRepository::new();
+        theme_repository
+            .find_by_kind(&mut database::Executor::Connection(&mut conn), &kind)
+            .await
     }
 
     async fn theme_repository_find_by_kind_fixtures(
@@ -243,4 +253,96 @@ mod tests {
             .collect::<Vec<_>>();
         conn.put_all(entities).await.unwrap();
     }
+
+    #[async_std::test]
+    async fn theme_repository_list_works() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        theme_repository_find_by_kind_fixtures(
+            vec![
+                new_theme("1234", "hoge", "first_hoge", "second_hoge"),
+                new_theme("2345", "hoge", "first_hoge2", "second_hoge2"),
+                new_theme("3456", "hoge2", "first_hoge3", "second_hoge3"),
+            ],
+            datastore.as_ref(),
+        )
+        .await;
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        let theme_repository = ThemeRepository::new();
+
+        let first = theme_repository
+            .list(
+                &mut database::Executor::Connection(&mut conn),
+                &domain::PageRequest::try_new(2, None).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(first.items().len(), 2);
+        assert!(first.has_next_page());
+
+        let second = theme_repository
+            .list(
+                &mut database::Executor::Connection(&mut conn),
+                &domain::PageRequest::try_new(2, first.end_cursor().clone()).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            theme_repository
+                .count_by_kind(
+                    &mut database::Executor::Connection(&mut conn),
+                    &domain::ThemeKind::try_new("hoge").unwrap(),
+                )
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            second.items(),
+            &vec![new_theme("3456", "hoge2", "first_hoge3", "second_hoge3")]
+        );
+    }
+
+    #[async_std::test]
+    async fn theme_read_from_int_key_is_stored_back_under_the_same_key() {
+        let datastore = testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        conn.put(new_theme_entity(1234, "hoge", "first_hoge", "second_hoge"))
+            .await
+            .unwrap();
+
+        let theme_repository = ThemeRepository::new();
+        let themes = theme_repository
+            .find_by_kind(
+                &mut database::Executor::Connection(&mut conn),
+                &domain::ThemeKind::try_new("hoge").unwrap(),
+            )
+            .await
+            .unwrap();
+        let theme = themes.into_iter().next().unwrap();
+        assert_eq!(
+            Key::from(theme.id().clone()).get_id(),
+            &proto_api::KeyID::IntID(1234)
+        );
+
+        let updated = domain::Theme::new(
+            theme.id().clone(),
+            domain::ThemeKind::try_new("hoge").unwrap(),
+            domain::Word::try_new("first_fuga").unwrap(),
+            domain::Word::try_new("second_fuga").unwrap(),
+        );
+        conn.put(updated.clone()).await.unwrap();
+        assert_eq!(
+            theme_repository
+                .find_by_kind(
+                    &mut database::Executor::Connection(&mut conn),
+                    &domain::ThemeKind::try_new("hoge").unwrap(),
+                )
+                .await,
+            Ok(vec![updated])
+        );
+    }
 }
@@ -0,0 +1,113 @@
+use super::*;
+use proto_api::{ConvertError, IntoValue, Value};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+// NOTE: IntIDのキーを指すIDは整数のまま保存し、読み込んだ後も同じキーを指せるようにする
+impl<T> IntoValue for domain::Id<T> {
+    fn into_value(self) -> Value {
+        match self.integer_id() {
+            Some(id) => Value::Integer(id),
+            None => self.raw_id().as_str().into_value(),
+        }
+    }
+}
+
+impl<T> FromValue for domain::Id<T> {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::Integer(id) => Ok(domain::Id::from_integer(id)),
+            value => String::from_value(value).map(domain::Id::new),
+        }
+    }
+}
+
+// NOTE: 負の数や範囲外の整数を丸めずに、不正な値として扱う
+fn integer_from_value<T: TryFrom<i64>>(value: Value, name: &str) -> Result<T, ConvertError> {
+    let raw = i64::from_value(value)?;
+    T::try_from(raw)
+        .map_err(|_| ConvertError::InvalidValue(format!("{} is out of range: {}", name, raw)))
+}
+
+impl IntoValue for domain::PlayerCount {
+    fn into_value(self) -> Value {
+        (*self.raw_player_count() as i64).into_value()
+    }
+}
+
+impl FromValue for domain::PlayerCount {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        Ok(domain::PlayerCount::try_new(integer_from_value(
+            value,
+            "player count",
+        )?)?)
+    }
+}
+
+impl IntoValue for domain::WolfCount {
+    fn into_value(self) -> Value {
+        (*self.raw_count() as i64).into_value()
+    }
+}
+
+impl FromValue for domain::WolfCount {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        Ok(domain::WolfCount::try_new(integer_from_value(
+            value,
+            "wolf count",
+        )?)?)
+    }
+}
+
+impl IntoValue for domain::GameMinutes {
+    fn into_value(self) -> Value {
+        (self.raw_minutes() as i64).into_value()
+    }
+}
+
+impl FromValue for domain::GameMinutes {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        Ok(domain::GameMinutes::try_new(integer_from_value(
+            value,
+            "game minutes",
+        )?)?)
+    }
+}
+
+impl IntoValue for domain::GameStatus {
+    fn into_value(self) -> Value {
+        let status: &'static str = self.into();
+        status.into_value()
+    }
+}
+
+impl FromValue for domain::GameStatus {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        domain::GameStatus::from_str(&String::from_value(value)?)
+            .map_err(|e| ConvertError::InvalidValue(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Value::Integer(4) => Ok(4))]
+    #[test_case(Value::Integer(-1) => Err(ConvertError::InvalidValue("player count is out of range: -1".into())))]
+    fn player_count_from_value_works(value: Value) -> Result<usize, ConvertError> {
+        domain::PlayerCount::from_value(value).map(|count| *count.raw_player_count())
+    }
+
+    #[test_case(Value::Integer(-1) => Err(ConvertError::InvalidValue("wolf count is out of range: -1".into())))]
+    fn wolf_count_from_value_works(value: Value) -> Result<usize, ConvertError> {
+        domain::WolfCount::from_value(value).map(|count| *count.raw_count())
+    }
+
+    #[test_case(Value::Integer(5) => Ok(5))]
+    #[test_case(Value::Integer(-1) => Err(ConvertError::InvalidValue("game minutes is out of range: -1".into())))]
+    #[test_case(Value::Integer(u32::MAX as i64 + 5) => Err(ConvertError::InvalidValue("game minutes is out of range: 4294967300".into())))]
+    fn game_minutes_from_value_works(value: Value) -> Result<u32, ConvertError> {
+        domain::GameMinutes::from_value(value).map(|minutes| minutes.raw_minutes())
+    }
+}
@@ -0,0 +1,199 @@
+use once_cell::sync::OnceCell;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+/// NOTE: 計測値の送り先。サーバーごとにPrometheusなどの実装を差し込む
+pub trait Metrics: Send + Sync {
+    fn increment_counter(&self, name: &'static str, labels: Labels, value: u64);
+    fn observe_histogram(&self, name: &'static str, labels: Labels, value: f64);
+}
+
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {
+    fn increment_counter(&self, _: &'static str, _: Labels, _: u64) {}
+    fn observe_histogram(&self, _: &'static str, _: Labels, _: f64) {}
+}
+
+static METRICS: OnceCell<Arc<dyn Metrics>> = OnceCell::new();
+
+/// NOTE: 最初に設定したものだけが使われる。既に設定されていればfalseを返す
+pub fn set_metrics(metrics: Arc<dyn Metrics>) -> bool {
+    METRICS.set(metrics).is_ok()
+}
+
+// NOTE: 設定されていなければ何も記録しない
+pub fn metrics() -> &'static dyn Metrics {
+    match METRICS.get() {
+        Some(metrics) => metrics.as_ref(),
+        None => &NoopMetrics,
+    }
+}
+
+type Series = (&'static str, Vec<(&'static str, String)>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Histogram {
+    // NOTE: Prometheusの形式に合わせて、各バケットには上限以下の観測数を累積して持つ
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub struct PrometheusMetrics {
+    buckets: Vec<f64>,
+    counters: Mutex<BTreeMap<Series, u64>>,
+    histograms: Mutex<BTreeMap<Series, Histogram>>,
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_BUCKETS.to_vec())
+    }
+}
+
+impl PrometheusMetrics {
+    pub const DEFAULT_BUCKETS: [f64; 11] = [
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ];
+
+    pub fn new(buckets: Vec<f64>) -> Self {
+        Self {
+            buckets,
+            counters: Mutex::new(BTreeMap::new()),
+            histograms: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// NOTE: Prometheusのテキスト形式で出力する。/metricsのレスポンスにそのまま使える
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = self.counters.lock().unwrap();
+        let mut last_name = None;
+        for ((name, labels), value) in counters.iter() {
+            if last_name != Some(name) {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                last_name = Some(name);
+            }
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+
+        let histograms = self.histograms.lock().unwrap();
+        let mut last_name = None;
+        for ((name, labels), histogram) in histograms.iter() {
+            if last_name != Some(name) {
+                let _ = writeln!(out, "# TYPE {} histogram", name);
+                last_name = Some(name);
+            }
+            for (le, count) in self.buckets.iter().zip(histogram.buckets.iter()) {
+                let le = le.to_string();
+                let labels = format_labels(labels, Some(&le));
+                let _ = writeln!(out, "{}_bucket{} {}", name, labels, count);
+            }
+            let labels_inf = format_labels(labels, Some("+Inf"));
+            let _ = writeln!(out, "{}_bucket{} {}", name, labels_inf, histogram.count);
+            let labels = format_labels(labels, None);
+            let _ = writeln!(out, "{}_sum{} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, labels, histogram.count);
+        }
+        out
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn increment_counter(&self, name: &'static str, labels: Labels, value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry(series(name, labels)).or_insert(0) += value;
+    }
+
+    fn observe_histogram(&self, name: &'static str, labels: Labels, value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms
+            .entry(series(name, labels))
+            .or_insert_with(|| Histogram {
+                buckets: vec![0; self.buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (le, count) in self.buckets.iter().zip(histogram.buckets.iter_mut()) {
+            if value <= *le {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+fn series(name: &'static str, labels: Labels) -> Series {
+    let mut labels = labels
+        .iter()
+        .map(|(key, value)| (*key, String::from(*value)))
+        .collect::<Vec<_>>();
+    labels.sort();
+    (name, labels)
+}
+
+fn format_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_metrics_renders_text_format() {
+        let metrics = PrometheusMetrics::new(vec![0.1, 1.0]);
+        metrics.increment_counter("rpc_total", &[("method", "lookup"), ("result", "ok")], 2);
+        metrics.increment_counter("rpc_total", &[("result", "ok"), ("method", "lookup")], 1);
+        metrics.increment_counter(
+            "rpc_total",
+            &[("method", "commit"), ("result", "aborted")],
+            1,
+        );
+        metrics.observe_histogram("rpc_seconds", &[("method", "lookup")], 0.05);
+        metrics.observe_histogram("rpc_seconds", &[("method", "lookup")], 0.5);
+        metrics.observe_histogram("rpc_seconds", &[("method", "lookup")], 3.0);
+        metrics.increment_counter("escaped_total", &[("kind", "a\"b\\c")], 1);
+
+        assert_eq!(
+            metrics.render(),
+            [
+                "# TYPE escaped_total counter",
+                r#"escaped_total{kind="a\"b\\c"} 1"#,
+                "# TYPE rpc_total counter",
+                r#"rpc_total{method="commit",result="aborted"} 1"#,
+                r#"rpc_total{method="lookup",result="ok"} 3"#,
+                "# TYPE rpc_seconds histogram",
+                r#"rpc_seconds_bucket{method="lookup",le="0.1"} 1"#,
+                r#"rpc_seconds_bucket{method="lookup",le="1"} 2"#,
+                r#"rpc_seconds_bucket{method="lookup",le="+Inf"} 3"#,
+                r#"rpc_seconds_sum{method="lookup"} 3.55"#,
+                r#"rpc_seconds_count{method="lookup"} 3"#,
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1,4 +1,6 @@
 pub mod datastore;
+pub mod metrics;
+pub mod tenant;
 
 use super::*;
 use libmww::database;
@@ -0,0 +1,99 @@
+/// NOTE: リクエストごとのテナントの決め方。認証の仕組みがまだ無いので、ヘッダーは送り手が自由に書き換えられる。
+/// ヘッダーを信じるのは、認証するプロキシがヘッダーを付け直す場合と開発中だけにし、
+/// それ以外は設定した一つのテナントだけを使う
+#[derive(Clone, Debug, PartialEq)]
+pub enum TenantSelector {
+    Fixed(String),
+    TrustedHeader,
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum TenantSelectionError {
+    #[error("{} header is required", TenantSelector::HEADER)]
+    MissingHeader,
+    #[error("tenant {0} is not served here")]
+    Forbidden(String),
+    #[error(
+        "set {} to serve one tenant, or {}=true behind a proxy that authenticates the tenant",
+        TenantSelector::TENANT_ENV,
+        TenantSelector::TRUST_HEADER_ENV
+    )]
+    NotConfigured,
+}
+
+impl TenantSelector {
+    pub const HEADER: &'static str = "X-Tenant-ID";
+    pub const TENANT_ENV: &'static str = "MWWOLF_TENANT_ID";
+    pub const TRUST_HEADER_ENV: &'static str = "MWWOLF_TRUST_TENANT_HEADER";
+
+    pub fn from_env() -> Result<TenantSelector, TenantSelectionError> {
+        Self::from_settings(
+            std::env::var(Self::TENANT_ENV).ok().as_deref(),
+            std::env::var(Self::TRUST_HEADER_ENV).ok().as_deref(),
+        )
+    }
+
+    // NOTE: ヘッダーを信じる設定は明示的にtrueにしたときだけ有効にする
+    pub fn from_settings(
+        tenant_id: Option<&str>,
+        trust_header: Option<&str>,
+    ) -> Result<TenantSelector, TenantSelectionError> {
+        match (tenant_id, trust_header) {
+            (_, Some("true")) => Ok(TenantSelector::TrustedHeader),
+            (Some(tenant_id), _) if !tenant_id.is_empty() => {
+                Ok(TenantSelector::Fixed(tenant_id.into()))
+            }
+            _ => Err(TenantSelectionError::NotConfigured),
+        }
+    }
+
+    pub fn select(&self, header: Option<&str>) -> Result<String, TenantSelectionError> {
+        match (self, header) {
+            (TenantSelector::TrustedHeader, Some(tenant_id)) => Ok(tenant_id.into()),
+            (TenantSelector::TrustedHeader, None) => Err(TenantSelectionError::MissingHeader),
+            (TenantSelector::Fixed(tenant_id), Some(requested)) if requested != tenant_id => {
+                Err(TenantSelectionError::Forbidden(requested.into()))
+            }
+            (TenantSelector::Fixed(tenant_id), _) => Ok(tenant_id.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Some("tenant1"), None => Ok(TenantSelector::Fixed("tenant1".into())))]
+    #[test_case(Some("tenant1"), Some("false") => Ok(TenantSelector::Fixed("tenant1".into())))]
+    #[test_case(Some("tenant1"), Some("true") => Ok(TenantSelector::TrustedHeader))]
+    #[test_case(None, Some("true") => Ok(TenantSelector::TrustedHeader))]
+    #[test_case(None, Some("1") => Err(TenantSelectionError::NotConfigured))]
+    #[test_case(Some(""), None => Err(TenantSelectionError::NotConfigured))]
+    #[test_case(None, None => Err(TenantSelectionError::NotConfigured))]
+    fn tenant_selector_from_settings_works(
+        tenant_id: Option<&str>,
+        trust_header: Option<&str>,
+    ) -> Result<TenantSelector, TenantSelectionError> {
+        TenantSelector::from_settings(tenant_id, trust_header)
+    }
+
+    #[test_case(None => Ok("tenant1".into()))]
+    #[test_case(Some("tenant1") => Ok("tenant1".into()))]
+    #[test_case(Some("tenant2") => Err(TenantSelectionError::Forbidden("tenant2".into())))]
+    fn tenant_selector_without_trusted_header_serves_only_its_tenant(
+        header: Option<&str>,
+    ) -> Result<String, TenantSelectionError> {
+        TenantSelector::from_settings(Some("tenant1"), None)
+            .unwrap()
+            .select(header)
+    }
+
+    #[test_case(Some("tenant2") => Ok("tenant2".into()))]
+    #[test_case(None => Err(TenantSelectionError::MissingHeader))]
+    fn tenant_selector_with_trusted_header_works(
+        header: Option<&str>,
+    ) -> Result<String, TenantSelectionError> {
+        TenantSelector::TrustedHeader.select(header)
+    }
+}
@@ -1,3 +1,6 @@
+use chrono::{DateTime, Utc};
+use rand::{Rng, RngCore};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,12 +13,36 @@ pub enum DatabaseError {
     TransactionRollback(anyhow::Error),
     #[error("{0}")]
     TransactionCommit(anyhow::Error),
+    #[error("{0}")]
+    Contention(anyhow::Error),
+    #[error("{0}")]
+    Transient(anyhow::Error),
+    // NOTE: 既にあるキーへのinsertやバージョンの不一致など、再試行しても成功しないもの
+    #[error("{0}")]
+    Conflict(anyhow::Error),
+}
+
+impl DatabaseError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DatabaseError::Contention(_) | DatabaseError::Transient(_)
+        )
+    }
 }
 
 #[macro_export]
 macro_rules! run_in_transaction {
     ($ex:expr,$tx:ident,$s:block) => {{
-        let mut $tx = $ex.begin().await?;
+        $crate::run_in_transaction!(
+            $ex,
+            $crate::libmww::database::TransactionMode::ReadWrite,
+            $tx,
+            $s
+        )
+    }};
+    ($ex:expr,$mode:expr,$tx:ident,$s:block) => {{
+        let mut $tx = $ex.begin_with($mode).await?;
         let r = $s;
         if r.is_ok() {
             $tx.commit().await?;
@@ -26,6 +53,93 @@ macro_rules! run_in_transaction {
     }};
 }
 
+/// NOTE: beginが競合や一時的な障害で失敗した場合と、commitが競合で失敗した場合に、
+/// 新しいトランザクションでブロックを再実行する。commitの一時的な障害は、適用済みかもしれないので再試行しない。
+/// ブロックは何度か実行されうるので、トランザクション外に副作用を持たせないこと。
+#[macro_export]
+macro_rules! run_in_transaction_with_retry {
+    ($ex:expr,$policy:expr,$tx:ident,$s:block) => {{
+        let policy: &$crate::libmww::database::RetryPolicy = &$policy;
+        let mut attempt = 0;
+        loop {
+            let mut $tx = match $ex.begin().await {
+                Ok(tx) => tx,
+                Err(e) if e.is_retryable() && policy.has_next_attempt(attempt) => {
+                    $crate::libmww::database::sleep_backoff(policy, attempt).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => break Err(e.into()),
+            };
+            let r = $s;
+            if r.is_ok() {
+                match $tx.commit().await {
+                    Ok(()) => break r,
+                    Err(e)
+                        if matches!(e, $crate::libmww::database::DatabaseError::Contention(_))
+                            && policy.has_next_attempt(attempt) =>
+                    {
+                        $crate::libmww::database::sleep_backoff(policy, attempt).await;
+                        attempt += 1;
+                    }
+                    Err(e) => break Err(e.into()),
+                }
+            } else {
+                // NOTE: ロールバックに失敗しても、ブロックが返したエラーを返す
+                if let Err(e) = $tx.rollback().await {
+                    tracing::warn!(error = %e, "failed to rollback transaction");
+                }
+                break r;
+            }
+        }
+    }};
+}
+
+#[derive(new, Getters, Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::from_millis(50), Duration::from_secs(2))
+    }
+}
+
+impl RetryPolicy {
+    pub fn has_next_attempt(&self, attempt: u32) -> bool {
+        attempt + 1 < self.max_attempts
+    }
+
+    // full jitter: 0 ~ min(max_backoff, initial_backoff * 2^attempt)
+    pub fn backoff(&self, attempt: u32, rng: &mut impl RngCore) -> Duration {
+        let ceil_millis = self.backoff_ceil(attempt).as_millis() as u64;
+        Duration::from_millis(rng.gen_range(0..=ceil_millis))
+    }
+
+    fn backoff_ceil(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+pub async fn sleep_backoff(policy: &RetryPolicy, attempt: u32) {
+    let backoff = policy.backoff(attempt, &mut rand::thread_rng());
+    async_std::task::sleep(backoff).await;
+}
+
+/// NOTE: 読み取り専用トランザクションはロックを取らないので、書き込み中のトランザクションと競合しない。
+/// Snapshotは指定時刻時点の一貫したデータを読む
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransactionMode {
+    ReadWrite,
+    ReadOnly,
+    Snapshot(DateTime<Utc>),
+}
+
 #[async_trait]
 pub trait ConnectionFactory: std::marker::Sync + std::marker::Send {
     type Transaction: Transaction;
@@ -35,23 +149,29 @@ pub trait ConnectionFactory: std::marker::Sync + std::marker::Send {
 }
 
 #[async_trait]
-pub trait Connection {
+pub trait Connection: std::marker::Send {
     type Transaction: Transaction;
 
-    async fn begin(&mut self) -> Result<Self::Transaction, DatabaseError>;
+    async fn begin(&mut self) -> Result<Self::Transaction, DatabaseError> {
+        self.begin_with(TransactionMode::ReadWrite).await
+    }
+
+    async fn begin_with(
+        &mut self,
+        mode: TransactionMode,
+    ) -> Result<Self::Transaction, DatabaseError>;
 }
 
 #[async_trait]
-pub trait Transaction {
+pub trait Transaction: std::marker::Send {
     async fn commit(self) -> Result<(), DatabaseError>;
     async fn rollback(self) -> Result<(), DatabaseError>;
 }
 
-/// NOTE(ryutah): Datastoreの実装時に必要になる
-#[allow(dead_code)]
-pub enum Executor<'a, C: Connection, Tx: Transaction> {
+/// NOTE: リポジトリはこれを受け取り、コネクション・トランザクションのどちらでも同じように読み書きする
+pub enum Executor<'a, C: Connection> {
     Connection(&'a mut C),
-    Transaction(&'a mut Tx),
+    Transaction(&'a mut C::Transaction),
 }
 
 impl PartialEq for DatabaseError {
@@ -71,6 +191,136 @@ impl PartialEq for DatabaseError {
                     DatabaseError::TransactionCommit(_),
                     DatabaseError::TransactionCommit(_)
                 )
+                | (DatabaseError::Contention(_), DatabaseError::Contention(_))
+                | (DatabaseError::Transient(_), DatabaseError::Transient(_))
+                | (DatabaseError::Conflict(_), DatabaseError::Conflict(_))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmww::mock::mock_libmww::database::{MockConnection, MockTransaction};
+    use anyhow::anyhow;
+    use std::collections::VecDeque;
+    use test_case::test_case;
+
+    fn no_wait_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(
+            max_attempts,
+            Duration::from_millis(0),
+            Duration::from_millis(0),
         )
     }
+
+    fn mock_connection(commit_results: Vec<Result<(), DatabaseError>>) -> MockConnection {
+        let commit_results = std::sync::Mutex::new(VecDeque::from(commit_results));
+        let mut conn = MockConnection::new();
+        conn.expect_begin().returning(move || {
+            let commit_result = commit_results.lock().unwrap().pop_front().unwrap();
+            let mut tx = MockTransaction::new();
+            tx.expect_commit().return_once(move || commit_result);
+            tx.expect_rollback().returning(|| Ok(()));
+            Ok(tx)
+        });
+        conn
+    }
+
+    #[test_case(3, vec![Ok(())] => (Ok(1), 1); "commit_at_first")]
+    #[test_case(
+        3,
+        vec![Err(DatabaseError::Contention(anyhow!("conflict"))), Ok(())]
+        => (Ok(2), 2); "retry_on_contention"
+    )]
+    #[test_case(
+        3,
+        vec![Err(DatabaseError::Transient(anyhow!("unavailable")))]
+        => (Err(DatabaseError::Transient(anyhow!("unavailable"))), 1); "not_retry_on_transient_commit"
+    )]
+    #[test_case(
+        2,
+        vec![
+            Err(DatabaseError::Contention(anyhow!("conflict"))),
+            Err(DatabaseError::Contention(anyhow!("conflict"))),
+        ]
+        => (Err(DatabaseError::Contention(anyhow!("conflict"))), 2); "give_up_after_max_attempts"
+    )]
+    #[test_case(
+        3,
+        vec![Err(DatabaseError::TransactionCommit(anyhow!("invalid")))]
+        => (Err(DatabaseError::TransactionCommit(anyhow!("invalid"))), 1); "not_retry_on_other_error"
+    )]
+    #[async_std::test]
+    async fn run_in_transaction_with_retry_works(
+        max_attempts: u32,
+        commit_results: Vec<Result<(), DatabaseError>>,
+    ) -> (Result<u32, DatabaseError>, u32) {
+        let mut conn = mock_connection(commit_results);
+        let mut executed = 0;
+        let result = count_up_in_transaction(&mut conn, max_attempts, &mut executed).await;
+        (result, executed)
+    }
+
+    async fn count_up_in_transaction(
+        conn: &mut MockConnection,
+        max_attempts: u32,
+        executed: &mut u32,
+    ) -> Result<u32, DatabaseError> {
+        run_in_transaction_with_retry!(conn, no_wait_policy(max_attempts), _tx, {
+            *executed += 1;
+            Ok(*executed)
+        })
+    }
+
+    #[test_case(Ok(()))]
+    #[test_case(Err(DatabaseError::TransactionRollback(anyhow!("rollback"))))]
+    #[async_std::test]
+    async fn run_in_transaction_with_retry_keeps_block_error(
+        rollback_result: Result<(), DatabaseError>,
+    ) {
+        let mut conn = MockConnection::new();
+        conn.expect_begin().return_once(move || {
+            let mut tx = MockTransaction::new();
+            tx.expect_rollback().return_once(move || rollback_result);
+            Ok(tx)
+        });
+        let result: Result<(), DatabaseError> =
+            run_in_transaction_with_retry!(conn, no_wait_policy(3), _tx, {
+                Err(DatabaseError::Conflict(anyhow!("conflict")))
+            });
+        assert_eq!(result, Err(DatabaseError::Conflict(anyhow!("conflict"))));
+    }
+
+    #[test_case(TransactionMode::ReadWrite)]
+    #[test_case(TransactionMode::ReadOnly)]
+    #[test_case(TransactionMode::Snapshot(Utc::now()))]
+    #[async_std::test]
+    async fn run_in_transaction_begins_with_mode(mode: TransactionMode) {
+        let mut conn = MockConnection::new();
+        let expected = mode.clone();
+        conn.expect_begin_with()
+            .withf(move |mode| mode == &expected)
+            .times(1)
+            .returning(|_| {
+                let mut tx = MockTransaction::new();
+                tx.expect_commit().return_once(|| Ok(()));
+                Ok(tx)
+            });
+        let result: Result<(), DatabaseError> =
+            async { run_in_transaction!(conn, mode, _tx, { Ok(()) }) }.await;
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test_case(0 => Duration::from_millis(100))]
+    #[test_case(1 => Duration::from_millis(200))]
+    #[test_case(3 => Duration::from_millis(800))]
+    #[test_case(4 => Duration::from_millis(1000))]
+    #[test_case(40 => Duration::from_millis(1000))]
+    fn retry_policy_backoff_works(attempt: u32) -> Duration {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_millis(1000));
+        let ceil = policy.backoff_ceil(attempt);
+        assert!(policy.backoff(attempt, &mut rand::thread_rng()) <= ceil);
+        ceil
+    }
 }
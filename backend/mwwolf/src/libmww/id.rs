@@ -1,11 +1,93 @@
+use chrono::Utc;
+use rand::RngCore;
+use uuid::Uuid;
+
 pub type Id<T> = inner::Id<T, String>;
 
+#[async_trait]
+pub trait IdGenerator<T>: std::marker::Send + std::marker::Sync {
+    async fn generate(&self) -> anyhow::Result<Id<T>>;
+}
+
+#[derive(new)]
+pub struct UuidGenerator;
+
+#[async_trait]
+impl<T> IdGenerator<T> for UuidGenerator {
+    async fn generate(&self) -> anyhow::Result<Id<T>> {
+        Ok(Id::new(Uuid::new_v4().to_string()))
+    }
+}
+
+#[derive(new)]
+pub struct UlidGenerator;
+
+#[async_trait]
+impl<T> IdGenerator<T> for UlidGenerator {
+    async fn generate(&self) -> anyhow::Result<Id<T>> {
+        let mut randomness = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut randomness[6..]);
+        Ok(Id::new(encode_ulid(
+            Utc::now().timestamp_millis() as u64,
+            u128::from_be_bytes(randomness),
+        )))
+    }
+}
+
+// 48bitのミリ秒タイムスタンプ + 80bitの乱数をCrockford's Base32で26文字にする
+fn encode_ulid(timestamp_millis: u64, randomness: u128) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let value =
+        ((timestamp_millis as u128 & 0xFFFF_FFFF_FFFF) << 80) | (randomness & ((1 << 80) - 1));
+    (0..26)
+        .rev()
+        .map(|i| ALPHABET[((value >> (i * 5)) & 0x1F) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    struct IdTag;
+
+    #[test_case(1469918176385, 0 => "01ARYZ6S410000000000000000")]
+    #[test_case(0, 1 => "00000000000000000000000001")]
+    #[test_case(0xFFFF_FFFF_FFFF, (1 << 80) - 1 => "7ZZZZZZZZZZZZZZZZZZZZZZZZZ")]
+    fn encode_ulid_works(timestamp_millis: u64, randomness: u128) -> String {
+        encode_ulid(timestamp_millis, randomness)
+    }
+
+    #[async_std::test]
+    async fn ulid_generator_generates_sortable_ids() {
+        let generator = UlidGenerator::new();
+        let first: Id<IdTag> = generator.generate().await.unwrap();
+        async_std::task::sleep(std::time::Duration::from_millis(2)).await;
+        let second: Id<IdTag> = generator.generate().await.unwrap();
+        assert_eq!(first.raw_id().len(), 26);
+        assert!(first < second);
+    }
+
+    #[async_std::test]
+    async fn uuid_generator_generates_unique_ids() {
+        let generator = UuidGenerator::new();
+        let first: Id<IdTag> = generator.generate().await.unwrap();
+        let second: Id<IdTag> = generator.generate().await.unwrap();
+        assert!(Uuid::parse_str(first.raw_id()).is_ok());
+        assert_ne!(first, second);
+    }
+}
+
 mod inner {
     use std::{fmt, hash::Hash};
     use std::{hash::Hasher, marker::PhantomData};
 
+    // NOTE: 整数で振られたIDは整数も持ち回り、保存するときに整数のIDに戻せるようにする。
+    // 比較とハッシュには文字列のIDだけを使う
     pub struct Id<T: ?Sized, R: PartialEq + Clone + fmt::Display + fmt::Debug + Hash + PartialOrd + Ord>(
         R,
+        Option<i64>,
         PhantomData<T>,
     );
 
@@ -13,11 +95,20 @@ mod inner {
         Id<T, R>
     {
         pub fn new(raw_id: impl Into<R>) -> Self {
-            Self(raw_id.into(), PhantomData)
+            Self(raw_id.into(), None, PhantomData)
+        }
+        pub fn from_integer(id: i64) -> Self
+        where
+            R: From<String>,
+        {
+            Self(R::from(id.to_string()), Some(id), PhantomData)
         }
         pub fn raw_id(&self) -> &R {
             &self.0
         }
+        pub fn integer_id(&self) -> Option<i64> {
+            self.1
+        }
     }
 
     impl<T: ?Sized, R: PartialEq + Clone + fmt::Display + fmt::Debug + Hash + PartialOrd + Ord> Eq
@@ -44,7 +135,7 @@ mod inner {
         Clone for Id<T, R>
     {
         fn clone(&self) -> Self {
-            Self(self.0.clone(), PhantomData)
+            Self(self.0.clone(), self.1, PhantomData)
         }
     }
 
@@ -135,5 +226,14 @@ mod inner {
             let id = Id::<IdTag, R>::new(v1.clone());
             id.raw_id() == &v1
         }
+
+        #[test]
+        fn works_from_integer() {
+            let id = Id::<IdTag, String>::from_integer(1234);
+            assert_eq!(id.raw_id(), "1234");
+            assert_eq!(id.integer_id(), Some(1234));
+            assert_eq!(Id::<IdTag, String>::new("1234").integer_id(), None);
+            assert_eq!(id, Id::new("1234"));
+        }
     }
 }
@@ -1,6 +1,9 @@
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 
+// NOTE: ドメインで扱う日時のタイムゾーン。保存した日時もこれで読み出す
+pub const TIMEZONE: Tz = chrono_tz::Japan;
+
 pub trait DateTimeGen {
     fn now(&self) -> DateTime<Tz>;
 }
@@ -9,6 +12,6 @@ pub struct DateTimeGenImpl;
 
 impl DateTimeGen for DateTimeGenImpl {
     fn now(&self) -> DateTime<Tz> {
-        Utc::now().with_timezone(&chrono_tz::Japan)
+        Utc::now().with_timezone(&TIMEZONE)
     }
 }
@@ -0,0 +1,1015 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryInto;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use tonic::{Request, Response, Status};
+
+use crate::infrastructure::datastore::proto_api::api;
+use api::datastore_server::{Datastore, DatastoreServer};
+use api::key::path_element::IdType;
+use api::query_result_batch::MoreResultsType;
+use api::read_options::ConsistencyType;
+use api::value::ValueType;
+
+// NOTE: クライアントが複数バッチを読み進める経路も通るよう、1バッチの件数を絞っている
+const BATCH_SIZE: usize = 50;
+const FIRST_ALLOCATED_ID: i64 = 5_000_000_000;
+// NOTE: 本物のDatastoreと同じく、1回のリクエストに含められる数を制限する
+const MAX_MUTATIONS: usize = 500;
+const MAX_LOOKUP_KEYS: usize = 1000;
+
+/// NOTE: テストプロセス内で一度だけ起動し、全てのテストで共有する
+pub fn start() -> SocketAddr {
+    static ADDR: OnceCell<SocketAddr> = OnceCell::new();
+    *ADDR.get_or_init(|| {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        async_std::task::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(DatastoreServer::new(FakeDatastore::default()))
+                .serve(addr)
+                .await
+                .unwrap()
+        });
+        for _ in 0..500 {
+            if TcpStream::connect(addr).is_ok() {
+                return addr;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("fake datastore did not start on {}", addr)
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum IdOrName {
+    Id(i64),
+    Name(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct StoredKey {
+    namespace: String,
+    path: Vec<(String, IdOrName)>,
+}
+
+impl StoredKey {
+    fn from_key(key: &api::Key) -> Option<StoredKey> {
+        let path = key
+            .path
+            .iter()
+            .map(|element| {
+                let id = match element.id_type.as_ref()? {
+                    IdType::Id(id) => IdOrName::Id(*id),
+                    IdType::Name(name) => IdOrName::Name(name.clone()),
+                };
+                Some((element.kind.clone(), id))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(StoredKey {
+            namespace: namespace_of(key),
+            path,
+        })
+    }
+
+    fn kind(&self) -> &str {
+        self.path.last().map_or("", |(kind, _)| kind.as_str())
+    }
+
+    fn has_ancestor(&self, ancestor: &StoredKey) -> bool {
+        self.namespace == ancestor.namespace && self.path.starts_with(&ancestor.path)
+    }
+}
+
+#[derive(Clone)]
+struct Stored {
+    entity: api::Entity,
+    version: i64,
+}
+
+struct TransactionState {
+    read_only: bool,
+    reads: HashMap<StoredKey, i64>,
+}
+
+#[derive(Default)]
+struct State {
+    entities: BTreeMap<StoredKey, Stored>,
+    versions: HashMap<StoredKey, i64>,
+    transactions: HashMap<Vec<u8>, TransactionState>,
+    last_transaction: u64,
+    last_version: i64,
+    last_id: i64,
+}
+
+impl State {
+    fn version_of(&self, key: &StoredKey) -> i64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    // NOTE: トランザクション内で読んだキーとそのバージョンを覚えておき、コミット時に競合を検出する
+    fn record_read(&mut self, transaction: Option<&[u8]>, key: &StoredKey) -> Result<(), Status> {
+        if let Some(transaction) = transaction {
+            let version = self.version_of(key);
+            self.transactions
+                .get_mut(transaction)
+                .ok_or_else(|| Status::invalid_argument("transaction is not found"))?
+                .reads
+                .entry(key.clone())
+                .or_insert(version);
+        }
+        Ok(())
+    }
+
+    fn allocate_id(&mut self, key: &mut api::Key) -> Result<(), Status> {
+        let element = key
+            .path
+            .last_mut()
+            .ok_or_else(|| Status::invalid_argument("key path is empty"))?;
+        if element.id_type.is_some() {
+            return Err(Status::invalid_argument("key is already complete"));
+        }
+        self.last_id += 1;
+        element.id_type = Some(IdType::Id(FIRST_ALLOCATED_ID + self.last_id));
+        Ok(())
+    }
+
+    fn matching(
+        &self,
+        namespace: &str,
+        query: &api::Query,
+    ) -> Result<Vec<(StoredKey, Stored)>, Status> {
+        let kind = query.kind.first().map(|kind| kind.name.as_str());
+        let candidates = match kind {
+            Some("__kind__") => self.kinds(namespace),
+            Some("__namespace__") => self.namespaces(),
+            _ => self
+                .entities
+                .iter()
+                .filter(|(key, _)| {
+                    key.namespace == namespace && kind.map_or(true, |kind| key.kind() == kind)
+                })
+                .map(|(key, stored)| (key.clone(), stored.clone()))
+                .collect(),
+        };
+        let mut results = vec![];
+        for (key, stored) in candidates {
+            let matched = match &query.filter {
+                Some(filter) => matches_filter(&stored.entity, &key, filter)?,
+                None => true,
+            };
+            if matched {
+                results.push((key, stored));
+            }
+        }
+
+        let orders = query
+            .order
+            .iter()
+            .map(|order| {
+                let name = order
+                    .property
+                    .as_ref()
+                    .map(|property| property.name.clone())
+                    .unwrap_or_default();
+                let descending =
+                    order.direction == api::property_order::Direction::Descending as i32;
+                (name, descending)
+            })
+            .collect::<Vec<_>>();
+        results.retain(|(_, stored)| {
+            orders.iter().all(|(name, _)| {
+                name == "__key__" || !indexed_values(&stored.entity, name).is_empty()
+            })
+        });
+        results.sort_by(|(a_key, a), (b_key, b)| {
+            orders
+                .iter()
+                .map(|(name, descending)| {
+                    let ordering = if name == "__key__" {
+                        a_key.cmp(b_key)
+                    } else {
+                        sort_values(
+                            indexed_values(&a.entity, name)[0],
+                            indexed_values(&b.entity, name)[0],
+                        )
+                    };
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or_else(|| a_key.cmp(b_key))
+        });
+        Ok(results)
+    }
+
+    // NOTE: メタデータのクエリは、保存されているエンティティから都度組み立てる
+    fn kinds(&self, namespace: &str) -> Vec<(StoredKey, Stored)> {
+        let kinds = self
+            .entities
+            .keys()
+            .filter(|key| key.namespace == namespace)
+            .map(|key| key.kind().to_string())
+            .collect::<BTreeSet<_>>();
+        kinds
+            .into_iter()
+            .map(|kind| metadata(namespace, "__kind__", IdOrName::Name(kind)))
+            .collect()
+    }
+
+    fn namespaces(&self) -> Vec<(StoredKey, Stored)> {
+        let namespaces = self
+            .entities
+            .keys()
+            .map(|key| key.namespace.clone())
+            .collect::<BTreeSet<_>>();
+        namespaces
+            .into_iter()
+            .map(|namespace| {
+                let id = if namespace.is_empty() {
+                    IdOrName::Id(1)
+                } else {
+                    IdOrName::Name(namespace)
+                };
+                metadata("", "__namespace__", id)
+            })
+            .collect()
+    }
+
+    fn run_query(
+        &mut self,
+        namespace: &str,
+        query: &api::Query,
+        transaction: Option<&[u8]>,
+    ) -> Result<api::QueryResultBatch, Status> {
+        let results = self.matching(namespace, query)?;
+        let start = decode_cursor(&query.start_cursor)?.min(results.len());
+        let skipped = (query.offset.max(0) as usize).min(results.len() - start);
+        let limit = query.limit.map(|limit| limit.max(0) as usize);
+        let taken = results[start + skipped..]
+            .iter()
+            .take(limit.unwrap_or(usize::MAX).min(BATCH_SIZE))
+            .collect::<Vec<_>>();
+        let end = start + skipped + taken.len();
+        let more_results = if limit == Some(taken.len()) {
+            MoreResultsType::MoreResultsAfterLimit
+        } else if end < results.len() {
+            MoreResultsType::NotFinished
+        } else {
+            MoreResultsType::NoMoreResults
+        };
+
+        let projection = query
+            .projection
+            .iter()
+            .filter_map(|projection| projection.property.as_ref())
+            .map(|property| property.name.as_str())
+            .collect::<Vec<_>>();
+        let keys_only = projection == ["__key__"];
+        let mut entity_results = vec![];
+        for (index, (key, stored)) in taken.into_iter().enumerate() {
+            self.record_read(transaction, key)?;
+            let mut entity = stored.entity.clone();
+            if keys_only {
+                entity.properties.clear();
+            } else if !projection.is_empty() {
+                entity
+                    .properties
+                    .retain(|name, _| projection.contains(&name.as_str()));
+            }
+            entity_results.push(api::EntityResult {
+                entity: Some(entity),
+                version: stored.version,
+                cursor: encode_cursor(start + skipped + index + 1),
+            });
+        }
+
+        let entity_result_type = if keys_only {
+            api::entity_result::ResultType::KeyOnly
+        } else if projection.is_empty() {
+            api::entity_result::ResultType::Full
+        } else {
+            api::entity_result::ResultType::Projection
+        };
+        Ok(api::QueryResultBatch {
+            skipped_results: skipped as i32,
+            skipped_cursor: encode_cursor(start + skipped),
+            entity_result_type: entity_result_type as i32,
+            entity_results,
+            end_cursor: encode_cursor(end),
+            more_results: more_results as i32,
+            ..Default::default()
+        })
+    }
+
+    fn apply(&mut self, mutation: api::Mutation) -> Result<api::MutationResult, Status> {
+        use api::mutation::{ConflictDetectionStrategy, Operation};
+
+        let base_version = mutation
+            .conflict_detection_strategy
+            .map(|ConflictDetectionStrategy::BaseVersion(version)| version);
+        let operation = mutation
+            .operation
+            .ok_or_else(|| Status::invalid_argument("mutation has no operation"))?;
+        let (mut key, entity) = match &operation {
+            Operation::Insert(entity) | Operation::Update(entity) | Operation::Upsert(entity) => (
+                entity
+                    .key
+                    .clone()
+                    .ok_or_else(|| Status::invalid_argument("entity has no key"))?,
+                Some(entity.clone()),
+            ),
+            Operation::Delete(key) => (key.clone(), None),
+        };
+        let allocated = StoredKey::from_key(&key).is_none();
+        if allocated {
+            match operation {
+                Operation::Insert(_) | Operation::Upsert(_) => self.allocate_id(&mut key)?,
+                _ => return Err(Status::invalid_argument("key is incomplete")),
+            }
+        }
+        let stored_key = StoredKey::from_key(&key)
+            .ok_or_else(|| Status::invalid_argument("key is incomplete"))?;
+        let exists = self.entities.contains_key(&stored_key);
+        match operation {
+            Operation::Insert(_) if exists => {
+                return Err(Status::already_exists("entity already exists"))
+            }
+            Operation::Update(_) if !exists => {
+                return Err(Status::not_found("no entity to update"))
+            }
+            _ => {}
+        }
+        if let Some(base_version) = base_version {
+            if self.version_of(&stored_key) != base_version {
+                return Ok(api::MutationResult {
+                    version: self.version_of(&stored_key),
+                    conflict_detected: true,
+                    ..Default::default()
+                });
+            }
+        }
+
+        self.last_version += 1;
+        let version = self.last_version;
+        self.versions.insert(stored_key.clone(), version);
+        match entity {
+            Some(mut entity) => {
+                entity.key = Some(key.clone());
+                self.entities.insert(stored_key, Stored { entity, version });
+            }
+            None => {
+                self.entities.remove(&stored_key);
+            }
+        }
+        Ok(api::MutationResult {
+            key: if allocated { Some(key) } else { None },
+            version,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct FakeDatastore {
+    state: Mutex<State>,
+}
+
+#[tonic::async_trait]
+impl Datastore for FakeDatastore {
+    async fn lookup(
+        &self,
+        request: Request<api::LookupRequest>,
+    ) -> Result<Response<api::LookupResponse>, Status> {
+        let request = request.into_inner();
+        if request.keys.len() > MAX_LOOKUP_KEYS {
+            return Err(Status::invalid_argument("too many keys in a lookup"));
+        }
+        let transaction = transaction_of(request.read_options.as_ref());
+        let mut state = self.state.lock().unwrap();
+        let mut found = vec![];
+        let mut missing = vec![];
+        for key in request.keys {
+            let stored_key = StoredKey::from_key(&key)
+                .ok_or_else(|| Status::invalid_argument("key is incomplete"))?;
+            state.record_read(transaction.as_deref(), &stored_key)?;
+            match state.entities.get(&stored_key) {
+                Some(stored) => found.push(api::EntityResult {
+                    entity: Some(stored.entity.clone()),
+                    version: stored.version,
+                    cursor: vec![],
+                }),
+                None => missing.push(api::EntityResult {
+                    entity: Some(api::Entity {
+                        key: Some(key),
+                        properties: HashMap::new(),
+                    }),
+                    version: state.version_of(&stored_key),
+                    cursor: vec![],
+                }),
+            }
+        }
+        Ok(Response::new(api::LookupResponse {
+            found,
+            missing,
+            ..Default::default()
+        }))
+    }
+
+    async fn run_query(
+        &self,
+        request: Request<api::RunQueryRequest>,
+    ) -> Result<Response<api::RunQueryResponse>, Status> {
+        let request = request.into_inner();
+        let query = match request.query_type {
+            Some(api::run_query_request::QueryType::Query(query)) => query,
+            _ => {
+                return Err(Status::unimplemented(
+                    "only structured queries are supported",
+                ))
+            }
+        };
+        let namespace = request
+            .partition_id
+            .map(|partition_id| partition_id.namespace_id)
+            .unwrap_or_default();
+        let transaction = transaction_of(request.read_options.as_ref());
+        let batch =
+            self.state
+                .lock()
+                .unwrap()
+                .run_query(&namespace, &query, transaction.as_deref())?;
+        Ok(Response::new(api::RunQueryResponse {
+            batch: Some(batch),
+            query: None,
+        }))
+    }
+
+    async fn run_aggregation_query(
+        &self,
+        request: Request<api::RunAggregationQueryRequest>,
+    ) -> Result<Response<api::RunAggregationQueryResponse>, Status> {
+        use api::aggregation_query::aggregation::Operator;
+
+        let request = request.into_inner();
+        let aggregation_query = match request.query_type {
+            Some(api::run_aggregation_query_request::QueryType::AggregationQuery(query)) => query,
+            _ => {
+                return Err(Status::unimplemented(
+                    "only structured queries are supported",
+                ))
+            }
+        };
+        let query = match aggregation_query.query_type {
+            Some(api::aggregation_query::QueryType::NestedQuery(query)) => query,
+            None => return Err(Status::invalid_argument("nested query is missing")),
+        };
+        let namespace = request
+            .partition_id
+            .map(|partition_id| partition_id.namespace_id)
+            .unwrap_or_default();
+        let matched = self
+            .state
+            .lock()
+            .unwrap()
+            .matching(&namespace, &query)?
+            .len();
+        let count = matched.saturating_sub(query.offset.max(0) as usize).min(
+            query
+                .limit
+                .map_or(usize::MAX, |limit| limit.max(0) as usize),
+        );
+
+        let mut aggregate_properties = HashMap::new();
+        for aggregation in aggregation_query.aggregations {
+            let count = match aggregation.operator {
+                Some(Operator::Count(count_operator)) => count_operator
+                    .up_to
+                    .map_or(count as i64, |up_to| (count as i64).min(up_to)),
+                None => return Err(Status::invalid_argument("aggregation has no operator")),
+            };
+            aggregate_properties.insert(
+                aggregation.alias,
+                api::Value {
+                    value_type: Some(ValueType::IntegerValue(count)),
+                    ..Default::default()
+                },
+            );
+        }
+        Ok(Response::new(api::RunAggregationQueryResponse {
+            batch: Some(api::AggregationResultBatch {
+                aggregation_results: vec![api::AggregationResult {
+                    aggregate_properties,
+                }],
+                more_results: MoreResultsType::NoMoreResults as i32,
+            }),
+            query: None,
+        }))
+    }
+
+    async fn begin_transaction(
+        &self,
+        request: Request<api::BeginTransactionRequest>,
+    ) -> Result<Response<api::BeginTransactionResponse>, Status> {
+        let read_only = matches!(
+            request.into_inner().transaction_options,
+            Some(api::TransactionOptions {
+                mode: Some(api::transaction_options::Mode::ReadOnly(_)),
+            })
+        );
+        let mut state = self.state.lock().unwrap();
+        state.last_transaction += 1;
+        let transaction = state.last_transaction.to_be_bytes().to_vec();
+        state.transactions.insert(
+            transaction.clone(),
+            TransactionState {
+                read_only,
+                reads: HashMap::new(),
+            },
+        );
+        Ok(Response::new(api::BeginTransactionResponse { transaction }))
+    }
+
+    async fn commit(
+        &self,
+        request: Request<api::CommitRequest>,
+    ) -> Result<Response<api::CommitResponse>, Status> {
+        let request = request.into_inner();
+        if request.mutations.len() > MAX_MUTATIONS {
+            return Err(Status::invalid_argument("too many mutations in a commit"));
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(api::commit_request::TransactionSelector::Transaction(transaction)) =
+            request.transaction_selector
+        {
+            let transaction = state
+                .transactions
+                .remove(&transaction)
+                .ok_or_else(|| Status::invalid_argument("transaction is not found"))?;
+            if transaction.read_only && !request.mutations.is_empty() {
+                return Err(Status::invalid_argument(
+                    "cannot modify entities in a read-only transaction",
+                ));
+            }
+            if transaction
+                .reads
+                .iter()
+                .any(|(key, version)| state.version_of(key) != *version)
+            {
+                return Err(Status::aborted(
+                    "too much contention on these datastore entities",
+                ));
+            }
+        }
+
+        // NOTE: コミットは全て適用されるか何も適用されないかのどちらかにする
+        let entities = state.entities.clone();
+        let versions = state.versions.clone();
+        let mut mutation_results = vec![];
+        for mutation in request.mutations {
+            match state.apply(mutation) {
+                Ok(result) => mutation_results.push(result),
+                Err(status) => {
+                    state.entities = entities;
+                    state.versions = versions;
+                    return Err(status);
+                }
+            }
+        }
+        Ok(Response::new(api::CommitResponse {
+            mutation_results,
+            ..Default::default()
+        }))
+    }
+
+    async fn rollback(
+        &self,
+        request: Request<api::RollbackRequest>,
+    ) -> Result<Response<api::RollbackResponse>, Status> {
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .remove(&request.into_inner().transaction)
+            .ok_or_else(|| Status::invalid_argument("transaction is not found"))?;
+        Ok(Response::new(api::RollbackResponse {}))
+    }
+
+    async fn allocate_ids(
+        &self,
+        request: Request<api::AllocateIdsRequest>,
+    ) -> Result<Response<api::AllocateIdsResponse>, Status> {
+        let mut state = self.state.lock().unwrap();
+        let keys = request
+            .into_inner()
+            .keys
+            .into_iter()
+            .map(|mut key| state.allocate_id(&mut key).map(|_| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Response::new(api::AllocateIdsResponse { keys }))
+    }
+
+    async fn reserve_ids(
+        &self,
+        _request: Request<api::ReserveIdsRequest>,
+    ) -> Result<Response<api::ReserveIdsResponse>, Status> {
+        Ok(Response::new(api::ReserveIdsResponse {}))
+    }
+}
+
+fn metadata(namespace: &str, kind: &str, id: IdOrName) -> (StoredKey, Stored) {
+    let key = StoredKey {
+        namespace: namespace.into(),
+        path: vec![(kind.into(), id)],
+    };
+    let entity = api::Entity {
+        key: Some(api::Key {
+            partition_id: Some(api::PartitionId {
+                namespace_id: namespace.into(),
+                ..Default::default()
+            }),
+            path: key
+                .path
+                .iter()
+                .map(|(kind, id)| api::key::PathElement {
+                    kind: kind.clone(),
+                    id_type: Some(match id {
+                        IdOrName::Id(id) => IdType::Id(*id),
+                        IdOrName::Name(name) => IdType::Name(name.clone()),
+                    }),
+                })
+                .collect(),
+        }),
+        properties: HashMap::new(),
+    };
+    (key, Stored { entity, version: 0 })
+}
+
+fn namespace_of(key: &api::Key) -> String {
+    key.partition_id
+        .as_ref()
+        .map(|partition_id| partition_id.namespace_id.clone())
+        .unwrap_or_default()
+}
+
+fn transaction_of(read_options: Option<&api::ReadOptions>) -> Option<Vec<u8>> {
+    match read_options.and_then(|options| options.consistency_type.as_ref()) {
+        Some(ConsistencyType::Transaction(transaction)) => Some(transaction.clone()),
+        _ => None,
+    }
+}
+
+fn encode_cursor(position: usize) -> Vec<u8> {
+    (position as u64).to_be_bytes().to_vec()
+}
+
+fn decode_cursor(cursor: &[u8]) -> Result<usize, Status> {
+    if cursor.is_empty() {
+        return Ok(0);
+    }
+    let bytes: [u8; 8] = cursor
+        .try_into()
+        .map_err(|_| Status::invalid_argument("invalid cursor"))?;
+    Ok(u64::from_be_bytes(bytes) as usize)
+}
+
+fn matches_filter(
+    entity: &api::Entity,
+    key: &StoredKey,
+    filter: &api::Filter,
+) -> Result<bool, Status> {
+    use api::composite_filter::Operator as CompositeOperator;
+    use api::filter::FilterType;
+    use api::property_filter::Operator;
+
+    let filter = match &filter.filter_type {
+        Some(FilterType::CompositeFilter(composite)) => {
+            let mut results = composite
+                .filters
+                .iter()
+                .map(|filter| matches_filter(entity, key, filter));
+            return if composite.op == CompositeOperator::Or as i32 {
+                results.try_fold(false, |matched, result| Ok(matched || result?))
+            } else {
+                results.try_fold(true, |matched, result| Ok(matched && result?))
+            };
+        }
+        Some(FilterType::PropertyFilter(filter)) => filter,
+        None => return Ok(true),
+    };
+    let name = filter
+        .property
+        .as_ref()
+        .map(|property| property.name.as_str())
+        .unwrap_or_default();
+    let operand = filter
+        .value
+        .as_ref()
+        .and_then(|value| value.value_type.as_ref())
+        .ok_or_else(|| Status::invalid_argument("filter value is missing"))?;
+
+    if filter.op == Operator::HasAncestor as i32 {
+        return match operand {
+            ValueType::KeyValue(ancestor) => {
+                Ok(StoredKey::from_key(ancestor)
+                    .map_or(false, |ancestor| key.has_ancestor(&ancestor)))
+            }
+            _ => Err(Status::invalid_argument("ancestor filter requires a key")),
+        };
+    }
+
+    let key_value;
+    let candidates: Vec<&ValueType> = if name == "__key__" {
+        key_value = ValueType::KeyValue(entity.key.clone().unwrap_or_default());
+        vec![&key_value]
+    } else {
+        indexed_values(entity, name)
+    };
+    if candidates.is_empty() {
+        return Ok(false);
+    }
+    let operands: Vec<&ValueType> = match operand {
+        ValueType::ArrayValue(array)
+            if filter.op == Operator::In as i32 || filter.op == Operator::NotIn as i32 =>
+        {
+            array
+                .values
+                .iter()
+                .filter_map(|value| value.value_type.as_ref())
+                .collect()
+        }
+        operand => vec![operand],
+    };
+    let compare = |expected: fn(Ordering) -> bool| {
+        candidates.iter().any(|candidate| {
+            operands
+                .iter()
+                .any(|operand| compare_values(candidate, operand).map_or(false, expected))
+        })
+    };
+    let op = filter.op;
+    Ok(
+        if op == Operator::Equal as i32 || op == Operator::In as i32 {
+            compare(|o| o == Ordering::Equal)
+        } else if op == Operator::NotEqual as i32 {
+            compare(|o| o != Ordering::Equal)
+        } else if op == Operator::NotIn as i32 {
+            !compare(|o| o == Ordering::Equal)
+        } else if op == Operator::LessThan as i32 {
+            compare(|o| o == Ordering::Less)
+        } else if op == Operator::LessThanOrEqual as i32 {
+            compare(|o| o != Ordering::Greater)
+        } else if op == Operator::GreaterThan as i32 {
+            compare(|o| o == Ordering::Greater)
+        } else if op == Operator::GreaterThanOrEqual as i32 {
+            compare(|o| o != Ordering::Less)
+        } else {
+            return Err(Status::invalid_argument(format!(
+                "unsupported filter operator: {}",
+                op
+            )));
+        },
+    )
+}
+
+fn compare_values(a: &ValueType, b: &ValueType) -> Option<Ordering> {
+    match (a, b) {
+        (ValueType::NullValue(_), ValueType::NullValue(_)) => Some(Ordering::Equal),
+        (ValueType::BooleanValue(a), ValueType::BooleanValue(b)) => a.partial_cmp(b),
+        (ValueType::IntegerValue(a), ValueType::IntegerValue(b)) => a.partial_cmp(b),
+        (ValueType::IntegerValue(a), ValueType::DoubleValue(b)) => (*a as f64).partial_cmp(b),
+        (ValueType::DoubleValue(a), ValueType::IntegerValue(b)) => a.partial_cmp(&(*b as f64)),
+        (ValueType::DoubleValue(a), ValueType::DoubleValue(b)) => a.partial_cmp(b),
+        (ValueType::TimestampValue(a), ValueType::TimestampValue(b)) => {
+            (a.seconds, a.nanos).partial_cmp(&(b.seconds, b.nanos))
+        }
+        (ValueType::StringValue(a), ValueType::StringValue(b)) => a.partial_cmp(b),
+        (ValueType::BlobValue(a), ValueType::BlobValue(b)) => a.partial_cmp(b),
+        (ValueType::KeyValue(a), ValueType::KeyValue(b)) => {
+            StoredKey::from_key(a).partial_cmp(&StoredKey::from_key(b))
+        }
+        (ValueType::GeoPointValue(a), ValueType::GeoPointValue(b)) => {
+            (a.latitude, a.longitude).partial_cmp(&(b.latitude, b.longitude))
+        }
+        _ => None,
+    }
+}
+
+// NOTE: インデックスされていない値はフィルタにも並び替えにも使えない
+fn indexed_values<'a>(entity: &'a api::Entity, name: &str) -> Vec<&'a ValueType> {
+    match entity.properties.get(name) {
+        Some(api::Value {
+            value_type: Some(ValueType::ArrayValue(array)),
+            ..
+        }) => array
+            .values
+            .iter()
+            .filter(|value| !value.exclude_from_indexes)
+            .filter_map(|value| value.value_type.as_ref())
+            .collect(),
+        Some(value) if !value.exclude_from_indexes => value.value_type.iter().collect(),
+        _ => vec![],
+    }
+}
+
+// NOTE: 型が違う値は、Datastoreと同じく型ごとの順序で並べる
+fn sort_values(a: &ValueType, b: &ValueType) -> Ordering {
+    fn rank(value: &ValueType) -> u8 {
+        match value {
+            ValueType::NullValue(_) => 0,
+            ValueType::IntegerValue(_) | ValueType::DoubleValue(_) => 1,
+            ValueType::TimestampValue(_) => 2,
+            ValueType::BooleanValue(_) => 3,
+            ValueType::StringValue(_) | ValueType::BlobValue(_) => 4,
+            ValueType::KeyValue(_) => 5,
+            ValueType::GeoPointValue(_) => 6,
+            ValueType::ArrayValue(_) => 7,
+            ValueType::EntityValue(_) => 8,
+        }
+    }
+    compare_values(a, b).unwrap_or_else(|| rank(a).cmp(&rank(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::datastore_client::DatastoreClient;
+
+    async fn client() -> DatastoreClient<tonic::transport::Channel> {
+        DatastoreClient::connect(format!("http://{}", start()))
+            .await
+            .unwrap()
+    }
+
+    fn key(namespace: &str, name: &str) -> api::Key {
+        api::Key {
+            partition_id: Some(api::PartitionId {
+                namespace_id: namespace.into(),
+                ..Default::default()
+            }),
+            path: vec![api::key::PathElement {
+                kind: "Fake".into(),
+                id_type: Some(IdType::Name(name.into())),
+            }],
+        }
+    }
+
+    fn upsert(key: api::Key, score: i64) -> api::Mutation {
+        let mut properties = HashMap::new();
+        properties.insert(
+            String::from("score"),
+            api::Value {
+                value_type: Some(ValueType::IntegerValue(score)),
+                ..Default::default()
+            },
+        );
+        api::Mutation {
+            operation: Some(api::mutation::Operation::Upsert(api::Entity {
+                key: Some(key),
+                properties,
+            })),
+            conflict_detection_strategy: None,
+        }
+    }
+
+    async fn commit(
+        client: &mut DatastoreClient<tonic::transport::Channel>,
+        transaction: Option<Vec<u8>>,
+        mutations: Vec<api::Mutation>,
+    ) -> Result<api::CommitResponse, Status> {
+        client
+            .commit(api::CommitRequest {
+                mode: if transaction.is_some() {
+                    api::commit_request::Mode::Transactional as i32
+                } else {
+                    api::commit_request::Mode::NonTransactional as i32
+                },
+                transaction_selector: transaction
+                    .map(api::commit_request::TransactionSelector::Transaction),
+                mutations,
+                ..Default::default()
+            })
+            .await
+            .map(Response::into_inner)
+    }
+
+    #[async_std::test]
+    async fn commit_aborts_when_read_entity_is_changed() {
+        let mut client = client().await;
+        let namespace = "commit_aborts";
+        commit(&mut client, None, vec![upsert(key(namespace, "a"), 1)])
+            .await
+            .unwrap();
+
+        let transaction = client
+            .begin_transaction(api::BeginTransactionRequest::default())
+            .await
+            .unwrap()
+            .into_inner()
+            .transaction;
+        client
+            .lookup(api::LookupRequest {
+                keys: vec![key(namespace, "a")],
+                read_options: Some(api::ReadOptions {
+                    consistency_type: Some(ConsistencyType::Transaction(transaction.clone())),
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        commit(&mut client, None, vec![upsert(key(namespace, "a"), 2)])
+            .await
+            .unwrap();
+
+        let status = commit(
+            &mut client,
+            Some(transaction),
+            vec![upsert(key(namespace, "a"), 3)],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+    }
+
+    #[async_std::test]
+    async fn run_query_filters_orders_and_pages() {
+        use api::property_filter::Operator;
+
+        let mut client = client().await;
+        let namespace = "run_query";
+        commit(
+            &mut client,
+            None,
+            (1..=5)
+                .map(|i| upsert(key(namespace, &format!("e{}", i)), 6 - i))
+                .collect(),
+        )
+        .await
+        .unwrap();
+
+        let mut query = api::Query {
+            kind: vec![api::KindExpression {
+                name: "Fake".into(),
+            }],
+            filter: Some(api::Filter {
+                filter_type: Some(api::filter::FilterType::PropertyFilter(
+                    api::PropertyFilter {
+                        property: Some(api::PropertyReference {
+                            name: "score".into(),
+                        }),
+                        op: Operator::GreaterThan as i32,
+                        value: Some(api::Value {
+                            value_type: Some(ValueType::IntegerValue(1)),
+                            ..Default::default()
+                        }),
+                    },
+                )),
+            }),
+            order: vec![api::PropertyOrder {
+                property: Some(api::PropertyReference {
+                    name: "score".into(),
+                }),
+                direction: api::property_order::Direction::Ascending as i32,
+            }],
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut scores = vec![];
+        loop {
+            let batch = client
+                .run_query(api::RunQueryRequest {
+                    partition_id: Some(api::PartitionId {
+                        namespace_id: namespace.into(),
+                        ..Default::default()
+                    }),
+                    query_type: Some(api::run_query_request::QueryType::Query(query.clone())),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_inner()
+                .batch
+                .unwrap();
+            if batch.entity_results.is_empty() {
+                break;
+            }
+            scores.extend(batch.entity_results.into_iter().map(|result| {
+                match result.entity.unwrap().properties["score"].value_type {
+                    Some(ValueType::IntegerValue(score)) => score,
+                    _ => unreachable!(),
+                }
+            }));
+            query.start_cursor = batch.end_cursor;
+        }
+        assert_eq!(scores, vec![2, 3, 4, 5]);
+    }
+}
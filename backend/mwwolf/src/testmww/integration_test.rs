@@ -1,3 +1,4 @@
+use super::fake_datastore;
 use crate::infrastructure::datastore;
 use async_std::sync::Arc;
 use std::env;
@@ -5,6 +6,31 @@ use uuid::Uuid;
 
 pub struct ConnectionFactoryGurad {
     cf: Arc<datastore::ConnectionFactory>,
+    project_id: String,
+    namespace: String,
+}
+
+// NOTE: 非同期のテストからも同期のテストからも落とせるよう、後片付けは別スレッドで完了まで待つ
+impl Drop for ConnectionFactoryGurad {
+    fn drop(&mut self) {
+        let project_id = self.project_id.clone();
+        let namespace = self.namespace.clone();
+        let purged = std::thread::spawn(move || {
+            async_std::task::block_on(datastore::purge_namespace(&project_id, &namespace))
+        })
+        .join();
+        let message = match purged {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => format!("failed to purge test namespace {}: {}", self.namespace, e),
+            Err(_) => format!("failed to purge test namespace {}", self.namespace),
+        };
+        // NOTE: テストが既に失敗しているときに重ねてpanicするとabortするので、ログに残すだけにする
+        if std::thread::panicking() {
+            tracing::error!("{}", message);
+        } else {
+            panic!("{}", message);
+        }
+    }
 }
 
 impl AsRef<Arc<datastore::ConnectionFactory>> for ConnectionFactoryGurad {
@@ -22,12 +48,70 @@ impl AsMut<Arc<datastore::ConnectionFactory>> for ConnectionFactoryGurad {
 /// NOTE(ryutah): 実装進んだら使うようになる(はず)
 #[allow(dead_code)]
 pub async fn init_test_database() -> Result<ConnectionFactoryGurad, anyhow::Error> {
+    // NOTE: エミュレータが指定されていなければ、プロセス内のフェイクサーバに繋ぐ
+    if env::var("DATASTORE_EMULATOR_HOST").is_err() {
+        env::set_var(
+            "DATASTORE_EMULATOR_HOST",
+            fake_datastore::start().to_string(),
+        );
+    }
     let db_uuid = Uuid::new_v4();
     let namespace = db_uuid.to_string().replace("-", "");
+    let project_id = env::var("GOOGLE_CLOUD_PROJECT").unwrap_or_else(|_| "mwwolf-test".into());
     let cf = Arc::new(datastore::ConnectionFactory::new(
-        env::var("GOOGLE_CLOUD_PROJECT").unwrap(),
-        namespace,
+        project_id.clone(),
+        namespace.clone(),
     ));
-    let cf = ConnectionFactoryGurad { cf };
+    let cf = ConnectionFactoryGurad {
+        cf,
+        project_id,
+        namespace,
+    };
     Ok(cf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::datastore::proto_api::{Entity, Key, Value};
+    use std::collections::HashMap;
+
+    async fn put_and_drop() -> (String, String) {
+        use crate::libmww::database::ConnectionFactory as _;
+
+        let guard = init_test_database().await.unwrap();
+        let mut conn = guard.as_ref().create().await.unwrap();
+        let mut properties = HashMap::new();
+        properties.insert(String::from("name"), Value::Strings("x".into()));
+        conn.put_all(vec![
+            Entity::new(Key::new("Purged").id("a"), properties.clone()).unwrap(),
+            Entity::new(Key::new("AlsoPurged").id("b"), properties).unwrap(),
+        ])
+        .await
+        .unwrap();
+        (guard.project_id.clone(), guard.namespace.clone())
+    }
+
+    async fn remaining(project_id: &str, namespace: &str) -> usize {
+        let namespaces = datastore::list_namespaces(project_id).await.unwrap();
+        assert!(!namespaces.iter().any(|n| n == namespace));
+        datastore::purge_namespace(project_id, namespace)
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn guard_purges_namespace_from_async_test() {
+        let (project_id, namespace) = put_and_drop().await;
+        assert_eq!(remaining(&project_id, &namespace).await, 0);
+    }
+
+    #[test]
+    fn guard_purges_namespace_from_sync_test() {
+        let (project_id, namespace) = async_std::task::block_on(put_and_drop());
+        assert_eq!(
+            async_std::task::block_on(remaining(&project_id, &namespace)),
+            0
+        );
+    }
+}
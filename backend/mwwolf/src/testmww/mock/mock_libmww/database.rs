@@ -0,0 +1,37 @@
+use crate::libmww::database::{self, DatabaseError, TransactionMode};
+
+use crate::*;
+
+mock! {
+    pub Transaction {}
+
+    #[async_trait]
+    impl database::Transaction for Transaction {
+        async fn commit(self) -> Result<(), DatabaseError>;
+        async fn rollback(self) -> Result<(), DatabaseError>;
+    }
+}
+
+mock! {
+    pub Connection {}
+
+    #[async_trait]
+    impl database::Connection for Connection {
+        type Transaction = MockTransaction;
+
+        async fn begin(&mut self) -> Result<MockTransaction, DatabaseError>;
+        async fn begin_with(&mut self, mode: TransactionMode) -> Result<MockTransaction, DatabaseError>;
+    }
+}
+
+mock! {
+    pub ConnectionFactory {}
+
+    #[async_trait]
+    impl database::ConnectionFactory for ConnectionFactory {
+        type Transaction = MockTransaction;
+        type Connection = MockConnection;
+
+        async fn create(&self) -> Result<MockConnection, DatabaseError>;
+    }
+}
@@ -0,0 +1,12 @@
+use crate::libmww::id;
+
+use crate::*;
+
+mock! {
+    pub IdGenerator<T: 'static + std::marker::Send + std::marker::Sync> {}
+
+    #[async_trait]
+    impl<T: 'static + std::marker::Send + std::marker::Sync> id::IdGenerator<T> for IdGenerator<T> {
+        async fn generate(&self) -> anyhow::Result<id::Id<T>>;
+    }
+}
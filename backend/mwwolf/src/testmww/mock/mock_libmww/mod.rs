@@ -1 +1,3 @@
+pub mod database;
+pub mod id;
 pub mod time;
@@ -1,3 +1,5 @@
 // pub mod integration_project_test;
+pub mod fake_datastore;
 pub mod integration_test;
 pub mod mock;
+pub mod stand_in_server;
@@ -0,0 +1,68 @@
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+
+/// NOTE: 外部のHTTPエンドポイントの代わりに、決まったレスポンスを返してリクエストを記録する
+pub struct StandInServer {
+    host: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandInServer {
+    pub async fn start(status: u16, body: String) -> StandInServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        task::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                recorded.lock().await.push(request);
+                let response = format!(
+                    "HTTP/1.1 {} Stand-In\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        StandInServer { host, requests }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.host, path)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        task::block_on(self.requests.lock()).clone()
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> String {
+    let mut data = vec![];
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        data.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&data).into_owned();
+        if let Some(header_end) = text.find("\r\n\r\n") {
+            let content_length = text[..header_end]
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, value)| value.trim().parse().unwrap());
+            if data.len() >= header_end + 4 + content_length || n == 0 {
+                return text;
+            }
+        } else if n == 0 {
+            return text;
+        }
+    }
+}
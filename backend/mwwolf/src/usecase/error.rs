@@ -19,6 +19,17 @@ impl From<domain::DomainError> for UsecaseError {
     }
 }
 
+impl From<domain::RepositoryError> for UsecaseError {
+    fn from(err: domain::RepositoryError) -> Self {
+        match err.kind() {
+            domain::RepositoryErrorKind::NotFound => {
+                UsecaseError::Notfound(err.message().clone(), err.into())
+            }
+            _ => UsecaseError::Fail(err.message().clone(), err.into()),
+        }
+    }
+}
+
 impl From<libmww::database::DatabaseError> for UsecaseError {
     fn from(err: libmww::database::DatabaseError) -> Self {
         UsecaseError::DatabaseError(err)
@@ -0,0 +1,332 @@
+use super::*;
+use async_std::sync::Arc;
+use domain::{GameRepository as _, RoomRepository as _};
+use libmww::database::{self, Connection as _, ConnectionFactory as _, Transaction as _};
+
+pub trait GameUsecaseTypeParameters: domain::RoomServiceTypeParameters {
+    type ConnectionFactory: database::ConnectionFactory<Connection = Self::Connection>;
+    type RoomRepository: domain::RoomRepository<Connection = Self::Connection>;
+    type GameRepository: domain::GameRepository<Connection = Self::Connection>;
+}
+
+#[derive(new)]
+pub struct GameUsecase<GUT: GameUsecaseTypeParameters> {
+    connection_factory: Arc<GUT::ConnectionFactory>,
+    room_repository: GUT::RoomRepository,
+    game_repository: GUT::GameRepository,
+    room_service: domain::RoomService<GUT>,
+    retry_policy: database::RetryPolicy,
+}
+
+impl<GUT: GameUsecaseTypeParameters> GameUsecase<GUT> {
+    pub async fn start_game(
+        &self,
+        room_id: &domain::Id<domain::Room>,
+    ) -> Result<domain::Game, UsecaseError> {
+        let mut conn = self.connection_factory.create().await?;
+        crate::run_in_transaction_with_retry!(conn, self.retry_policy, tx, {
+            self.start_game_in(
+                &mut database::Executor::Transaction(&mut tx),
+                &mut database::Executor::Connection(&mut conn),
+                room_id,
+            )
+            .await
+        })
+    }
+
+    // NOTE: テーマはトランザクション内でクエリできないので、readerでトランザクション外から読む
+    async fn start_game_in(
+        &self,
+        executor: &mut database::Executor<'_, GUT::Connection>,
+        reader: &mut database::Executor<'_, GUT::Connection>,
+        room_id: &domain::Id<domain::Room>,
+    ) -> Result<domain::Game, UsecaseError> {
+        let room = self.room_repository.find(executor, room_id).await?;
+        let game = self.room_service.start_game(reader, &room).await?;
+        self.game_repository.store(executor, &game).await?;
+        Ok(game)
+    }
+
+    // NOTE: 同時に投票されても票が失われないよう、読み込みから保存までを一つのトランザクションで行う
+    pub async fn vote(
+        &self,
+        game_id: &domain::Id<domain::Game>,
+        vote: domain::Vote,
+    ) -> Result<domain::VoteResult, UsecaseError> {
+        let mut conn = self.connection_factory.create().await?;
+        crate::run_in_transaction_with_retry!(conn, self.retry_policy, tx, {
+            self.vote_in(
+                &mut database::Executor::Transaction(&mut tx),
+                game_id,
+                vote.clone(),
+            )
+            .await
+        })
+    }
+
+    async fn vote_in(
+        &self,
+        executor: &mut database::Executor<'_, GUT::Connection>,
+        game_id: &domain::Id<domain::Game>,
+        vote: domain::Vote,
+    ) -> Result<domain::VoteResult, UsecaseError> {
+        let mut game = self.game_repository.find(executor, game_id).await?;
+        let result = game.vote(vote)?;
+        self.game_repository.store(executor, &game).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::datastore;
+    use crate::testmww::mock::mock_libmww::database::{
+        MockConnection, MockConnectionFactory, MockTransaction,
+    };
+    use crate::testmww::mock::mock_libmww::time::MockDateTimeGen;
+    use chrono::TimeZone;
+    use database::DatabaseError;
+    use rand::rngs::mock::StepRng;
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use test_case::test_case;
+
+    struct MockGameUsecaseTypeParameters;
+
+    impl domain::RoomServiceTypeParameters for MockGameUsecaseTypeParameters {
+        type Connection = MockConnection;
+        type GameFactory = domain::MockGameFactory;
+        type ThemeRepository = domain::MockThemeRepository;
+        type DateTimeGen = MockDateTimeGen;
+        type RngCore = StepRng;
+    }
+
+    impl GameUsecaseTypeParameters for MockGameUsecaseTypeParameters {
+        type ConnectionFactory = MockConnectionFactory;
+        type RoomRepository = domain::MockRoomRepository;
+        type GameRepository = domain::MockGameRepository;
+    }
+
+    fn room() -> domain::Room {
+        domain::Room::try_new(
+            domain::Id::new("room1"),
+            domain::PlayerCount::try_new(3).unwrap(),
+            domain::WolfCount::try_new(1).unwrap(),
+            domain::Id::new("player1"),
+            vec![
+                domain::Id::new("player1"),
+                domain::Id::new("player2"),
+                domain::Id::new("player3"),
+            ],
+            domain::GameMinutes::try_new(5).unwrap(),
+            domain::ThemeKind::try_new("kind1").unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn mock_connection_factory(
+        commit_results: Vec<Result<(), DatabaseError>>,
+    ) -> MockConnectionFactory {
+        let commit_results = std::sync::Mutex::new(commit_results.into_iter());
+        let mut conn = MockConnection::new();
+        conn.expect_begin().returning(move || {
+            let commit_result = commit_results.lock().unwrap().next().unwrap();
+            let mut tx = MockTransaction::new();
+            tx.expect_commit().return_once(move || commit_result);
+            tx.expect_rollback().returning(|| Ok(()));
+            Ok(tx)
+        });
+        let mut connection_factory = MockConnectionFactory::new();
+        connection_factory
+            .expect_create()
+            .return_once(move || Ok(conn));
+        connection_factory
+    }
+
+    fn in_transaction(executor: &database::Executor<'_, MockConnection>) -> bool {
+        matches!(executor, database::Executor::Transaction(_))
+    }
+
+    #[test_case(
+        Ok(room()), vec![Ok(())]
+        => (Ok(domain::Id::new("game1")), 1); "start_game"
+    )]
+    #[test_case(
+        Ok(room()),
+        vec![Err(DatabaseError::Contention(anyhow::anyhow!("conflict"))), Ok(())]
+        => (Ok(domain::Id::new("game1")), 2); "retry_start_game_on_contention"
+    )]
+    #[test_case(
+        Err(domain::RepositoryError::new(domain::RepositoryErrorKind::NotFound, "room is not found: room1")),
+        vec![Ok(())]
+        => (Err(UsecaseError::Notfound("room is not found: room1".into(), anyhow::anyhow!(""))), 0);
+        "room_not_found"
+    )]
+    #[async_std::test]
+    async fn game_usecase_start_game_works(
+        find_room_result: domain::RepositoryResult<domain::Room>,
+        commit_results: Vec<Result<(), DatabaseError>>,
+    ) -> (Result<domain::Id<domain::Game>, UsecaseError>, usize) {
+        let mut room_repository = domain::MockRoomRepository::new();
+        room_repository
+            .expect_find()
+            .withf(|executor, id| in_transaction(executor) && id == &domain::Id::new("room1"))
+            .returning(move |_, _| match &find_room_result {
+                Ok(room) => Ok(room.clone()),
+                Err(e) => Err(domain::RepositoryError::new(e.kind().clone(), e.message())),
+            });
+
+        let stored = Arc::new(AtomicUsize::new(0));
+        let mut game_repository = domain::MockGameRepository::new();
+        game_repository
+            .expect_store()
+            .withf(|executor, _| in_transaction(executor))
+            .returning({
+                let stored = stored.clone();
+                move |_, _| {
+                    stored.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+
+        let mut theme_repository = domain::MockThemeRepository::new();
+        theme_repository
+            .expect_find_by_kind()
+            .withf(|executor, _| !in_transaction(executor))
+            .returning(|_, kind| {
+                Ok(vec![domain::Theme::new(
+                    domain::Id::new("theme1"),
+                    kind.clone(),
+                    domain::Word::try_new("foo").unwrap(),
+                    domain::Word::try_new("bar").unwrap(),
+                )])
+            });
+        let mut game_factory = domain::MockGameFactory::new();
+        game_factory.expect_create().returning(
+            |room_id, theme_id, ended_at, wolf_group, citizen_group| {
+                domain::Game::try_new(
+                    domain::Id::new("game1"),
+                    room_id,
+                    theme_id,
+                    ended_at,
+                    wolf_group,
+                    citizen_group,
+                    domain::VoteBox::new(vec![]),
+                    domain::GameStatus::Talking,
+                )
+            },
+        );
+        let mut date_time_gen = MockDateTimeGen::new();
+        date_time_gen
+            .expect_now()
+            .returning(|| chrono_tz::Japan.ymd(2021, 8, 11).and_hms(12, 30, 15));
+
+        let usecase = GameUsecase::<MockGameUsecaseTypeParameters>::new(
+            Arc::new(mock_connection_factory(commit_results)),
+            room_repository,
+            game_repository,
+            domain::RoomService::new(
+                game_factory,
+                theme_repository,
+                date_time_gen,
+                RefCell::new(StepRng::new(0, 1)),
+            ),
+            database::RetryPolicy::new(3, Duration::from_millis(0), Duration::from_millis(0)),
+        );
+        let result = usecase
+            .start_game(&domain::Id::new("room1"))
+            .await
+            .map(|game| game.id().clone());
+        (result, stored.load(Ordering::SeqCst))
+    }
+
+    struct DatastoreGameUsecaseTypeParameters;
+
+    impl domain::RoomServiceTypeParameters for DatastoreGameUsecaseTypeParameters {
+        type Connection = datastore::Connection;
+        type GameFactory = domain::MockGameFactory;
+        type ThemeRepository = datastore::ThemeRepository;
+        type DateTimeGen = MockDateTimeGen;
+        type RngCore = StepRng;
+    }
+
+    impl GameUsecaseTypeParameters for DatastoreGameUsecaseTypeParameters {
+        type ConnectionFactory = datastore::ConnectionFactory;
+        type RoomRepository = datastore::RoomRepository;
+        type GameRepository = datastore::GameRepository;
+    }
+
+    #[async_std::test]
+    async fn game_usecase_vote_keeps_concurrent_votes() {
+        let datastore = crate::testmww::integration_test::init_test_database()
+            .await
+            .unwrap();
+        let players = ["player1", "player2", "player3"];
+        let game = domain::Game::try_new(
+            domain::Id::new("game1"),
+            domain::Id::new("room1"),
+            domain::Id::new("theme1"),
+            chrono_tz::Japan.ymd(2021, 8, 11).and_hms(12, 30, 15),
+            domain::WolfGroup::new(
+                vec![domain::Id::new(players[0])],
+                domain::Word::try_new("foo").unwrap(),
+            ),
+            domain::CitizenGroup::new(
+                vec![domain::Id::new(players[1]), domain::Id::new(players[2])],
+                domain::Word::try_new("bar").unwrap(),
+            ),
+            domain::VoteBox::new(vec![]),
+            domain::GameStatus::Voting,
+        )
+        .unwrap();
+        let mut conn = datastore.as_ref().create().await.unwrap();
+        datastore::GameRepository::new()
+            .store(&mut database::Executor::Connection(&mut conn), &game)
+            .await
+            .unwrap();
+
+        let usecase = GameUsecase::<DatastoreGameUsecaseTypeParameters>::new(
+            datastore.as_ref().clone(),
+            datastore::RoomRepository::new(),
+            datastore::GameRepository::new(),
+            domain::RoomService::new(
+                domain::MockGameFactory::new(),
+                datastore::ThemeRepository::new(),
+                MockDateTimeGen::new(),
+                RefCell::new(StepRng::new(0, 1)),
+            ),
+            database::RetryPolicy::new(10, Duration::from_millis(0), Duration::from_millis(0)),
+        );
+        // NOTE: 全員が同じゲームを読んでから書き込むので、後からコミットした投票は競合してやり直す
+        let results = futures_util::future::join_all(players.iter().map(|voter| {
+            usecase.vote(
+                game.id(),
+                domain::Vote::new(domain::Id::new("player1"), domain::Id::new(*voter)),
+            )
+        }))
+        .await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(
+            results
+                .iter()
+                .filter(|result| matches!(result, Ok(result) if *result.is_end()))
+                .count(),
+            1
+        );
+
+        let voted = datastore::GameRepository::new()
+            .find(&mut database::Executor::Connection(&mut conn), game.id())
+            .await
+            .unwrap();
+        let mut voters = voted
+            .vote_box()
+            .votes()
+            .iter()
+            .map(|vote| vote.voter().to_string())
+            .collect::<Vec<_>>();
+        voters.sort();
+        assert_eq!(voters, players);
+    }
+}
@@ -1,6 +1,8 @@
 mod error;
+mod game;
 
 pub use error::*;
+pub use game::*;
 
 use crate::domain;
 use crate::libmww;
@@ -2,3 +2,5 @@
 
 export GOOGLE_CLOUD_PROJECT="mwwolf-local"
 export DATASTORE_EMULATOR_HOST=localhost:61000
+# NOTE: 開発中だけX-Tenant-IDヘッダーでテナントを選べるようにする
+export MWWOLF_TRUST_TENANT_HEADER=true